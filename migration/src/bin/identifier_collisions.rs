@@ -0,0 +1,47 @@
+//! One-off report of users whose usernames or emails collide once compared case-insensitively
+//! (NFKC + case folding), and of look-alike usernames. Resolve the `username` and `email`
+//! collisions before applying the canonical identifier migration.
+//!
+//! ```sh
+//! DATABASE_URL=postgres://... cargo run --bin identifier_collisions
+//! ```
+use migration::identifiers::find_collisions;
+use sea_orm_migration::sea_orm::Database;
+use std::env;
+use std::process::ExitCode;
+
+#[async_std::main]
+async fn main() -> ExitCode {
+    let url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let db = Database::connect(&url)
+        .await
+        .expect("Failed to connect to the database");
+    let collisions = find_collisions(&db)
+        .await
+        .expect("Failed to read auth_users");
+
+    for collision in &collisions {
+        let users: Vec<String> = collision
+            .users
+            .iter()
+            .map(|(id, value)| format!("{} {:?}", id, value))
+            .collect();
+        println!(
+            "{} {:?}: {}",
+            collision.kind,
+            collision.key,
+            users.join(", ")
+        );
+    }
+    let blocking = collisions.iter().filter(|c| c.blocks_migration()).count();
+    println!(
+        "{} collisions, {} to resolve before migrating",
+        collisions.len(),
+        blocking
+    );
+    if blocking > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
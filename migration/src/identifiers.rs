@@ -0,0 +1,75 @@
+//! Canonical usernames and emails, with the same rules as `src/utils/identifier.rs`, and the
+//! collision report used before they are made unique.
+use caseless::default_case_fold_str;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, Statement};
+use std::collections::BTreeMap;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::skeleton;
+
+pub fn canonical(value: &str) -> String {
+    let nfkc: String = value.trim().nfkc().collect();
+    default_case_fold_str(&nfkc).nfkc().collect()
+}
+
+pub fn username_skeleton(username: &str) -> String {
+    skeleton(&canonical(username)).collect()
+}
+
+/// Users sharing one canonical value.
+pub struct Collision {
+    /// `username`, `email` or `username look-alike`.
+    pub kind: &'static str,
+    pub key: String,
+    /// `(id, stored value)` of each user involved.
+    pub users: Vec<(i32, String)>,
+}
+
+impl Collision {
+    /// Look-alike usernames are reported but only refused for new accounts.
+    pub fn blocks_migration(&self) -> bool {
+        self.kind != "username look-alike"
+    }
+}
+
+/// Every group of users whose usernames or emails are equal once normalized, and whose
+/// usernames share a confusable skeleton.
+pub async fn find_collisions<C: ConnectionTrait>(db: &C) -> Result<Vec<Collision>, DbErr> {
+    let rows = db
+        .query_all(Statement::from_string(
+            db.get_database_backend(),
+            "SELECT id, username, email FROM auth_users ORDER BY id",
+        ))
+        .await?;
+
+    let mut groups: BTreeMap<(&'static str, String), Vec<(i32, String)>> = BTreeMap::new();
+    for row in rows {
+        let id: i32 = row.try_get("", "id")?;
+        let username: String = row.try_get("", "username")?;
+        let email: String = row.try_get("", "email")?;
+        groups
+            .entry(("username", canonical(&username)))
+            .or_default()
+            .push((id, username.clone()));
+        groups
+            .entry(("username look-alike", username_skeleton(&username)))
+            .or_default()
+            .push((id, username));
+        groups
+            .entry(("email", canonical(&email)))
+            .or_default()
+            .push((id, email));
+    }
+
+    Ok(groups
+        .into_iter()
+        .filter(|((kind, _), users)| match *kind {
+            // Look-alikes that are already the same username are reported once, as such
+            "username look-alike" => users
+                .iter()
+                .any(|(_, username)| canonical(username) != canonical(&users[0].1)),
+            _ => users.len() > 1,
+        })
+        .map(|((kind, key), users)| Collision { kind, key, users })
+        .collect())
+}
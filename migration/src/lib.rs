@@ -1,12 +1,42 @@
 pub use sea_orm_migration::prelude::*;
 
+pub mod identifiers;
+
 mod m20220101_000001_create_table;
+mod m20261019_000002_create_service_accounts;
+mod m20261019_000003_create_api_keys;
+mod m20261019_000004_create_oauth_clients;
+mod m20261019_000005_add_email_verified_at;
+mod m20261019_000006_add_roles_and_client_credentials;
+mod m20261019_000007_create_identities;
+mod m20261019_000008_add_phone_verification;
+mod m20261019_000009_normalize_phone_numbers;
+mod m20261019_000010_add_canonical_identifiers;
+mod m20261019_000011_add_pending_contact_changes;
+mod m20261019_000012_add_account_status;
+mod m20261019_000013_create_audit_events;
+mod m20261019_000014_add_scheduled_purge;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20220101_000001_create_table::Migration)]
+        vec![
+            Box::new(m20220101_000001_create_table::Migration),
+            Box::new(m20261019_000002_create_service_accounts::Migration),
+            Box::new(m20261019_000003_create_api_keys::Migration),
+            Box::new(m20261019_000004_create_oauth_clients::Migration),
+            Box::new(m20261019_000005_add_email_verified_at::Migration),
+            Box::new(m20261019_000006_add_roles_and_client_credentials::Migration),
+            Box::new(m20261019_000007_create_identities::Migration),
+            Box::new(m20261019_000008_add_phone_verification::Migration),
+            Box::new(m20261019_000009_normalize_phone_numbers::Migration),
+            Box::new(m20261019_000010_add_canonical_identifiers::Migration),
+            Box::new(m20261019_000011_add_pending_contact_changes::Migration),
+            Box::new(m20261019_000012_add_account_status::Migration),
+            Box::new(m20261019_000013_create_audit_events::Migration),
+            Box::new(m20261019_000014_add_scheduled_purge::Migration),
+        ]
     }
 }
@@ -0,0 +1,84 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthUsers::Table)
+                    .add_column(
+                        ColumnDef::new(AuthUsers::Role)
+                            .string_len(16)
+                            .not_null()
+                            .default("user"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ServiceAccounts::Table)
+                    .add_column(
+                        ColumnDef::new(ServiceAccounts::ClientId)
+                            .string()
+                            .null()
+                            .unique_key(),
+                    )
+                    .add_column(
+                        ColumnDef::new(ServiceAccounts::ClientSecretHash)
+                            .string()
+                            .null(),
+                    )
+                    .add_column(
+                        ColumnDef::new(ServiceAccounts::AllowedScopes)
+                            .string()
+                            .not_null()
+                            .default(""),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ServiceAccounts::Table)
+                    .drop_column(ServiceAccounts::ClientId)
+                    .drop_column(ServiceAccounts::ClientSecretHash)
+                    .drop_column(ServiceAccounts::AllowedScopes)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthUsers::Table)
+                    .drop_column(AuthUsers::Role)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AuthUsers {
+    Table,
+    Role,
+}
+
+#[derive(DeriveIden)]
+enum ServiceAccounts {
+    Table,
+    ClientId,
+    ClientSecretHash,
+    AllowedScopes,
+}
@@ -0,0 +1,108 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Identities::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Identities::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Identities::UserId).integer().not_null())
+                    .col(ColumnDef::new(Identities::Provider).string().not_null())
+                    .col(ColumnDef::new(Identities::Subject).string().not_null())
+                    .col(ColumnDef::new(Identities::Email).string().null())
+                    .col(
+                        ColumnDef::new(Identities::LastLoginAt)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(Identities::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(Identities::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_identities_user_id")
+                            .from(Identities::Table, Identities::UserId)
+                            .to(AuthUsers::Table, AuthUsers::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // An external account can only ever sign in as one local user.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_identities_provider_subject")
+                    .table(Identities::Table)
+                    .col(Identities::Provider)
+                    .col(Identities::Subject)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Accounts provisioned from an identity provider have no password.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthUsers::Table)
+                    .modify_column(ColumnDef::new(AuthUsers::Password).string().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Identities::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthUsers::Table)
+                    .modify_column(ColumnDef::new(AuthUsers::Password).string().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Identities {
+    Table,
+    Id,
+    UserId,
+    Provider,
+    Subject,
+    Email,
+    LastLoginAt,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum AuthUsers {
+    Table,
+    Id,
+    Password,
+}
@@ -0,0 +1,117 @@
+use phonenumber::country;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, Statement};
+use std::collections::HashMap;
+use std::env;
+use tracing::{info, warn};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// The migration CLI only prints events logged under its own target unless run with `-v`.
+const LOG_TARGET: &str = "sea_orm_migration";
+
+/// Same parsing as `src/utils/phone.rs`: numbers without a country code are read in
+/// `PHONE_DEFAULT_REGION`.
+fn normalize_phone(region: Option<country::Id>, input: &str) -> Option<String> {
+    let number = phonenumber::parse(region, input.trim()).ok()?;
+    number
+        .is_valid()
+        .then(|| number.format().mode(phonenumber::Mode::E164).to_string())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let region = match env::var("PHONE_DEFAULT_REGION") {
+            Ok(region) if !region.trim().is_empty() => {
+                Some(region.trim().to_ascii_uppercase().parse().map_err(|_| {
+                    DbErr::Migration(format!(
+                        "PHONE_DEFAULT_REGION must be an ISO 3166 country code, got {}",
+                        region
+                    ))
+                })?)
+            }
+            _ => None,
+        };
+        let db = manager.get_connection();
+        let backend = manager.get_database_backend();
+
+        // 1. Work out every row's canonical number; unparseable ones are kept as they are
+        let rows = db
+            .query_all(Statement::from_string(
+                backend,
+                "SELECT id, phone FROM auth_users WHERE phone <> '' ORDER BY id",
+            ))
+            .await?;
+        let mut updates = Vec::new();
+        let mut owners: HashMap<String, Vec<i32>> = HashMap::new();
+        for row in rows {
+            let id: i32 = row.try_get("", "id")?;
+            let phone: String = row.try_get("", "phone")?;
+            let canonical = match normalize_phone(region, &phone) {
+                Some(canonical) => canonical,
+                None => {
+                    // The number itself stays out of the log; the id is enough to find the row.
+                    warn!(
+                        target: LOG_TARGET,
+                        "auth_users {}: cannot parse the phone number, left unchanged",
+                        id
+                    );
+                    phone.clone()
+                }
+            };
+            if canonical != phone {
+                updates.push((id, canonical.clone()));
+            }
+            owners.entry(canonical).or_default().push(id);
+        }
+
+        // 2. Numbers must be unique once normalized; duplicates need a human decision
+        let mut duplicates: Vec<String> = owners
+            .iter()
+            .filter(|(_, ids)| ids.len() > 1)
+            .map(|(phone, ids)| format!("{} (users {:?})", phone, ids))
+            .collect();
+        if !duplicates.is_empty() {
+            duplicates.sort();
+            return Err(DbErr::Migration(format!(
+                "phone numbers shared by several users, resolve before migrating: {}",
+                duplicates.join(", ")
+            )));
+        }
+
+        // 3. Store the canonical form and enforce uniqueness for users that have a number
+        for (id, phone) in &updates {
+            db.execute(Statement::from_sql_and_values(
+                backend,
+                "UPDATE auth_users SET phone = $1 WHERE id = $2",
+                [phone.clone().into(), (*id).into()],
+            ))
+            .await?;
+        }
+        info!(target: LOG_TARGET, "Normalized {} phone numbers to E.164", updates.len());
+        db.execute_unprepared(
+            "CREATE UNIQUE INDEX idx_auth_users_phone ON auth_users (phone) WHERE phone <> ''",
+        )
+        .await?;
+        Ok(())
+    }
+
+    // Normalized numbers are kept; only the index is dropped.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_auth_users_phone")
+                    .table(AuthUsers::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AuthUsers {
+    Table,
+}
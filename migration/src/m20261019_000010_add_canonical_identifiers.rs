@@ -0,0 +1,131 @@
+use crate::identifiers::{canonical, find_collisions, username_skeleton};
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, Statement};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let backend = manager.get_database_backend();
+
+        // 1. Existing collisions would break the unique indexes; they need a human decision
+        let blocking = find_collisions(db)
+            .await?
+            .into_iter()
+            .filter(|c| c.blocks_migration())
+            .count();
+        if blocking > 0 {
+            return Err(DbErr::Migration(format!(
+                "{} usernames or emails collide once compared case-insensitively; \
+                 run `cargo run --bin identifier_collisions` for the list",
+                blocking
+            )));
+        }
+
+        // 2. Add the columns and fill them from the stored values
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthUsers::Table)
+                    .add_column(
+                        ColumnDef::new(AuthUsers::UsernameCanonical)
+                            .string()
+                            .not_null()
+                            .default(""),
+                    )
+                    .add_column(
+                        ColumnDef::new(AuthUsers::UsernameSkeleton)
+                            .string()
+                            .not_null()
+                            .default(""),
+                    )
+                    .add_column(
+                        ColumnDef::new(AuthUsers::EmailCanonical)
+                            .string()
+                            .not_null()
+                            .default(""),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        let rows = db
+            .query_all(Statement::from_string(
+                backend,
+                "SELECT id, username, email FROM auth_users",
+            ))
+            .await?;
+        for row in rows {
+            let id: i32 = row.try_get("", "id")?;
+            let username: String = row.try_get("", "username")?;
+            let email: String = row.try_get("", "email")?;
+            db.execute(Statement::from_sql_and_values(
+                backend,
+                "UPDATE auth_users \
+                 SET username_canonical = $1, username_skeleton = $2, email_canonical = $3 \
+                 WHERE id = $4",
+                [
+                    canonical(&username).into(),
+                    username_skeleton(&username).into(),
+                    canonical(&email).into(),
+                    id.into(),
+                ],
+            ))
+            .await?;
+        }
+
+        // 3. Lookups and uniqueness go through the canonical forms
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_auth_users_username_canonical")
+                    .table(AuthUsers::Table)
+                    .col(AuthUsers::UsernameCanonical)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_auth_users_email_canonical")
+                    .table(AuthUsers::Table)
+                    .col(AuthUsers::EmailCanonical)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_auth_users_username_skeleton")
+                    .table(AuthUsers::Table)
+                    .col(AuthUsers::UsernameSkeleton)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthUsers::Table)
+                    .drop_column(AuthUsers::UsernameCanonical)
+                    .drop_column(AuthUsers::UsernameSkeleton)
+                    .drop_column(AuthUsers::EmailCanonical)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AuthUsers {
+    Table,
+    UsernameCanonical,
+    UsernameSkeleton,
+    EmailCanonical,
+}
@@ -0,0 +1,104 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::ConnectionTrait;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // 1. Add the lifecycle columns; every account starts out active
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthUsers::Table)
+                    .add_column(
+                        ColumnDef::new(AuthUsers::Status)
+                            .string_len(32)
+                            .not_null()
+                            .default("active"),
+                    )
+                    .add_column(ColumnDef::new(AuthUsers::StatusReason).string().null())
+                    .add_column(
+                        ColumnDef::new(AuthUsers::StatusUntil)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // 2. Accounts switched off through the old flag were closed by hand
+        db.execute_unprepared("UPDATE auth_users SET status = 'deactivated' WHERE NOT active")
+            .await?;
+
+        // 3. The status replaces the flag
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthUsers::Table)
+                    .drop_column(AuthUsers::Active)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_auth_users_status")
+                    .table(AuthUsers::Table)
+                    .col(AuthUsers::Status)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthUsers::Table)
+                    .add_column(
+                        ColumnDef::new(AuthUsers::Active)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        db.execute_unprepared("UPDATE auth_users SET active = (status = 'active')")
+            .await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_auth_users_status")
+                    .table(AuthUsers::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthUsers::Table)
+                    .drop_column(AuthUsers::Status)
+                    .drop_column(AuthUsers::StatusReason)
+                    .drop_column(AuthUsers::StatusUntil)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AuthUsers {
+    Table,
+    Active,
+    Status,
+    StatusReason,
+    StatusUntil,
+}
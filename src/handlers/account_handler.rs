@@ -0,0 +1,477 @@
+// src/handlers/account_handler.rs
+use crate::handlers::passwordless_handler::send_in_background;
+use crate::handlers::phone_handler::{SendOutcome, requires_sms, text_code};
+use crate::models::auth_model::{
+    self, Column, DeleteAccountRequest, Entity as User, ExportDownloadQuery, ExportFormat,
+};
+use crate::services::account_service::{StatusError, schedule_deletion};
+use crate::services::audit_service;
+use crate::services::auth_service::verify_password;
+use crate::services::export_service::collect_account_data;
+use crate::utils::account_deletion::AccountDeletionConfig;
+use crate::utils::account_export::{
+    AccountExportConfig, ExportState, discard_export, finish_export, start_export, take_export,
+};
+use crate::utils::auth_middleware::AuthenticatedUser;
+use crate::utils::client_ip::ClientIp;
+use crate::utils::mailer::{Email, Mailer};
+use crate::utils::otp::{OtpCheck, issue_code, start_cooldown, verify_code};
+use crate::utils::passwordless::PasswordlessConfig;
+use crate::utils::revocation::revoke_user_tokens;
+use crate::utils::sms::{SmsConfig, SmsSender};
+use actix_web::{HttpResponse, Responder, delete, get, http::header, post, rt, web};
+use chrono::{Duration, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use redis::aio::ConnectionManager;
+use sea_orm::entity::prelude::*;
+use sea_orm::{DatabaseConnection, TransactionTrait};
+use serde_json::json;
+use std::io::{Cursor, Write};
+use tracing::{debug, error, info, warn};
+use validator::Validate;
+use zip::result::ZipResult;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// `otp` purpose of the code confirming the deletion of an account without a password.
+const ACCOUNT_DELETE: &str = "account_delete";
+/// `otp` cooldown purpose limiting how often an account can be exported.
+const ACCOUNT_EXPORT: &str = "account_export";
+
+fn internal_error() -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({"code":500,"message":"Internal server error"}))
+}
+
+// Closing or exporting the account takes a full login, like API keys.
+async fn account_owner(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+) -> Result<auth_model::Model, HttpResponse> {
+    if !user.is_full_login() {
+        return Err(HttpResponse::Forbidden()
+            .json(json!({"code":403,"message":"The account can only be managed after a login"})));
+    }
+
+    match User::find()
+        .filter(Column::Username.eq(&user.username))
+        .one(db)
+        .await
+    {
+        Ok(Some(owner)) => Ok(owner),
+        Ok(None) => Err(HttpResponse::Unauthorized()
+            .json(json!({"code":401,"message":"User no longer exists"}))),
+        Err(e) => {
+            error!("Database error: {}", e);
+            Err(internal_error())
+        }
+    }
+}
+
+/// Send the confirmation code for an account without a password: by text when the user signs
+/// in with SMS MFA, by email otherwise.
+async fn send_confirmation_code(
+    redis: &ConnectionManager,
+    mailer: web::Data<Mailer>,
+    passwordless: &PasswordlessConfig,
+    sms: web::Data<SmsSender>,
+    sms_config: &SmsConfig,
+    owner: &auth_model::Model,
+) -> HttpResponse {
+    let code_sent = |channel: &str| {
+        HttpResponse::Accepted().json(json!({
+            "code":202,
+            "message":"Confirmation code sent; repeat the request with it",
+            "channel":channel
+        }))
+    };
+    let cooling_down = || {
+        HttpResponse::TooManyRequests()
+            .json(json!({"code":429,"message":"Please wait before requesting another code"}))
+    };
+
+    if requires_sms(owner) {
+        return match text_code(redis, sms, sms_config, owner, &owner.phone, ACCOUNT_DELETE).await {
+            Ok(SendOutcome::Sent) => code_sent("sms"),
+            Ok(SendOutcome::CoolingDown) => cooling_down(),
+            Ok(SendOutcome::DailyLimit) => HttpResponse::TooManyRequests()
+                .json(json!({"code":429,"message":"Too many codes sent; try again later"})),
+            Err(e) => {
+                error!("Redis error sending deletion code: {}", e);
+                internal_error()
+            }
+        };
+    }
+
+    match start_cooldown(
+        redis,
+        ACCOUNT_DELETE,
+        owner.id,
+        passwordless.resend_cooldown,
+    )
+    .await
+    {
+        Ok(true) => (),
+        Ok(false) => return cooling_down(),
+        Err(e) => {
+            error!("Redis error: {}", e);
+            return internal_error();
+        }
+    }
+    let code = match issue_code(redis, ACCOUNT_DELETE, owner.id, passwordless.otp_ttl).await {
+        Ok(code) => code,
+        Err(e) => {
+            error!("Redis error storing deletion code: {}", e);
+            return internal_error();
+        }
+    };
+    send_in_background(
+        mailer,
+        Email {
+            to: owner.email.clone(),
+            subject: "Confirm the deletion of your account".to_string(),
+            body: format!(
+                "Your confirmation code is {}\n\nIt expires in {} minutes. \
+                 If you did not ask to delete your account, ignore this email.",
+                code,
+                passwordless.otp_ttl / 60
+            ),
+        },
+    );
+    code_sent("email")
+}
+
+//===============================
+// Actix-web Handlers
+//===============================
+/// Close the caller's account: it is deactivated and its sessions end at once, and it is
+/// purged after `ACCOUNT_DELETION_GRACE_PERIOD` unless the owner signs in before then.
+#[delete("/account")]
+#[allow(clippy::too_many_arguments)]
+pub async fn delete_account(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    mailer: web::Data<Mailer>,
+    passwordless: web::Data<PasswordlessConfig>,
+    sms: web::Data<SmsSender>,
+    sms_config: web::Data<SmsConfig>,
+    config: web::Data<AccountDeletionConfig>,
+    user: AuthenticatedUser,
+    client_ip: ClientIp,
+    form: web::Json<DeleteAccountRequest>,
+) -> impl Responder {
+    debug!("delete account checkpoint api.");
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    let owner = match account_owner(db.get_ref(), &user).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    // 1. Confirm with the password, or with a code for accounts that have none
+    match (&owner.password, &form.code) {
+        (Some(hash), _) => {
+            let Some(password) = form.password.clone() else {
+                return HttpResponse::BadRequest()
+                    .json(json!({"code":400,"message":"Password required"}));
+            };
+            match verify_password(password, hash.clone()).await {
+                Ok(true) => (),
+                Ok(false) => {
+                    warn!(
+                        "Account deletion for user {} refused: wrong password from IP {}",
+                        owner.username, client_ip
+                    );
+                    return HttpResponse::Unauthorized()
+                        .json(json!({"code":401,"message":"Invalid password"}));
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    return internal_error();
+                }
+            }
+        }
+        (None, None) => {
+            return send_confirmation_code(
+                redis.get_ref(),
+                mailer,
+                passwordless.get_ref(),
+                sms,
+                sms_config.get_ref(),
+                &owner,
+            )
+            .await;
+        }
+        (None, Some(code)) => {
+            let max_attempts = if requires_sms(&owner) {
+                sms_config.otp_max_attempts
+            } else {
+                passwordless.otp_max_attempts
+            };
+            match verify_code(
+                redis.get_ref(),
+                ACCOUNT_DELETE,
+                owner.id,
+                code,
+                max_attempts,
+            )
+            .await
+            {
+                Ok(OtpCheck::Valid) => (),
+                Ok(OtpCheck::Invalid) => {
+                    warn!(
+                        "Invalid account deletion code for user {} from IP {}",
+                        owner.username, client_ip
+                    );
+                    return HttpResponse::Unauthorized()
+                        .json(json!({"code":401,"message":"Invalid or expired code"}));
+                }
+                Ok(OtpCheck::TooManyAttempts) => {
+                    return HttpResponse::TooManyRequests().json(
+                        json!({"code":429,"message":"Too many attempts; request a new code"}),
+                    );
+                }
+                Err(e) => {
+                    error!("Redis error verifying deletion code: {}", e);
+                    return internal_error();
+                }
+            }
+        }
+    }
+
+    // 2. Deactivate now and schedule the purge, together with the audit event
+    let purge_after = Utc::now() + Duration::seconds(config.grace_period as i64);
+    let result: Result<auth_model::Model, StatusError> = async {
+        let txn = db.begin().await.map_err(StatusError::Database)?;
+        audit_service::record(
+            &txn,
+            &owner,
+            "user.delete_requested",
+            Some(owner.id),
+            json!({"purge_after":purge_after}),
+            client_ip,
+        )
+        .await
+        .map_err(StatusError::Database)?;
+        let owner = schedule_deletion(&txn, owner, purge_after.into()).await?;
+        txn.commit().await.map_err(StatusError::Database)?;
+        Ok(owner)
+    }
+    .await;
+    let owner = match result {
+        Ok(owner) => owner,
+        Err(e @ StatusError::Transition(..)) => {
+            return HttpResponse::Conflict().json(json!({"code":409,"message":e.to_string()}));
+        }
+        Err(StatusError::Database(e)) => {
+            error!("Database error: {}", e);
+            return internal_error();
+        }
+    };
+
+    // 3. End every session, then tell the owner how to change their mind
+    if let Err(e) = revoke_user_tokens(redis.get_ref(), &owner.username).await {
+        error!("Redis error revoking sessions of {}: {}", owner.username, e);
+        return internal_error();
+    }
+    send_in_background(
+        mailer,
+        Email {
+            to: owner.email.clone(),
+            subject: "Your account will be deleted".to_string(),
+            body: format!(
+                "Your account {} was closed and will be deleted for good on {}.\n\n\
+                 Changed your mind? Sign in before then to keep it.",
+                owner.username,
+                purge_after.format("%Y-%m-%d %H:%M UTC")
+            ),
+        },
+    );
+    info!(
+        "User {} deleted their account from IP {}; purge after {}",
+        owner.username, client_ip, purge_after
+    );
+    HttpResponse::Ok().json(json!({
+        "code":200,
+        "message":"Account closed; sign in before purge_after to keep it",
+        "purge_after":purge_after
+    }))
+}
+
+/// Start a data export of the caller's account. The archive is built in the background and
+/// served once from the returned `download_url`, for `ACCOUNT_EXPORT_TTL` seconds.
+#[post("/account/export")]
+pub async fn request_export(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    mailer: web::Data<Mailer>,
+    config: web::Data<AccountExportConfig>,
+    user: AuthenticatedUser,
+    client_ip: ClientIp,
+) -> impl Responder {
+    debug!("request export checkpoint api.");
+    let owner = match account_owner(db.get_ref(), &user).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    // 1. One export per cooldown: each reads the whole account and holds the archive in Redis
+    match start_cooldown(redis.get_ref(), ACCOUNT_EXPORT, owner.id, config.cooldown).await {
+        Ok(true) => (),
+        Ok(false) => {
+            return HttpResponse::TooManyRequests().json(
+                json!({"code":429,"message":"An export was requested recently; try again later"}),
+            );
+        }
+        Err(e) => {
+            error!("Redis error: {}", e);
+            return internal_error();
+        }
+    }
+    if let Err(e) = audit_service::record(
+        db.get_ref(),
+        &owner,
+        "user.export",
+        Some(owner.id),
+        json!({}),
+        client_ip,
+    )
+    .await
+    {
+        error!("Failed to record audit event: {}", e);
+        return internal_error();
+    }
+
+    // 2. Reserve the link, then build the archive in the background
+    let token = match start_export(redis.get_ref(), config.link_ttl).await {
+        Ok(token) => token,
+        Err(e) => {
+            error!("Redis error starting export: {}", e);
+            return internal_error();
+        }
+    };
+    let job_token = token.clone();
+    let link_ttl = config.link_ttl;
+    rt::spawn(async move {
+        let stored = match collect_account_data(db.get_ref(), redis.get_ref(), &owner).await {
+            Ok(archive) => {
+                finish_export(redis.get_ref(), &job_token, &archive.to_string(), link_ttl)
+                    .await
+                    .map_err(|e| format!("Redis error: {}", e))
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = stored {
+            error!("Data export of {} failed: {}", owner.username, e);
+            if let Err(e) = discard_export(redis.get_ref(), &job_token).await {
+                error!("Redis error discarding export: {}", e);
+            }
+            return;
+        }
+
+        info!("Data export of {} ready", owner.username);
+        let email = Email {
+            to: owner.email.clone(),
+            subject: "Your data export is ready".to_string(),
+            body: format!(
+                "The export of your account data you requested is ready. Download it within \
+                 {} hours from the download link you were given when you requested it; it \
+                 works once.\n\nIf you did not request it, change your password.",
+                link_ttl / 3600
+            ),
+        };
+        if let Err(e) = mailer.send(email).await {
+            error!("Failed to send email to {}: {}", owner.email, e);
+        }
+    });
+
+    info!(
+        "User {} requested a data export from IP {}",
+        user.username, client_ip
+    );
+    HttpResponse::Accepted().json(json!({
+        "code":202,
+        "message":"Export started",
+        "download_url":format!("/api/v1/auth/account/export/{}", token),
+        "expires_in":config.link_ttl
+    }))
+}
+
+/// Pack the export document into a `.zip` archive with a single deflated entry.
+fn zip_archive(archive: &str) -> ZipResult<Vec<u8>> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    writer.start_file("account-export.json", options)?;
+    writer.write_all(archive.as_bytes())?;
+    Ok(writer.finish()?.into_inner())
+}
+
+/// Serve a finished export. The token in the link is the only credential, and it works once.
+#[get("/account/export/{token}")]
+pub async fn download_export(
+    redis: web::Data<ConnectionManager>,
+    path: web::Path<String>,
+    query: web::Query<ExportDownloadQuery>,
+) -> impl Responder {
+    debug!("download export checkpoint api.");
+    let archive = match take_export(redis.get_ref(), &path.into_inner()).await {
+        Ok(ExportState::Ready(archive)) => archive,
+        Ok(ExportState::Pending) => {
+            return HttpResponse::Accepted()
+                .insert_header((header::RETRY_AFTER, "5"))
+                .json(json!({"code":202,"message":"Export in progress; retry shortly"}));
+        }
+        Ok(ExportState::Missing) => {
+            return HttpResponse::NotFound()
+                .json(json!({"code":404,"message":"Export link expired or already used"}));
+        }
+        Err(e) => {
+            error!("Redis error reading export: {}", e);
+            return internal_error();
+        }
+    };
+
+    match query.format {
+        ExportFormat::Json => HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"account-export.json\"",
+            ))
+            .body(archive),
+        ExportFormat::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            let compressed = encoder
+                .write_all(archive.as_bytes())
+                .and_then(|()| encoder.finish());
+            match compressed {
+                Ok(bytes) => HttpResponse::Ok()
+                    .content_type("application/gzip")
+                    .insert_header((
+                        header::CONTENT_DISPOSITION,
+                        "attachment; filename=\"account-export.json.gz\"",
+                    ))
+                    .body(bytes),
+                Err(e) => {
+                    error!("Failed to compress export: {}", e);
+                    internal_error()
+                }
+            }
+        }
+        ExportFormat::Zip => match zip_archive(&archive) {
+            Ok(bytes) => HttpResponse::Ok()
+                .content_type("application/zip")
+                .insert_header((
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"account-export.zip\"",
+                ))
+                .body(bytes),
+            Err(e) => {
+                error!("Failed to compress export: {}", e);
+                internal_error()
+            }
+        },
+    }
+}
@@ -0,0 +1,595 @@
+// src/handlers/admin_user_handler.rs
+use crate::handlers::password_handler::send_reset_code;
+use crate::models::auth_model::{
+    self, Column, Entity, ImpersonateUserRequest, ListUsersQuery, Role, SortOrder,
+    SuspendUserRequest, UserSort, UserStatus,
+};
+use crate::models::{api_key_model, audit_model, identity_model};
+use crate::services::account_service::{StatusError, change_status, purge_account};
+use crate::services::audit_service;
+use crate::utils::auth_middleware::AdminUser;
+use crate::utils::client_ip::ClientIp;
+use crate::utils::identifier::canonical_email;
+use crate::utils::impersonation::ImpersonationConfig;
+use crate::utils::jwt::encode_impersonation_token;
+use crate::utils::mailer::Mailer;
+use crate::utils::passwordless::PasswordlessConfig;
+use crate::utils::revocation::revoke_user_tokens;
+use actix_web::{HttpResponse, Responder, delete, get, post, web};
+use chrono::{Duration, Utc};
+use redis::aio::ConnectionManager;
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::LikeExpr;
+use sea_orm::{
+    ActiveModelTrait, Condition, DatabaseConnection, IntoActiveModel, Order, PaginatorTrait,
+    QueryOrder, QuerySelect, Set, TransactionTrait,
+};
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+use validator::Validate;
+
+/// Audit events shown with a user's details.
+const RECENT_EVENTS: u64 = 50;
+
+/// A LIKE pattern matching `needle` anywhere, with `%`, `_` and `\` taken literally.
+fn contains_pattern(needle: &str) -> LikeExpr {
+    let mut escaped = String::with_capacity(needle.len() + 2);
+    escaped.push('%');
+    for c in needle.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped.push('%');
+    LikeExpr::new(escaped).escape('\\')
+}
+
+fn internal_error() -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({"code":500,"message":"Internal server error"}))
+}
+
+async fn target_user(db: &DatabaseConnection, id: i32) -> Result<auth_model::Model, HttpResponse> {
+    match Entity::find_by_id(id).one(db).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => {
+            Err(HttpResponse::NotFound().json(json!({"code":404,"message":"User not found"})))
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            Err(internal_error())
+        }
+    }
+}
+
+fn not_yourself(admin: &AdminUser, user: &auth_model::Model) -> Option<HttpResponse> {
+    (admin.0.id == user.id).then(|| {
+        HttpResponse::Conflict().json(
+            json!({"code":409,"message":"Use another administrator account to change your own status"}),
+        )
+    })
+}
+
+/// Apply a status change and its audit event in one transaction.
+#[allow(clippy::too_many_arguments)]
+async fn audited_status_change(
+    db: &DatabaseConnection,
+    admin: &AdminUser,
+    user: auth_model::Model,
+    status: UserStatus,
+    reason: Option<String>,
+    until: Option<DateTimeWithTimeZone>,
+    action: &str,
+    client_ip: ClientIp,
+) -> HttpResponse {
+    let result: Result<auth_model::Model, StatusError> = async {
+        let txn = db.begin().await.map_err(StatusError::Database)?;
+        audit_service::record(
+            &txn,
+            &admin.0,
+            action,
+            Some(user.id),
+            json!({"from":user.status,"to":status,"reason":reason,"until":until}),
+            client_ip,
+        )
+        .await
+        .map_err(StatusError::Database)?;
+        let user = change_status(&txn, user, status, reason, until).await?;
+        txn.commit().await.map_err(StatusError::Database)?;
+        Ok(user)
+    }
+    .await;
+
+    match result {
+        Ok(user) => {
+            info!(
+                "Admin {} set user {} to {}",
+                admin.0.username,
+                user.username,
+                status.to_value()
+            );
+            HttpResponse::Ok().json(json!({"code":200,"message":"User status updated","user":user}))
+        }
+        Err(e @ StatusError::Transition(..)) => {
+            HttpResponse::Conflict().json(json!({"code":409,"message":e.to_string()}))
+        }
+        Err(StatusError::Database(e)) => {
+            error!("Database error: {}", e);
+            internal_error()
+        }
+    }
+}
+
+/// Suspending or deleting an account ends its sessions too; the status alone only stops new
+/// sign-ins.
+async fn end_sessions(redis: &ConnectionManager, user: &auth_model::Model) -> Option<HttpResponse> {
+    match revoke_user_tokens(redis, &user.username).await {
+        Ok(()) => None,
+        Err(e) => {
+            error!("Redis error revoking sessions of {}: {}", user.username, e);
+            Some(internal_error())
+        }
+    }
+}
+
+//===============================
+// Actix-web Handlers
+//===============================
+#[get("/users")]
+pub async fn list_users(
+    db: web::Data<DatabaseConnection>,
+    admin: AdminUser,
+    client_ip: ClientIp,
+    query: web::Query<ListUsersQuery>,
+) -> impl Responder {
+    debug!("list users checkpoint api.");
+    if let Err(e) = query.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(20);
+
+    // 1. Filters
+    let mut select = Entity::find();
+    if let Some(status) = query.status {
+        select = select.filter(Column::Status.eq(status));
+    }
+    if let Some(from) = query.created_from {
+        select = select.filter(Column::CreatedAt.gte(from));
+    }
+    if let Some(to) = query.created_to {
+        select = select.filter(Column::CreatedAt.lt(to));
+    }
+    if let Some(q) = &query.q {
+        // Usernames and emails fold the same way, so one search key matches both.
+        let pattern = contains_pattern(&canonical_email(q));
+        select = select.filter(
+            Condition::any()
+                .add(Column::UsernameCanonical.like(pattern.clone()))
+                .add(Column::EmailCanonical.like(pattern)),
+        );
+    }
+
+    // 2. Sorting; the id breaks ties so pages never overlap
+    let column = match query.sort.unwrap_or(UserSort::CreatedAt) {
+        UserSort::CreatedAt => Column::CreatedAt,
+        UserSort::Username => Column::UsernameCanonical,
+        UserSort::Email => Column::EmailCanonical,
+    };
+    let order = match query.order.unwrap_or(SortOrder::Desc) {
+        SortOrder::Asc => Order::Asc,
+        SortOrder::Desc => Order::Desc,
+    };
+    let paginator = select
+        .order_by(column, order.clone())
+        .order_by(Column::Id, order)
+        .paginate(db.get_ref(), per_page);
+
+    let (total, users) = match paginator.num_items().await {
+        Ok(total) => match paginator.fetch_page(page - 1).await {
+            Ok(users) => (total, users),
+            Err(e) => {
+                error!("Database error: {}", e);
+                return internal_error();
+            }
+        },
+        Err(e) => {
+            error!("Database error: {}", e);
+            return internal_error();
+        }
+    };
+
+    // 3. Reading account data is audited like changing it
+    if let Err(e) = audit_service::record(
+        db.get_ref(),
+        &admin.0,
+        "user.list",
+        None,
+        json!(query.into_inner()),
+        client_ip,
+    )
+    .await
+    {
+        error!("Failed to record audit event: {}", e);
+        return internal_error();
+    }
+    HttpResponse::Ok().json(json!({
+        "code":200,
+        "message":"Users fetched successfully",
+        "users":users,
+        "page":page,
+        "per_page":per_page,
+        "total":total
+    }))
+}
+
+#[get("/users/{id}")]
+pub async fn get_user(
+    db: web::Data<DatabaseConnection>,
+    admin: AdminUser,
+    client_ip: ClientIp,
+    path: web::Path<i32>,
+) -> impl Responder {
+    debug!("get user checkpoint api.");
+    let user = match target_user(db.get_ref(), path.into_inner()).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    let related = async {
+        let identities = identity_model::Entity::find()
+            .filter(identity_model::Column::UserId.eq(user.id))
+            .order_by_asc(identity_model::Column::Id)
+            .all(db.get_ref())
+            .await?;
+        let api_keys = api_key_model::Entity::find()
+            .filter(api_key_model::Column::UserId.eq(user.id))
+            .order_by_asc(api_key_model::Column::Id)
+            .all(db.get_ref())
+            .await?;
+        let events = audit_model::Entity::find()
+            .filter(audit_model::Column::TargetUserId.eq(user.id))
+            .order_by_desc(audit_model::Column::Id)
+            .limit(RECENT_EVENTS)
+            .all(db.get_ref())
+            .await?;
+        audit_service::record(
+            db.get_ref(),
+            &admin.0,
+            "user.view",
+            Some(user.id),
+            json!({}),
+            client_ip,
+        )
+        .await?;
+        Ok::<_, DbErr>((identities, api_keys, events))
+    }
+    .await;
+
+    match related {
+        Ok((identities, api_keys, events)) => HttpResponse::Ok().json(json!({
+            "code":200,
+            "message":"User fetched successfully",
+            "user":user,
+            "identities":identities,
+            "api_keys":api_keys,
+            "audit_events":events
+        })),
+        Err(e) => {
+            error!("Database error: {}", e);
+            internal_error()
+        }
+    }
+}
+
+#[post("/users/{id}/suspend")]
+pub async fn suspend_user(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    admin: AdminUser,
+    client_ip: ClientIp,
+    path: web::Path<i32>,
+    form: web::Json<SuspendUserRequest>,
+) -> impl Responder {
+    debug!("suspend user checkpoint api.");
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    if form.until.is_some_and(|until| until <= Utc::now()) {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"until must be in the future"}));
+    }
+    let user = match target_user(db.get_ref(), path.into_inner()).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Some(response) = not_yourself(&admin, &user) {
+        return response;
+    }
+
+    let form = form.into_inner();
+    let response = audited_status_change(
+        db.get_ref(),
+        &admin,
+        user.clone(),
+        UserStatus::Suspended,
+        Some(form.reason),
+        form.until,
+        "user.suspend",
+        client_ip,
+    )
+    .await;
+    if !response.status().is_success() {
+        return response;
+    }
+    end_sessions(redis.get_ref(), &user)
+        .await
+        .unwrap_or(response)
+}
+
+#[post("/users/{id}/unsuspend")]
+pub async fn unsuspend_user(
+    db: web::Data<DatabaseConnection>,
+    admin: AdminUser,
+    client_ip: ClientIp,
+    path: web::Path<i32>,
+) -> impl Responder {
+    debug!("unsuspend user checkpoint api.");
+    let user = match target_user(db.get_ref(), path.into_inner()).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if user.status != UserStatus::Suspended {
+        return HttpResponse::Conflict()
+            .json(json!({"code":409,"message":"User is not suspended"}));
+    }
+    audited_status_change(
+        db.get_ref(),
+        &admin,
+        user,
+        UserStatus::Active,
+        None,
+        None,
+        "user.unsuspend",
+        client_ip,
+    )
+    .await
+}
+
+/// Refuse password login until the user sets a new password with the code emailed to them.
+#[post("/users/{id}/force-password-reset")]
+#[allow(clippy::too_many_arguments)]
+pub async fn force_password_reset(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    mailer: web::Data<Mailer>,
+    config: web::Data<PasswordlessConfig>,
+    admin: AdminUser,
+    client_ip: ClientIp,
+    path: web::Path<i32>,
+) -> impl Responder {
+    debug!("force password reset checkpoint api.");
+    let user = match target_user(db.get_ref(), path.into_inner()).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if user.password.is_none() {
+        return HttpResponse::Conflict().json(
+            json!({"code":409,"message":"User signs in through an identity provider and has no password"}),
+        );
+    }
+    if user.status == UserStatus::Deleted {
+        return HttpResponse::Conflict().json(json!({"code":409,"message":"User is deleted"}));
+    }
+
+    let result: Result<auth_model::Model, DbErr> = async {
+        let txn = db.begin().await?;
+        audit_service::record(
+            &txn,
+            &admin.0,
+            "user.force_password_reset",
+            Some(user.id),
+            json!({}),
+            client_ip,
+        )
+        .await?;
+        let mut active = user.into_active_model();
+        active.password_reset_required = Set(true);
+        let user = active.update(&txn).await?;
+        txn.commit().await?;
+        Ok(user)
+    }
+    .await;
+    let user = match result {
+        Ok(user) => user,
+        Err(e) => {
+            error!("Database error: {}", e);
+            return internal_error();
+        }
+    };
+
+    if let Some(response) = end_sessions(redis.get_ref(), &user).await {
+        return response;
+    }
+    if let Err(e) = send_reset_code(redis.get_ref(), mailer, config.get_ref(), &user).await {
+        error!("Redis error storing password reset code: {}", e);
+        return internal_error();
+    }
+    info!(
+        "Admin {} forced a password reset for user {}",
+        admin.0.username, user.username
+    );
+    HttpResponse::Ok().json(
+        json!({"code":200,"message":"Password reset required; a reset code was emailed to the user"}),
+    )
+}
+
+#[post("/users/{id}/revoke-sessions")]
+pub async fn revoke_user_sessions(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    admin: AdminUser,
+    client_ip: ClientIp,
+    path: web::Path<i32>,
+) -> impl Responder {
+    debug!("revoke user sessions checkpoint api.");
+    let user = match target_user(db.get_ref(), path.into_inner()).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = audit_service::record(
+        db.get_ref(),
+        &admin.0,
+        "user.revoke_sessions",
+        Some(user.id),
+        json!({}),
+        client_ip,
+    )
+    .await
+    {
+        error!("Failed to record audit event: {}", e);
+        return internal_error();
+    }
+    if let Some(response) = end_sessions(redis.get_ref(), &user).await {
+        return response;
+    }
+    warn!(
+        "Admin {} revoked all sessions of user {}",
+        admin.0.username, user.username
+    );
+    HttpResponse::Ok().json(json!({"code":200,"message":"All sessions revoked"}))
+}
+
+/// Delete the account at once. It is purged exactly like a self-service deletion whose grace
+/// period ended, leaving a tombstone that reserves the username.
+#[delete("/users/{id}")]
+pub async fn delete_user(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    admin: AdminUser,
+    client_ip: ClientIp,
+    path: web::Path<i32>,
+) -> impl Responder {
+    debug!("delete user checkpoint api.");
+    let user = match target_user(db.get_ref(), path.into_inner()).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if let Some(response) = not_yourself(&admin, &user) {
+        return response;
+    }
+    let current = user.effective_status();
+    if !current.can_become(UserStatus::Deleted) {
+        let e = StatusError::Transition(current, UserStatus::Deleted);
+        return HttpResponse::Conflict().json(json!({"code":409,"message":e.to_string()}));
+    }
+
+    // 1. Audit and purge in one transaction
+    let result: Result<(), DbErr> = async {
+        let txn = db.begin().await?;
+        audit_service::record(
+            &txn,
+            &admin.0,
+            "user.delete",
+            Some(user.id),
+            json!({"from":user.status,"to":UserStatus::Deleted}),
+            client_ip,
+        )
+        .await?;
+        purge_account(&txn, user.clone()).await?;
+        txn.commit().await
+    }
+    .await;
+    if let Err(e) = result {
+        error!("Database error: {}", e);
+        return internal_error();
+    }
+    info!("Admin {} deleted user {}", admin.0.username, user.username);
+
+    // 2. End the sessions still holding tokens
+    if let Some(response) = end_sessions(redis.get_ref(), &user).await {
+        return response;
+    }
+    HttpResponse::Ok().json(json!({"code":200,"message":"User deleted"}))
+}
+
+/// Issue a short-lived token acting as the user, for support. Requests made with it are
+/// audited one by one and cannot change credentials, MFA or contact details.
+#[post("/users/{id}/impersonate")]
+pub async fn impersonate_user(
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<ImpersonationConfig>,
+    admin: AdminUser,
+    client_ip: ClientIp,
+    path: web::Path<i32>,
+    form: web::Json<ImpersonateUserRequest>,
+) -> impl Responder {
+    debug!("impersonate user checkpoint api.");
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    let user = match target_user(db.get_ref(), path.into_inner()).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    // 1. Administrators cannot borrow each other's rights, nor act as accounts that are gone
+    if admin.0.id == user.id {
+        return HttpResponse::Conflict()
+            .json(json!({"code":409,"message":"You cannot impersonate yourself"}));
+    }
+    if user.role == Role::Admin {
+        warn!(
+            "Admin {} tried to impersonate admin {}",
+            admin.0.username, user.username
+        );
+        return HttpResponse::Forbidden()
+            .json(json!({"code":403,"message":"Administrators cannot be impersonated"}));
+    }
+    if user.status == UserStatus::Deleted {
+        return HttpResponse::Conflict()
+            .json(json!({"code":409,"message":"Deleted accounts cannot be impersonated"}));
+    }
+
+    // 2. Record the impersonation before handing out the token
+    let ttl = Duration::seconds(config.ttl as i64);
+    let expires_at = Utc::now() + ttl;
+    if let Err(e) = audit_service::record(
+        db.get_ref(),
+        &admin.0,
+        "user.impersonate",
+        Some(user.id),
+        json!({"reason":form.reason,"expires_at":expires_at}),
+        client_ip,
+    )
+    .await
+    {
+        error!("Failed to record audit event: {}", e);
+        return internal_error();
+    }
+
+    // 3. Issue the token, naming the administrator in its act claim
+    let token =
+        match encode_impersonation_token(user.username.clone(), admin.0.username.clone(), ttl) {
+            Ok(token) => token,
+            Err(e) => {
+                error!("JWT encoding error: {}", e);
+                return internal_error();
+            }
+        };
+    warn!(
+        "Admin {} is impersonating user {} until {}",
+        admin.0.username, user.username, expires_at
+    );
+    HttpResponse::Ok().json(json!({
+        "code":200,
+        "message":"Impersonation token issued",
+        "token":token,
+        "token_type":"Bearer",
+        "expires_in":config.ttl
+    }))
+}
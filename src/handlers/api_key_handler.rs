@@ -0,0 +1,167 @@
+// src/handlers/api_key_handler.rs
+use crate::models::api_key_model::{ActiveModel, Column, CreateApiKeyRequest, Entity};
+use crate::models::auth_model::{self, Entity as User};
+use crate::utils::api_key::generate_api_key;
+use crate::utils::auth_middleware::AuthenticatedUser;
+use actix_web::{HttpResponse, Responder, delete, get, post, web};
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, IntoActiveModel, QueryOrder, Set};
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+use validator::Validate;
+
+// Keys can only be managed from a password login session, so a leaked key cannot mint more keys.
+async fn key_owner(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+) -> Result<auth_model::Model, HttpResponse> {
+    if !user.is_full_login() {
+        return Err(HttpResponse::Forbidden().json(
+            json!({"code":403,"message":"API keys can only be managed after a password login"}),
+        ));
+    }
+
+    match User::find()
+        .filter(auth_model::Column::Username.eq(&user.username))
+        .one(db)
+        .await
+    {
+        Ok(Some(owner)) => Ok(owner),
+        Ok(None) => Err(HttpResponse::Unauthorized()
+            .json(json!({"code":401,"message":"User no longer exists"}))),
+        Err(e) => {
+            error!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"})))
+        }
+    }
+}
+
+//===============================
+// Actix-web Handlers
+//===============================
+#[post("/api-keys")]
+pub async fn create_api_key(
+    db: web::Data<DatabaseConnection>,
+    user: AuthenticatedUser,
+    form: web::Json<CreateApiKeyRequest>,
+) -> impl Responder {
+    debug!("create api key checkpoint api.");
+    if let Err(e) = form.validate() {
+        warn!("Validation error during API key creation: {:?}", e);
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+
+    let owner = match key_owner(db.get_ref(), &user).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    // The plaintext key is only ever part of this response; the database keeps its hash.
+    let generated = generate_api_key();
+    let form = form.into_inner();
+    let new_key = ActiveModel {
+        user_id: Set(owner.id),
+        name: Set(form.name),
+        prefix: Set(generated.prefix),
+        key_hash: Set(generated.hash),
+        scopes: Set(form.scopes.join(" ")),
+        expires_at: Set(form.expires_at.map(Into::into)),
+        ..Default::default()
+    };
+
+    match new_key.insert(db.get_ref()).await {
+        Ok(res) => {
+            info!("User {} created API key {}", owner.username, res.prefix);
+            HttpResponse::Created().json(json!({
+                "code":201,
+                "message":"API key created, store it now as it will not be shown again",
+                "key":generated.key,
+                "api_key":res,
+            }))
+        }
+        Err(e) => {
+            error!("Database insertion error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+#[get("/api-keys")]
+pub async fn list_api_keys(
+    db: web::Data<DatabaseConnection>,
+    user: AuthenticatedUser,
+) -> impl Responder {
+    debug!("list api keys checkpoint api.");
+    let owner = match key_owner(db.get_ref(), &user).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    match Entity::find()
+        .filter(Column::UserId.eq(owner.id))
+        .order_by_desc(Column::CreatedAt)
+        .all(db.get_ref())
+        .await
+    {
+        Ok(keys) => HttpResponse::Ok()
+            .json(json!({"code":200,"message":"API keys fetched successfully","api_keys":keys})),
+        Err(e) => {
+            error!("Database error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+#[delete("/api-keys/{id}")]
+pub async fn revoke_api_key(
+    db: web::Data<DatabaseConnection>,
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+) -> impl Responder {
+    debug!("revoke api key checkpoint api.");
+    let owner = match key_owner(db.get_ref(), &user).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    let key = match Entity::find_by_id(path.into_inner())
+        .filter(Column::UserId.eq(owner.id))
+        .one(db.get_ref())
+        .await
+    {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            return HttpResponse::NotFound()
+                .json(json!({"code":404,"message":"API key not found"}));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    };
+
+    if key.revoked_at.is_some() {
+        return HttpResponse::Ok().json(json!({"code":200,"message":"API key already revoked"}));
+    }
+
+    let prefix = key.prefix.clone();
+    let mut revoked = key.into_active_model();
+    revoked.revoked_at = Set(Some(Utc::now().into()));
+    match revoked.update(db.get_ref()).await {
+        Ok(_) => {
+            info!("User {} revoked API key {}", owner.username, prefix);
+            HttpResponse::Ok().json(json!({"code":200,"message":"API key revoked"}))
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
@@ -1,20 +1,30 @@
 // src/handler/auth_handler.rs
-use crate::models::auth_model::{ActiveModel, Column, Entity, LoginRequest, RegisterRequest};
-use crate::utils::auth_middleware::AuthenticatedUser;
+use crate::handlers::phone_handler::{requires_sms, sms_challenge};
+use crate::models::auth_model::{
+    ActiveModel, Column, Entity, LoginRequest, RegisterRequest, UserStatus,
+};
+use crate::services::account_service::{cancel_deletion, status_refusal};
+use crate::services::auth_service::{AuthBackends, AuthFailure};
+use crate::utils::client_ip::ClientIp;
+use crate::utils::identifier::{
+    canonical_email, canonical_username, normalize_username, username_skeleton,
+};
 use crate::utils::jwt::{decode_jwt, encode_jwt};
+use crate::utils::passwordless::PasswordlessConfig;
+use crate::utils::phone::normalize_phone;
+use crate::utils::revocation::revoke_jwt;
+use crate::utils::sms::{SmsConfig, SmsSender};
 use actix_web::{HttpRequest, HttpResponse, Responder, get, post, web};
 use argon2::password_hash::SaltString;
 use argon2::{
     Argon2,
-    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, rand_core::OsRng},
+    password_hash::{PasswordHasher, rand_core::OsRng},
 };
-use chrono::Utc;
-use redis::AsyncCommands;
 use redis::aio::ConnectionManager;
 use sea_orm::Condition;
 use sea_orm::entity::prelude::*;
 use sea_orm::error::SqlErr;
-use sea_orm::{ActiveModelTrait, DatabaseConnection};
+use sea_orm::{ActiveModelTrait, DatabaseConnection, Set};
 use serde_json::json;
 use tracing::{debug, error, info, warn};
 use validator::Validate;
@@ -31,7 +41,11 @@ pub async fn index() -> impl Responder {
 #[post("/login")]
 pub async fn login(
     db: web::Data<DatabaseConnection>,
-    req: HttpRequest,
+    redis: web::Data<ConnectionManager>,
+    backends: web::Data<AuthBackends>,
+    sms: web::Data<SmsSender>,
+    sms_config: web::Data<SmsConfig>,
+    client_ip: ClientIp,
     form: web::Json<LoginRequest>,
 ) -> impl Responder {
     debug!("login checkpoint api.");
@@ -41,117 +55,95 @@ pub async fn login(
             .json(json!({"code":400,"message":"Validation error","errors":e}));
     }
 
-    let client_ip = req
-        .connection_info()
-        .realip_remote_addr()
-        .unwrap_or("unknown")
-        .to_string();
+    // 1. Try each configured backend in order; the first to accept the password wins
+    let mut unavailable = false;
+    for backend in backends.iter() {
+        let user = match backend
+            .authenticate(db.get_ref(), &form.identifier, &form.password)
+            .await
+        {
+            Ok(user) => user,
+            Err(AuthFailure::InvalidCredentials) => continue,
+            Err(AuthFailure::Refused(message)) => {
+                warn!(
+                    "Login refused by {} backend for user {} from IP {}: {}",
+                    backend.name(),
+                    form.identifier,
+                    client_ip,
+                    message
+                );
+                return HttpResponse::Forbidden().json(json!({"code":403,"message":message}));
+            }
+            Err(AuthFailure::Unavailable(e)) => {
+                error!("{} authentication backend error: {}", backend.name(), e);
+                unavailable = true;
+                continue;
+            }
+        };
 
-    // 1. Database Query (Async I/O Bound)
-    // This runs on the main async thread pool. It yields control while waiting for the DB.
-    // Main Thread / Async
-    let user = match Entity::find()
-        .filter(Column::Username.eq(&form.username))
-        .filter(Column::Active.eq(true))
-        .one(db.get_ref())
-        .await
-    {
-        Ok(Some(res)) => {
-            debug!("User found: {}", res.username);
-            res
-        }
-        Ok(None) => {
+        // 2. Accounts that are not active get their status instead of a token
+        if let Some(refusal) = status_refusal(&user) {
             warn!(
-                "Login failed: user {} not found from IP {}",
-                form.username, client_ip
+                "Login for {} user {} from IP {}",
+                user.status.to_value(),
+                user.username,
+                client_ip
             );
-            return HttpResponse::Unauthorized()
-                .json(json!({"code":401,"message":"invalid credentials"}));
+            return refusal;
         }
-        Err(e) => {
-            error!("Database error: {}", e);
-            return HttpResponse::InternalServerError()
-                .json(json!({"code":500,"message":"Internal server error"}));
-        }
-    };
 
-    // 2. Prepare Data for the Blocking Thread
-    // We must clone the data because we are sending it to a separate thread.
-    // Rust requires 'Owned' data to be moved into the closure, as references cannot safe-cross thread boundaries here.
-    let password_input = form.password.clone();
-    let password_hash_stored = user.password.clone();
-
-    // 3. CPU Intensive Task (Argon2 Verification)
-    // We offload this to `web::block`, which runs on a separate thread pool dedicated to blocking operations.
-    // This prevents the main async worker threads from freezing during the heavy calculation.
-    // Blocking Thread / Sync
-    let verify_result = web::block(move || {
-        // --- Inside Blocking Thread ---
-
-        // 3.1 Parse the stored hash string into a PasswordHash object
-        let parsed_hash = match PasswordHash::new(&password_hash_stored) {
-            Ok(hash) => hash,
-            Err(e) => return Err(format!("Password hash parsing error: {}", e)),
-        };
-
-        // 3.2 Verify the input password against the stored hash
-        match Argon2::default().verify_password(password_input.as_bytes(), &parsed_hash) {
-            Ok(_) => Ok(()),
-            Err(_) => Err("Invalid password".to_string()),
+        // 3. Users with a second factor get a texted code instead of the token
+        if requires_sms(&user) {
+            return sms_challenge(redis.get_ref(), sms, sms_config.get_ref(), &user).await;
         }
-    })
-    .await;
 
-    // 4. Handle the Nested Result (Unwrapping the layers)
-    match verify_result {
-        // Outer Layer (Ok): The task was successfully executed by the thread pool.
-        Ok(inner_result) => match inner_result {
-            // Inner Layer (Ok): Password verification succeeded.
-            Ok(()) => match encode_jwt(form.username.clone()) {
-                Ok(token) => {
-                    info!(
-                        "User {} logged in successfully from IP {}",
-                        form.username, client_ip
-                    );
-                    HttpResponse::Ok()
-                        .json(json!({"code":200,"message":"login successful","token":token}))
-                }
-                Err(e) => {
-                    error!("JWT encoding error for user {}: {}", form.username, e);
-                    HttpResponse::InternalServerError()
-                        .json(json!({"code":500,"message":"Internal server error"}))
-                }
-            },
+        // 4. Signing in during the grace period cancels a deletion the owner asked for
+        let username = user.username.clone();
+        let user = match cancel_deletion(db.get_ref(), user).await {
+            Ok(user) => user,
+            Err(e) => {
+                error!("Failed to restore account {}: {}", username, e);
+                return HttpResponse::InternalServerError()
+                    .json(json!({"code":500,"message":"Internal server error"}));
+            }
+        };
 
-            // Inner Layer (Err): Logic error (Wrong password or Malformed hash).
-            Err(err_msg) => {
-                if err_msg.contains("parsing error") {
-                    error!("{}", err_msg);
-                    HttpResponse::InternalServerError()
-                        .json(json!({"code":500,"message":"Internal server error"}))
-                } else {
-                    warn!(
-                        "Login failed: invalid password for user {} from IP {}",
-                        form.username, client_ip
-                    );
-                    HttpResponse::Unauthorized()
-                        .json(json!({"code":401,"message":"invalid credentials"}))
-                }
+        // 5. Issue the token for the resolved local user
+        return match encode_jwt(user.username.clone()) {
+            Ok(token) => {
+                info!(
+                    "User {} logged in successfully with {} backend from IP {}",
+                    user.username,
+                    backend.name(),
+                    client_ip
+                );
+                HttpResponse::Ok()
+                    .json(json!({"code":200,"message":"login successful","token":token}))
             }
-        },
+            Err(e) => {
+                error!("JWT encoding error for user {}: {}", user.username, e);
+                HttpResponse::InternalServerError()
+                    .json(json!({"code":500,"message":"Internal server error"}))
+            }
+        };
+    }
 
-        // Outer Layer (Err): The thread pool failed to execute the task (e.g., Pool overloaded or Cancelled).
-        Err(e) => {
-            error!("Blocking execution error (Thread pool issue): {}", e);
-            HttpResponse::InternalServerError()
-                .json(json!({"code":500,"message":"Internal server error"}))
-        }
+    // 6. No backend accepted the credentials
+    if unavailable {
+        return HttpResponse::InternalServerError()
+            .json(json!({"code":500,"message":"Internal server error"}));
     }
+    warn!(
+        "Login failed: invalid credentials for user {} from IP {}",
+        form.identifier, client_ip
+    );
+    HttpResponse::Unauthorized().json(json!({"code":401,"message":"invalid credentials"}))
 }
 
 #[post("/register")]
 pub async fn register(
     db: web::Data<DatabaseConnection>,
+    passwordless: web::Data<PasswordlessConfig>,
     form: web::Json<RegisterRequest>,
 ) -> impl Responder {
     debug!("register checkpoint api.");
@@ -160,20 +152,33 @@ pub async fn register(
         return HttpResponse::BadRequest()
             .json(json!({"code":400,"message":"Validation error","errors":e}));
     }
+    // Validation guarantees the number parses; it is stored and compared in E.164.
+    let mut form = form.into_inner();
+    if let Some(phone) = normalize_phone(&form.phone) {
+        form.phone = phone;
+    }
+    form.username = normalize_username(&form.username);
+    form.email = form.email.trim().to_string();
+    let username_key = canonical_username(&form.username);
+    let skeleton = username_skeleton(&form.username);
+    let email_key = canonical_email(&form.email);
+
     // 1. Check for Existing User (Async I/O)
     // Main Thread / Async
     let user_check = Entity::find()
         .filter(
             Condition::any()
-                .add(Column::Username.eq(&form.username))
-                .add(Column::Email.eq(&form.email)),
+                .add(Column::UsernameCanonical.eq(&username_key))
+                .add(Column::UsernameSkeleton.eq(&skeleton))
+                .add(Column::EmailCanonical.eq(&email_key))
+                .add(Column::Phone.eq(&form.phone)),
         )
         .one(db.get_ref())
         .await;
 
     match user_check {
         Ok(Some(res)) => {
-            if res.username == form.username {
+            if res.username_canonical == username_key {
                 warn!(
                     "Registration failed: username {} already exists",
                     form.username
@@ -181,10 +186,24 @@ pub async fn register(
                 return HttpResponse::Conflict()
                     .json(json!({"code":409,"message":"username already exists"}));
             }
-            if res.email == form.email {
+            if res.username_skeleton == skeleton {
+                warn!(
+                    "Registration failed: username {} looks like existing user {}",
+                    form.username, res.username
+                );
+                return HttpResponse::Conflict().json(
+                    json!({"code":409,"message":"username is too similar to an existing one"}),
+                );
+            }
+            if res.email_canonical == email_key {
                 warn!("Registration failed: email {} already exists", form.email);
                 return HttpResponse::Conflict()
                     .json(json!({"code":409,"message":"email already exists"}));
+            }
+            if res.phone == form.phone {
+                warn!("Registration failed: phone {} already exists", form.phone);
+                return HttpResponse::Conflict()
+                    .json(json!({"code":409,"message":"phone already exists"}));
             } else {
                 warn!("Registration failed: user/email already exists");
                 return HttpResponse::Conflict()
@@ -237,14 +256,21 @@ pub async fn register(
     };
 
     // 4. Insert New User (Async I/O)
-    let form_data = form.into_inner();
-    let create_user: ActiveModel = (form_data, password_hash).into();
+    // The account waits for email confirmation when an emailed code or link can provide it.
+    let mut create_user: ActiveModel = (form, password_hash).into();
+    if passwordless.confirms_email() {
+        create_user.status = Set(UserStatus::PendingVerification);
+    }
 
     match create_user.insert(db.get_ref()).await {
         Ok(res) => {
             info!("New user registered with ID: {}", res.id);
-            HttpResponse::Created()
-                .json(json!({"code":201,"message":"User registered successfully","user_id":res.id}))
+            HttpResponse::Created().json(json!({
+                "code":201,
+                "message":"User registered successfully",
+                "user_id":res.id,
+                "status":res.status
+            }))
         }
         Err(db_err) => match db_err.sql_err() {
             Some(SqlErr::UniqueConstraintViolation(msg)) => {
@@ -264,13 +290,6 @@ pub async fn register(
     }
 }
 
-#[get("/profile")]
-pub async fn profile(user: AuthenticatedUser) -> impl Responder {
-    debug!("profile checkpoint api.");
-    HttpResponse::Ok()
-        .json(json!({"code":200,"message":"Profile fetched successfully","username":user.username}))
-}
-
 #[post("/logout")]
 pub async fn logout(req: HttpRequest, redis: web::Data<ConnectionManager>) -> impl Responder {
     debug!("logout checkpoint api.");
@@ -304,19 +323,9 @@ pub async fn logout(req: HttpRequest, redis: web::Data<ConnectionManager>) -> im
         }
     };
 
-    let now = Utc::now().timestamp();
-    let exp = claims.exp as i64;
-    let ttl = exp.saturating_sub(now);
-    if ttl == 0 {
-        return HttpResponse::Ok().json(json!({"code":200,"message":"Token already expired"}));
-    }
-
-    let mut conn = redis.get_ref().clone();
-    let blacklist_key = format!("bl:{}", token);
-    let set_res: Result<(), redis::RedisError> = conn.set_ex(blacklist_key, "1", ttl as u64).await;
-
-    match set_res {
-        Ok(_) => HttpResponse::Ok().json(json!({"code":200,"message":"Logout successful"})),
+    match revoke_jwt(redis.get_ref(), token, claims.exp).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"code":200,"message":"Logout successful"})),
+        Ok(false) => HttpResponse::Ok().json(json!({"code":200,"message":"Token already expired"})),
         Err(e) => {
             error!("Redis error during logout: {}", e);
             HttpResponse::InternalServerError()
@@ -324,3 +333,101 @@ pub async fn logout(req: HttpRequest, redis: web::Data<ConnectionManager>) -> im
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::auth_model::{Model, Role};
+    use actix_web::http::StatusCode;
+    use actix_web::{App, test};
+    use chrono::Utc;
+    use sea_orm::{DatabaseBackend, MockDatabase};
+    use std::sync::Arc;
+
+    fn passwordless(email_otp: bool) -> PasswordlessConfig {
+        PasswordlessConfig {
+            magic_link: false,
+            email_otp,
+            magic_link_url: None,
+            magic_link_ttl: 900,
+            otp_ttl: 600,
+            otp_max_attempts: 5,
+            resend_cooldown: 60,
+            daily_limit: 10,
+            reset_code_ttl: 86400,
+        }
+    }
+
+    fn registered(status: UserStatus) -> Model {
+        let now = Utc::now().into();
+        Model {
+            id: 42,
+            username: "alice".to_string(),
+            username_canonical: "alice".to_string(),
+            username_skeleton: "alice".to_string(),
+            password: Some("hash".to_string()),
+            email: "alice@example.com".to_string(),
+            email_canonical: "alice@example.com".to_string(),
+            email_verified_at: None,
+            phone: "+66800000000".to_string(),
+            phone_verified_at: None,
+            pending_email: None,
+            pending_phone: None,
+            password_reset_required: false,
+            sms_mfa_enabled: false,
+            status,
+            status_reason: None,
+            status_until: None,
+            purge_after: None,
+            role: Role::User,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Register alice and return the response status and the SQL that ran.
+    async fn register_alice(email_otp: bool, stored: UserStatus) -> (StatusCode, String) {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            // No duplicate
+            .append_query_results([Vec::<Model>::new()])
+            .append_query_results([vec![registered(stored)]])
+            .into_connection();
+        let db = web::Data::new(db);
+        let app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .app_data(web::Data::new(passwordless(email_otp)))
+                .service(register),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri("/register")
+            .set_json(json!({
+                "username":"alice",
+                "password":"Secret123",
+                "email":"alice@example.com",
+                "phone":"+66 80 000 0000"
+            }))
+            .to_request();
+        let status = test::call_service(&app, request).await.status();
+        drop(app);
+        let db = Arc::into_inner(db.into_inner()).unwrap();
+        let log = format!("{:?}", db.into_transaction_log()).replace("\\\"", "\"");
+        (status, log)
+    }
+
+    #[actix_web::test]
+    async fn registers_accounts_pending_email_confirmation() {
+        let (status, log) = register_alice(true, UserStatus::PendingVerification).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(log.contains(r#"INSERT INTO "auth_users""#), "{}", log);
+        assert!(log.contains("pending_verification"), "{}", log);
+    }
+
+    #[actix_web::test]
+    async fn registers_active_accounts_when_email_cannot_be_confirmed() {
+        let (status, log) = register_alice(false, UserStatus::Active).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(!log.contains("pending_verification"), "{}", log);
+    }
+}
@@ -0,0 +1,47 @@
+// src/handlers/health_handler.rs
+use crate::utils::shutdown::ShutdownState;
+use actix_web::{HttpResponse, Responder, get, web};
+use redis::aio::ConnectionManager;
+use sea_orm::DatabaseConnection;
+use serde_json::json;
+use tracing::{debug, warn};
+
+//===============================
+// Actix-web Handlers
+//===============================
+#[get("/live")]
+pub async fn live() -> impl Responder {
+    debug!("liveness checkpoint api.");
+    HttpResponse::Ok().json(json!({"code":200,"message":"alive"}))
+}
+
+#[get("/ready")]
+pub async fn ready(
+    shutdown: web::Data<ShutdownState>,
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+) -> impl Responder {
+    debug!("readiness checkpoint api.");
+    // 1. Fail fast once a termination signal arrived so load balancers stop routing here.
+    if shutdown.is_draining() {
+        return HttpResponse::ServiceUnavailable()
+            .json(json!({"code":503,"message":"shutting down"}));
+    }
+
+    // 2. Dependencies must be reachable for the instance to take traffic.
+    if let Err(e) = db.ping().await {
+        warn!("Readiness check failed: database unreachable: {}", e);
+        return HttpResponse::ServiceUnavailable()
+            .json(json!({"code":503,"message":"database unavailable"}));
+    }
+
+    let mut conn = redis.get_ref().clone();
+    let pong: Result<String, redis::RedisError> = redis::cmd("PING").query_async(&mut conn).await;
+    if let Err(e) = pong {
+        warn!("Readiness check failed: redis unreachable: {}", e);
+        return HttpResponse::ServiceUnavailable()
+            .json(json!({"code":503,"message":"redis unavailable"}));
+    }
+
+    HttpResponse::Ok().json(json!({"code":200,"message":"ready"}))
+}
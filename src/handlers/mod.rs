@@ -1 +1,13 @@
+pub mod account_handler;
+pub mod admin_user_handler;
+pub mod api_key_handler;
 pub mod auth_handler;
+pub mod health_handler;
+pub mod oauth_handler;
+pub mod password_handler;
+pub mod passwordless_handler;
+pub mod phone_handler;
+pub mod profile_handler;
+pub mod saml_handler;
+pub mod service_account_handler;
+pub mod sso_handler;
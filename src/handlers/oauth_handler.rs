@@ -0,0 +1,1168 @@
+// src/handlers/oauth_handler.rs
+use crate::models::auth_model::{self, Entity as User};
+use crate::models::oauth_client_model::{
+    self, ActiveModel, AuthorizeRequest, ConsentRequest, DeviceApprovalRequest,
+    DeviceAuthorizationRequest, DeviceLookupQuery, Entity, OAUTH_SCOPES, RegisterClientRequest,
+    TokenActionRequest, TokenRequest,
+};
+use crate::utils::auth_middleware::{AuthenticatedUser, PrincipalKind};
+use crate::utils::crypto::sha256_hex;
+use crate::utils::jwt::{decode_jwt, encode_access_token, encode_service_token};
+use crate::utils::oauth::{
+    AuthorizationGrant, ClientCredentials, DeviceStatus, OAuthConfig, RefreshGrant,
+    authenticate_caller, authenticate_client, authenticate_service_account, client_credentials,
+    device_grant_by_device_code, device_grant_by_user_code, finish_device_grant, format_user_code,
+    generate_client_credentials, issue_refresh_token, oauth_error, peek_refresh_token,
+    record_device_poll, save_device_grant, server_error, start_device_authorization,
+    store_authorization_code, take_authorization_code, take_refresh_token, verify_pkce,
+};
+use crate::utils::oidc::{OidcProvider, user_claims};
+use crate::utils::revocation::{is_jwt_revoked, is_user_token_revoked, revoke_jwt};
+use actix_web::{
+    HttpRequest, HttpResponse, Responder, get,
+    http::{StatusCode, header},
+    post, route, web,
+};
+use chrono::{Duration, Utc};
+use redis::aio::ConnectionManager;
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, QueryOrder, Set};
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+use url::Url;
+use validator::Validate;
+
+//===============================
+// Shared Helpers
+//===============================
+/// How an invalid authorization request is reported. Until the client and redirect URI are
+/// known to be valid, errors must be shown to the user instead of redirecting anywhere.
+enum AuthorizeError {
+    Fatal(HttpResponse),
+    Redirect(&'static str, &'static str),
+}
+
+async fn validate_authorize(
+    db: &DatabaseConnection,
+    oidc: Option<&OidcProvider>,
+    params: &AuthorizeRequest,
+) -> Result<(oauth_client_model::Model, String), AuthorizeError> {
+    // 1. Client and redirect URI (errors are never redirected)
+    let client = match Entity::find()
+        .filter(oauth_client_model::Column::ClientId.eq(&params.client_id))
+        .one(db)
+        .await
+    {
+        Ok(Some(client)) => client,
+        Ok(None) => {
+            return Err(AuthorizeError::Fatal(
+                HttpResponse::BadRequest().json(json!({"code":400,"message":"Unknown client"})),
+            ));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return Err(AuthorizeError::Fatal(
+                HttpResponse::InternalServerError()
+                    .json(json!({"code":500,"message":"Internal server error"})),
+            ));
+        }
+    };
+    if !client.has_redirect_uri(&params.redirect_uri) {
+        warn!(
+            "Authorization request with unregistered redirect URI for client {}",
+            client.client_id
+        );
+        return Err(AuthorizeError::Fatal(HttpResponse::BadRequest().json(
+            json!({"code":400,"message":"Redirect URI not registered for this client"}),
+        )));
+    }
+
+    // 2. Protocol parameters (errors go back to the client)
+    if params.response_type != "code" {
+        return Err(AuthorizeError::Redirect(
+            "unsupported_response_type",
+            "Only the code response type is supported",
+        ));
+    }
+    if params
+        .code_challenge
+        .as_deref()
+        .unwrap_or_default()
+        .is_empty()
+    {
+        return Err(AuthorizeError::Redirect(
+            "invalid_request",
+            "PKCE code_challenge is required",
+        ));
+    }
+    if params.code_challenge_method.as_deref() != Some("S256") {
+        return Err(AuthorizeError::Redirect(
+            "invalid_request",
+            "code_challenge_method must be S256",
+        ));
+    }
+
+    // 3. Scope
+    match requested_scope(&client, params.scope.as_deref(), oidc) {
+        Ok(scope) => Ok((client, scope)),
+        Err(description) => Err(AuthorizeError::Redirect("invalid_scope", description)),
+    }
+}
+
+/// Requested scope, defaulting to everything the client is allowed. On failure returns
+/// the `invalid_scope` description.
+fn requested_scope(
+    client: &oauth_client_model::Model,
+    requested: Option<&str>,
+    oidc: Option<&OidcProvider>,
+) -> Result<String, &'static str> {
+    let scope = match requested.map(str::trim) {
+        Some(scope) if !scope.is_empty() => {
+            if !scope.split_whitespace().all(|s| client.allows_scope(s)) {
+                return Err("Requested scope is not allowed for this client");
+            }
+            scope.to_string()
+        }
+        _ => client.allowed_scopes.clone(),
+    };
+    if oidc.is_none() && scope.split_whitespace().any(|s| s == "openid") {
+        return Err("OpenID Connect is not enabled on this server");
+    }
+    Ok(scope)
+}
+
+fn redirect_with(redirect_uri: &str, params: &[(&str, &str)], state: Option<&str>) -> String {
+    let mut url = Url::parse(redirect_uri).expect("registered redirect URIs are valid");
+    {
+        let mut query = url.query_pairs_mut();
+        for (key, value) in params {
+            query.append_pair(key, value);
+        }
+        if let Some(state) = state {
+            query.append_pair("state", state);
+        }
+    }
+    url.to_string()
+}
+
+async fn find_active_user(
+    db: &DatabaseConnection,
+    username: &str,
+) -> Result<Option<auth_model::Model>, DbErr> {
+    User::find()
+        .filter(auth_model::Column::Username.eq(username))
+        .filter(auth_model::can_sign_in())
+        .one(db)
+        .await
+}
+
+//===============================
+// Client Registration
+//===============================
+#[post("/clients")]
+pub async fn register_client(
+    db: web::Data<DatabaseConnection>,
+    user: AuthenticatedUser,
+    form: web::Json<RegisterClientRequest>,
+) -> impl Responder {
+    debug!("register oauth client checkpoint api.");
+    if let Err(e) = form.validate() {
+        warn!("Validation error during client registration: {:?}", e);
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    if !user.is_full_login() {
+        return HttpResponse::Forbidden().json(
+            json!({"code":403,"message":"Clients can only be registered after a password login"}),
+        );
+    }
+
+    let owner = match User::find()
+        .filter(auth_model::Column::Username.eq(&user.username))
+        .one(db.get_ref())
+        .await
+    {
+        Ok(Some(owner)) => owner,
+        Ok(None) => {
+            return HttpResponse::Unauthorized()
+                .json(json!({"code":401,"message":"User no longer exists"}));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    };
+
+    let (client_id, client_secret) = generate_client_credentials();
+    let form = form.into_inner();
+    let client_secret = form.confidential.then_some(client_secret);
+    let new_client = ActiveModel {
+        owner_id: Set(owner.id),
+        client_id: Set(client_id),
+        client_secret_hash: Set(client_secret.as_deref().map(sha256_hex)),
+        name: Set(form.name),
+        redirect_uris: Set(form.redirect_uris.join(" ")),
+        allowed_scopes: Set(form.scopes.join(" ")),
+        confidential: Set(form.confidential),
+        ..Default::default()
+    };
+
+    match new_client.insert(db.get_ref()).await {
+        Ok(res) => {
+            info!(
+                "User {} registered OAuth client {}",
+                owner.username, res.client_id
+            );
+            HttpResponse::Created().json(json!({
+                "code":201,
+                "message":"Client registered, store the secret now as it will not be shown again",
+                "client":res,
+                "client_secret":client_secret,
+            }))
+        }
+        Err(e) => {
+            error!("Database insertion error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+#[get("/clients")]
+pub async fn list_clients(
+    db: web::Data<DatabaseConnection>,
+    user: AuthenticatedUser,
+) -> impl Responder {
+    debug!("list oauth clients checkpoint api.");
+    match Entity::find()
+        .inner_join(User)
+        .filter(auth_model::Column::Username.eq(&user.username))
+        .order_by_desc(oauth_client_model::Column::CreatedAt)
+        .all(db.get_ref())
+        .await
+    {
+        Ok(clients) => HttpResponse::Ok()
+            .json(json!({"code":200,"message":"Clients fetched successfully","clients":clients})),
+        Err(e) => {
+            error!("Database error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+//===============================
+// Authorization Endpoint
+//===============================
+#[get("/authorize")]
+pub async fn authorize(
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<OAuthConfig>,
+    oidc: Option<web::Data<OidcProvider>>,
+    req: HttpRequest,
+    params: web::Query<AuthorizeRequest>,
+) -> impl Responder {
+    debug!("oauth authorize checkpoint api.");
+    let oidc = oidc.as_ref().map(|oidc| oidc.get_ref());
+    let (client, scope) = match validate_authorize(db.get_ref(), oidc, &params).await {
+        Ok(validated) => validated,
+        Err(AuthorizeError::Fatal(response)) => return response,
+        Err(AuthorizeError::Redirect(error, description)) => {
+            let location = redirect_with(
+                &params.redirect_uri,
+                &[("error", error), ("error_description", description)],
+                params.state.as_deref(),
+            );
+            return HttpResponse::Found()
+                .insert_header(("Location", location))
+                .finish();
+        }
+    };
+
+    // The consent UI holds the user's session; hand the untouched request over to it.
+    if let Some(consent_url) = &config.consent_url {
+        let location = format!("{}?{}", consent_url, req.query_string());
+        return HttpResponse::Found()
+            .insert_header(("Location", location))
+            .finish();
+    }
+
+    HttpResponse::Ok().json(json!({
+        "code":200,
+        "message":"Consent required",
+        "client":{"client_id":client.client_id,"name":client.name},
+        "scope":scope,
+    }))
+}
+
+#[post("/authorize")]
+pub async fn approve(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    oidc: Option<web::Data<OidcProvider>>,
+    user: AuthenticatedUser,
+    form: web::Json<ConsentRequest>,
+) -> impl Responder {
+    debug!("oauth consent checkpoint api.");
+    // Consent must come from the user's own login session, not from a delegated credential.
+    if !user.is_full_login() {
+        return HttpResponse::Forbidden()
+            .json(json!({"code":403,"message":"Consent requires a password login"}));
+    }
+
+    let params = &form.authorize;
+    let state = params.state.as_deref();
+    let (client, scope) = match validate_authorize(
+        db.get_ref(),
+        oidc.as_ref().map(|oidc| oidc.get_ref()),
+        params,
+    )
+    .await
+    {
+        Ok(validated) => validated,
+        Err(AuthorizeError::Fatal(response)) => return response,
+        Err(AuthorizeError::Redirect(error, description)) => {
+            let redirect_to = redirect_with(
+                &params.redirect_uri,
+                &[("error", error), ("error_description", description)],
+                state,
+            );
+            return HttpResponse::Ok().json(
+                json!({"code":200,"message":"Authorization failed","redirect_to":redirect_to}),
+            );
+        }
+    };
+
+    if !form.approved {
+        info!(
+            "User {} denied access to client {}",
+            user.username, client.client_id
+        );
+        let redirect_to = redirect_with(
+            &params.redirect_uri,
+            &[
+                ("error", "access_denied"),
+                ("error_description", "The user denied access"),
+            ],
+            state,
+        );
+        return HttpResponse::Ok()
+            .json(json!({"code":200,"message":"Access denied","redirect_to":redirect_to}));
+    }
+
+    let grant = AuthorizationGrant {
+        client_id: client.client_id.clone(),
+        redirect_uri: params.redirect_uri.clone(),
+        username: user.username.clone(),
+        scope,
+        code_challenge: params.code_challenge.clone().unwrap_or_default(),
+        nonce: params.nonce.clone(),
+        auth_time: user.issued_at.unwrap_or_else(|| Utc::now().timestamp()),
+    };
+    match store_authorization_code(redis.get_ref(), &grant).await {
+        Ok(code) => {
+            info!(
+                "User {} authorized client {}",
+                user.username, client.client_id
+            );
+            let redirect_to = redirect_with(&params.redirect_uri, &[("code", &code)], state);
+            HttpResponse::Ok()
+                .json(json!({"code":200,"message":"Access granted","redirect_to":redirect_to}))
+        }
+        Err(e) => {
+            error!("Redis error storing authorization code: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+//===============================
+// Token Endpoint
+//===============================
+#[post("/token")]
+pub async fn token(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    config: web::Data<OAuthConfig>,
+    oidc: Option<web::Data<OidcProvider>>,
+    req: HttpRequest,
+    form: web::Form<TokenRequest>,
+) -> impl Responder {
+    debug!("oauth token checkpoint api.");
+    let endpoint = TokenEndpoint {
+        db: db.get_ref(),
+        redis: redis.get_ref(),
+        config: &config,
+        oidc: oidc.as_ref().map(|oidc| oidc.get_ref()),
+    };
+    let credentials = client_credentials(
+        &req,
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    );
+    // Service accounts authenticate against their own credentials, not registered clients.
+    if form.grant_type == "client_credentials" {
+        return issue_service_token(&endpoint, credentials, &form).await;
+    }
+    let client = match authenticate_client(db.get_ref(), credentials).await {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    match form.grant_type.as_str() {
+        "authorization_code" => exchange_code(&endpoint, &client, &form).await,
+        "refresh_token" => refresh(&endpoint, &client, &form).await,
+        DEVICE_CODE_GRANT => poll_device(&endpoint, &client, &form).await,
+        _ => oauth_error(
+            StatusCode::BAD_REQUEST,
+            "unsupported_grant_type",
+            "Grant type is not supported",
+        ),
+    }
+}
+
+/// Shared state of one token endpoint request.
+struct TokenEndpoint<'a> {
+    db: &'a DatabaseConnection,
+    redis: &'a ConnectionManager,
+    config: &'a OAuthConfig,
+    oidc: Option<&'a OidcProvider>,
+}
+
+async fn exchange_code(
+    endpoint: &TokenEndpoint<'_>,
+    client: &oauth_client_model::Model,
+    form: &TokenRequest,
+) -> HttpResponse {
+    let invalid_grant =
+        |description| oauth_error(StatusCode::BAD_REQUEST, "invalid_grant", description);
+
+    let (Some(code), Some(redirect_uri), Some(verifier)) =
+        (&form.code, &form.redirect_uri, &form.code_verifier)
+    else {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "code, redirect_uri and code_verifier are required",
+        );
+    };
+
+    // 1. Redeem the code (single use)
+    let grant = match take_authorization_code(endpoint.redis, code).await {
+        Ok(Some(grant)) => grant,
+        Ok(None) => return invalid_grant("Authorization code is invalid or expired"),
+        Err(e) => {
+            error!("Redis error redeeming authorization code: {}", e);
+            return server_error();
+        }
+    };
+
+    // 2. The code must be redeemed by the client and redirect URI it was issued for
+    if grant.client_id != client.client_id || grant.redirect_uri != *redirect_uri {
+        warn!("Authorization code presented by the wrong client or redirect URI");
+        return invalid_grant("Authorization code was not issued to this client");
+    }
+    if !verify_pkce(verifier, &grant.code_challenge) {
+        warn!("PKCE verification failed for client {}", client.client_id);
+        return invalid_grant("PKCE verification failed");
+    }
+
+    let refresh_grant = RefreshGrant {
+        client_id: grant.client_id,
+        username: grant.username,
+        scope: grant.scope,
+        auth_time: grant.auth_time,
+    };
+    issue_tokens(endpoint, client, refresh_grant, grant.nonce).await
+}
+
+async fn refresh(
+    endpoint: &TokenEndpoint<'_>,
+    client: &oauth_client_model::Model,
+    form: &TokenRequest,
+) -> HttpResponse {
+    let Some(refresh_token) = &form.refresh_token else {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "refresh_token is required",
+        );
+    };
+
+    // Refresh tokens rotate: the presented one is consumed and a new one issued.
+    let grant = match take_refresh_token(endpoint.redis, refresh_token).await {
+        Ok(Some(grant)) if grant.client_id == client.client_id => grant,
+        Ok(_) => {
+            return oauth_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "Refresh token is invalid or expired",
+            );
+        }
+        Err(e) => {
+            error!("Redis error redeeming refresh token: {}", e);
+            return server_error();
+        }
+    };
+
+    // Revoking a user's sessions also ends the refresh tokens from before it.
+    match is_user_token_revoked(endpoint.redis, &grant.username, grant.auth_time).await {
+        Ok(false) => (),
+        Ok(true) => {
+            return oauth_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "Refresh token is invalid or expired",
+            );
+        }
+        Err(e) => {
+            error!("Redis error checking session revocation: {}", e);
+            return server_error();
+        }
+    }
+
+    // A refresh may narrow the scope, never widen it.
+    let scope = match form.scope.as_deref().map(str::trim) {
+        Some(requested) if !requested.is_empty() => {
+            let granted: Vec<&str> = grant.scope.split_whitespace().collect();
+            if !requested.split_whitespace().all(|s| granted.contains(&s)) {
+                return oauth_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_scope",
+                    "Requested scope exceeds the original grant",
+                );
+            }
+            requested.to_string()
+        }
+        _ => grant.scope.clone(),
+    };
+
+    // ID tokens issued on refresh carry no nonce (OpenID Connect Core §12.2).
+    let refresh_grant = RefreshGrant { scope, ..grant };
+    issue_tokens(endpoint, client, refresh_grant, None).await
+}
+
+/// Device polling (RFC 8628 §3.4). The device keeps asking until the user answers, and is
+/// told to back off when it polls faster than the current interval.
+async fn poll_device(
+    endpoint: &TokenEndpoint<'_>,
+    client: &oauth_client_model::Model,
+    form: &TokenRequest,
+) -> HttpResponse {
+    let Some(device_code) = &form.device_code else {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "device_code is required",
+        );
+    };
+
+    let stored = match device_grant_by_device_code(endpoint.redis, device_code).await {
+        Ok(Some(stored)) if stored.grant.client_id == client.client_id => stored,
+        Ok(Some(_)) => {
+            return oauth_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "Device code was not issued to this client",
+            );
+        }
+        Ok(None) => {
+            return oauth_error(
+                StatusCode::BAD_REQUEST,
+                "expired_token",
+                "Device code is invalid or expired",
+            );
+        }
+        Err(e) => {
+            error!("Redis error loading device grant: {}", e);
+            return server_error();
+        }
+    };
+
+    // 1. Enforce the polling interval
+    let too_fast = match record_device_poll(endpoint.redis, &stored).await {
+        Ok(too_fast) => too_fast,
+        Err(e) => {
+            error!("Redis error updating device grant: {}", e);
+            return server_error();
+        }
+    };
+    if too_fast {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "slow_down",
+            "Polling too frequently",
+        );
+    }
+
+    // 2. Answer according to the user's decision
+    match stored.grant.status {
+        DeviceStatus::Pending => oauth_error(
+            StatusCode::BAD_REQUEST,
+            "authorization_pending",
+            "The user has not yet approved the request",
+        ),
+        DeviceStatus::Denied => {
+            if let Err(e) = finish_device_grant(endpoint.redis, &stored).await {
+                error!("Redis error removing device grant: {}", e);
+            }
+            oauth_error(
+                StatusCode::BAD_REQUEST,
+                "access_denied",
+                "The user denied the request",
+            )
+        }
+        DeviceStatus::Approved => {
+            match finish_device_grant(endpoint.redis, &stored).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return oauth_error(
+                        StatusCode::BAD_REQUEST,
+                        "expired_token",
+                        "Device code is invalid or expired",
+                    );
+                }
+                Err(e) => {
+                    error!("Redis error removing device grant: {}", e);
+                    return server_error();
+                }
+            }
+            let grant = stored.grant;
+            let refresh_grant = RefreshGrant {
+                client_id: grant.client_id,
+                username: grant.username.unwrap_or_default(),
+                scope: grant.scope,
+                auth_time: grant.auth_time,
+            };
+            issue_tokens(endpoint, client, refresh_grant, None).await
+        }
+    }
+}
+
+/// Client-credentials grant (RFC 6749 §4.4): a scoped access token and no refresh token,
+/// since the service can always authenticate again.
+async fn issue_service_token(
+    endpoint: &TokenEndpoint<'_>,
+    credentials: Option<ClientCredentials>,
+    form: &TokenRequest,
+) -> HttpResponse {
+    let account = match authenticate_service_account(endpoint.db, credentials).await {
+        Ok(account) => account,
+        Err(response) => return response,
+    };
+
+    let scope = match form.scope.as_deref().map(str::trim) {
+        Some(requested) if !requested.is_empty() => {
+            if !requested
+                .split_whitespace()
+                .all(|s| account.allows_scope(s))
+            {
+                return oauth_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_scope",
+                    "Requested scope is not allowed for this service account",
+                );
+            }
+            requested.to_string()
+        }
+        _ => account.allowed_scopes.clone(),
+    };
+
+    let config = endpoint.config;
+    let client_id = account.client_id.clone().unwrap_or_default();
+    match encode_service_token(
+        account.name.clone(),
+        client_id,
+        scope.clone(),
+        Duration::seconds(config.access_token_ttl),
+    ) {
+        Ok(access_token) => {
+            info!(
+                "Issued client-credentials token to service account {}",
+                account.name
+            );
+            HttpResponse::Ok().json(json!({
+                "access_token":access_token,
+                "token_type":"Bearer",
+                "expires_in":config.access_token_ttl,
+                "scope":scope,
+            }))
+        }
+        Err(e) => {
+            error!(
+                "JWT encoding error for service account {}: {}",
+                account.name, e
+            );
+            server_error()
+        }
+    }
+}
+
+async fn issue_tokens(
+    endpoint: &TokenEndpoint<'_>,
+    client: &oauth_client_model::Model,
+    grant: RefreshGrant,
+    nonce: Option<String>,
+) -> HttpResponse {
+    let config = endpoint.config;
+    let username = &grant.username;
+
+    // Deactivated users lose access even with an outstanding code or refresh token.
+    let user = match find_active_user(endpoint.db, username).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return oauth_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "User is no longer active",
+            );
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return server_error();
+        }
+    };
+
+    let ttl = Duration::seconds(config.access_token_ttl);
+    let access_token = match encode_access_token(
+        username.clone(),
+        client.client_id.clone(),
+        grant.scope.clone(),
+        ttl,
+    ) {
+        Ok(access_token) => access_token,
+        Err(e) => {
+            error!("JWT encoding error for user {}: {}", username, e);
+            return server_error();
+        }
+    };
+
+    let mut response = json!({
+        "access_token":access_token,
+        "token_type":"Bearer",
+        "expires_in":config.access_token_ttl,
+        "scope":grant.scope,
+    });
+
+    // OpenID Connect: the `openid` scope adds an ID token about the signed-in user.
+    if let Some(oidc) = endpoint.oidc
+        && grant.scope.split_whitespace().any(|s| s == "openid")
+    {
+        match oidc.encode_id_token(
+            &user,
+            &client.client_id,
+            &grant.scope,
+            grant.auth_time,
+            nonce.as_deref(),
+            ttl,
+        ) {
+            Ok(id_token) => response["id_token"] = json!(id_token),
+            Err(e) => {
+                error!("ID token signing error for user {}: {}", username, e);
+                return server_error();
+            }
+        }
+    }
+
+    match issue_refresh_token(endpoint.redis, &grant, config.refresh_token_ttl).await {
+        Ok(refresh_token) => response["refresh_token"] = json!(refresh_token),
+        Err(e) => {
+            error!("Redis error storing refresh token: {}", e);
+            return server_error();
+        }
+    }
+
+    info!(
+        "Issued tokens for user {} to client {}",
+        username, client.client_id
+    );
+    HttpResponse::Ok().json(response)
+}
+
+//===============================
+// Device Authorization (RFC 8628)
+//===============================
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+#[post("/device_authorization")]
+pub async fn device_authorization(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    config: web::Data<OAuthConfig>,
+    oidc: Option<web::Data<OidcProvider>>,
+    req: HttpRequest,
+    form: web::Form<DeviceAuthorizationRequest>,
+) -> impl Responder {
+    debug!("oauth device authorization checkpoint api.");
+    // The verification URI is shown to users as where to type the code, so it is never taken
+    // from the request's Host header.
+    let Some(verification_uri) = &config.device_verification_url else {
+        return oauth_error(
+            StatusCode::BAD_REQUEST,
+            "unauthorized_client",
+            "The device flow is not enabled on this server",
+        );
+    };
+    let credentials = client_credentials(
+        &req,
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    );
+    let client = match authenticate_client(db.get_ref(), credentials).await {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+    let oidc = oidc.as_ref().map(|oidc| oidc.get_ref());
+    let scope = match requested_scope(&client, form.scope.as_deref(), oidc) {
+        Ok(scope) => scope,
+        Err(description) => {
+            return oauth_error(StatusCode::BAD_REQUEST, "invalid_scope", description);
+        }
+    };
+
+    let (device_code, user_code) = match start_device_authorization(
+        redis.get_ref(),
+        &client.client_id,
+        &scope,
+        config.device_poll_interval,
+        config.device_code_ttl,
+    )
+    .await
+    {
+        Ok(codes) => codes,
+        Err(e) => {
+            error!("Redis error starting device authorization: {}", e);
+            return server_error();
+        }
+    };
+
+    let user_code = format_user_code(&user_code);
+    let mut verification_uri_complete = verification_uri.clone();
+    verification_uri_complete
+        .query_pairs_mut()
+        .append_pair("user_code", &user_code);
+
+    info!(
+        "Device authorization started for client {}",
+        client.client_id
+    );
+    HttpResponse::Ok().json(json!({
+        "device_code":device_code,
+        "user_code":user_code,
+        "verification_uri":verification_uri,
+        "verification_uri_complete":verification_uri_complete.to_string(),
+        "expires_in":config.device_code_ttl,
+        "interval":config.device_poll_interval,
+    }))
+}
+
+/// Show the signed-in user which client and scopes a user code belongs to.
+#[get("/device")]
+pub async fn device_lookup(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    user: AuthenticatedUser,
+    query: web::Query<DeviceLookupQuery>,
+) -> impl Responder {
+    debug!("oauth device lookup checkpoint api.");
+    if !user.is_full_login() {
+        return HttpResponse::Forbidden()
+            .json(json!({"code":403,"message":"Device approval requires a password login"}));
+    }
+
+    let stored = match device_grant_by_user_code(redis.get_ref(), &query.user_code).await {
+        Ok(Some(stored)) if stored.grant.status == DeviceStatus::Pending => stored,
+        Ok(_) => {
+            return HttpResponse::NotFound()
+                .json(json!({"code":404,"message":"Code is invalid or expired"}));
+        }
+        Err(e) => {
+            error!("Redis error loading device grant: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    };
+
+    match Entity::find()
+        .filter(oauth_client_model::Column::ClientId.eq(&stored.grant.client_id))
+        .one(db.get_ref())
+        .await
+    {
+        Ok(Some(client)) => HttpResponse::Ok().json(json!({
+            "code":200,
+            "message":"Approval required",
+            "client":{"client_id":client.client_id,"name":client.name},
+            "scope":stored.grant.scope,
+        })),
+        Ok(None) => HttpResponse::NotFound()
+            .json(json!({"code":404,"message":"Code is invalid or expired"})),
+        Err(e) => {
+            error!("Database error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+#[post("/device")]
+pub async fn device_approve(
+    redis: web::Data<ConnectionManager>,
+    user: AuthenticatedUser,
+    form: web::Json<DeviceApprovalRequest>,
+) -> impl Responder {
+    debug!("oauth device approval checkpoint api.");
+    if !user.is_full_login() {
+        return HttpResponse::Forbidden()
+            .json(json!({"code":403,"message":"Device approval requires a password login"}));
+    }
+
+    let mut stored = match device_grant_by_user_code(redis.get_ref(), &form.user_code).await {
+        Ok(Some(stored)) if stored.grant.status == DeviceStatus::Pending => stored,
+        Ok(_) => {
+            return HttpResponse::NotFound()
+                .json(json!({"code":404,"message":"Code is invalid or expired"}));
+        }
+        Err(e) => {
+            error!("Redis error loading device grant: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    };
+
+    stored.grant.status = if form.approved {
+        DeviceStatus::Approved
+    } else {
+        DeviceStatus::Denied
+    };
+    stored.grant.username = Some(user.username.clone());
+    stored.grant.auth_time = user.issued_at.unwrap_or_else(|| Utc::now().timestamp());
+
+    match save_device_grant(redis.get_ref(), &stored).await {
+        Ok(()) => {
+            let (action, message) = if form.approved {
+                ("approved", "Device approved, you can return to it now")
+            } else {
+                ("denied", "Device access denied")
+            };
+            info!(
+                "User {} {} device access for client {}",
+                user.username, action, stored.grant.client_id
+            );
+            HttpResponse::Ok().json(json!({"code":200,"message":message}))
+        }
+        Err(e) => {
+            error!("Redis error updating device grant: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+//===============================
+// Introspection and Revocation
+//===============================
+/// RFC 7662: lets resource servers ask whether a token is active. Only callers holding
+/// a secret may introspect; every inactive or unknown token gets the same `{"active":false}`.
+#[post("/introspect")]
+pub async fn introspect(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    req: HttpRequest,
+    form: web::Form<TokenActionRequest>,
+) -> impl Responder {
+    debug!("oauth introspect checkpoint api.");
+    let credentials = client_credentials(
+        &req,
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    );
+    let caller = match authenticate_caller(db.get_ref(), credentials).await {
+        Ok(caller) if caller.is_confidential() => caller,
+        Ok(_) | Err(_) => {
+            return oauth_error(
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+                "Client authentication failed",
+            );
+        }
+    };
+    debug!("Token introspection by client {}", caller.client_id());
+
+    // 1. Self-contained JWTs (login, OAuth and client-credentials access tokens)
+    if let Ok(claims) = decode_jwt(&form.token) {
+        let revoked = match is_jwt_revoked(redis.get_ref(), &form.token).await {
+            Ok(false) if claims.kind.unwrap_or(PrincipalKind::User) == PrincipalKind::User => {
+                is_user_token_revoked(redis.get_ref(), &claims.sub, claims.iat as i64).await
+            }
+            other => other,
+        };
+        return match revoked {
+            Ok(true) => HttpResponse::Ok().json(json!({"active":false})),
+            Ok(false) => {
+                let mut body = json!({
+                    "active":true,
+                    "token_type":"Bearer",
+                    "scope":claims.scope,
+                    "sub":claims.sub,
+                    "client_id":claims.client_id,
+                    "aud":claims.aud,
+                    "iat":claims.iat,
+                    "exp":claims.exp,
+                    "act":claims.act,
+                });
+                // Login tokens have no scope, audience or client; omit rather than send nulls.
+                if let Some(fields) = body.as_object_mut() {
+                    fields.retain(|_, value| !value.is_null());
+                }
+                HttpResponse::Ok().json(body)
+            }
+            Err(e) => {
+                error!("Redis error during introspection: {}", e);
+                server_error()
+            }
+        };
+    }
+
+    // 2. Opaque refresh tokens
+    match peek_refresh_token(redis.get_ref(), &form.token).await {
+        Ok(Some((grant, ttl))) => HttpResponse::Ok().json(json!({
+            "active":true,
+            "token_type":"refresh_token",
+            "scope":grant.scope,
+            "sub":grant.username,
+            "client_id":grant.client_id,
+            "exp":Utc::now().timestamp() + ttl,
+        })),
+        Ok(None) => HttpResponse::Ok().json(json!({"active":false})),
+        Err(e) => {
+            error!("Redis error during introspection: {}", e);
+            server_error()
+        }
+    }
+}
+
+/// RFC 7009: a client revokes an access or refresh token issued to it. Unknown and
+/// already-invalid tokens still return `200`, so responses reveal nothing about them.
+#[post("/revoke")]
+pub async fn revoke(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    req: HttpRequest,
+    form: web::Form<TokenActionRequest>,
+) -> impl Responder {
+    debug!("oauth revoke checkpoint api.");
+    let credentials = client_credentials(
+        &req,
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    );
+    let caller = match authenticate_caller(db.get_ref(), credentials).await {
+        Ok(caller) => caller,
+        Err(response) => return response,
+    };
+    let not_issued_to_caller = || {
+        warn!(
+            "Client {} tried to revoke a token issued to another client",
+            caller.client_id()
+        );
+        oauth_error(
+            StatusCode::BAD_REQUEST,
+            "unauthorized_client",
+            "Token was not issued to this client",
+        )
+    };
+
+    if let Ok(claims) = decode_jwt(&form.token) {
+        if claims.client_id.as_deref() != Some(caller.client_id()) {
+            return not_issued_to_caller();
+        }
+        return match revoke_jwt(redis.get_ref(), &form.token, claims.exp).await {
+            Ok(_) => {
+                info!("Client {} revoked an access token", caller.client_id());
+                HttpResponse::Ok().finish()
+            }
+            Err(e) => {
+                error!("Redis error during revocation: {}", e);
+                server_error()
+            }
+        };
+    }
+
+    match peek_refresh_token(redis.get_ref(), &form.token).await {
+        Ok(Some((grant, _))) if grant.client_id != caller.client_id() => not_issued_to_caller(),
+        Ok(Some(_)) => match take_refresh_token(redis.get_ref(), &form.token).await {
+            Ok(_) => {
+                info!("Client {} revoked a refresh token", caller.client_id());
+                HttpResponse::Ok().finish()
+            }
+            Err(e) => {
+                error!("Redis error during revocation: {}", e);
+                server_error()
+            }
+        },
+        Ok(None) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            error!("Redis error during revocation: {}", e);
+            server_error()
+        }
+    }
+}
+
+//===============================
+// OpenID Connect Endpoints
+//===============================
+#[route("/userinfo", method = "GET", method = "POST")]
+pub async fn userinfo(
+    db: web::Data<DatabaseConnection>,
+    user: AuthenticatedUser,
+) -> impl Responder {
+    debug!("oidc userinfo checkpoint api.");
+    // Only access tokens granted the `openid` scope may read identity claims.
+    let Some(scopes) = user
+        .scopes
+        .as_ref()
+        .filter(|s| s.iter().any(|s| s == "openid"))
+    else {
+        return HttpResponse::Forbidden()
+            .insert_header((
+                header::WWW_AUTHENTICATE,
+                r#"Bearer error="insufficient_scope", scope="openid""#,
+            ))
+            .json(json!({"error":"insufficient_scope","error_description":"The openid scope is required"}));
+    };
+
+    match find_active_user(db.get_ref(), &user.username).await {
+        Ok(Some(found)) => HttpResponse::Ok().json(user_claims(&found, &scopes.join(" "))),
+        Ok(None) => HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, r#"Bearer error="invalid_token""#))
+            .json(json!({"error":"invalid_token","error_description":"User is no longer active"})),
+        Err(e) => {
+            error!("Database error: {}", e);
+            server_error()
+        }
+    }
+}
+
+#[get("/openid-configuration")]
+pub async fn openid_configuration(oidc: Option<web::Data<OidcProvider>>) -> impl Responder {
+    debug!("oidc discovery checkpoint api.");
+    match oidc {
+        Some(oidc) => HttpResponse::Ok().json(oidc.discovery_document(OAUTH_SCOPES)),
+        None => oidc_disabled(),
+    }
+}
+
+#[get("/jwks.json")]
+pub async fn jwks(oidc: Option<web::Data<OidcProvider>>) -> impl Responder {
+    debug!("oidc jwks checkpoint api.");
+    match oidc {
+        Some(oidc) => HttpResponse::Ok().json(oidc.jwks()),
+        None => oidc_disabled(),
+    }
+}
+
+fn oidc_disabled() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({"code":404,"message":"OpenID Connect is not enabled"}))
+}
@@ -0,0 +1,160 @@
+// src/handlers/password_handler.rs
+use crate::handlers::passwordless_handler::send_in_background;
+use crate::models::auth_model::{self, Column, Entity, PasswordResetRequest, UserStatus};
+use crate::services::audit_service;
+use crate::services::auth_service::hash_password;
+use crate::utils::client_ip::ClientIp;
+use crate::utils::identifier::canonical_email;
+use crate::utils::mailer::{Email, Mailer};
+use crate::utils::otp::{OtpCheck, issue_code, verify_code};
+use crate::utils::passwordless::PasswordlessConfig;
+use crate::utils::revocation::revoke_user_tokens;
+use actix_web::{HttpResponse, Responder, post, web};
+use redis::aio::ConnectionManager;
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, IntoActiveModel, Set, TransactionTrait};
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+use validator::Validate;
+
+/// `otp` purpose of the code emailed when a reset is forced.
+const PASSWORD_RESET: &str = "password_reset";
+
+fn internal_error() -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({"code":500,"message":"Internal server error"}))
+}
+
+/// Email `user` a fresh reset code, replacing any earlier one.
+pub async fn send_reset_code(
+    redis: &ConnectionManager,
+    mailer: web::Data<Mailer>,
+    config: &PasswordlessConfig,
+    user: &auth_model::Model,
+) -> redis::RedisResult<()> {
+    let code = issue_code(redis, PASSWORD_RESET, user.id, config.reset_code_ttl).await?;
+    send_in_background(
+        mailer,
+        Email {
+            to: user.email.clone(),
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "An administrator asked you to choose a new password before signing in again.\n\n\
+                 Your reset code is {}\n\nIt expires in {} hours.",
+                code,
+                config.reset_code_ttl / 3600
+            ),
+        },
+    );
+    Ok(())
+}
+
+//===============================
+// Actix-web Handlers
+//===============================
+#[post("/password/reset")]
+pub async fn reset_password(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    config: web::Data<PasswordlessConfig>,
+    client_ip: ClientIp,
+    form: web::Json<PasswordResetRequest>,
+) -> impl Responder {
+    debug!("reset password checkpoint api.");
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    let invalid_code = || {
+        HttpResponse::Unauthorized().json(json!({"code":401,"message":"Invalid or expired code"}))
+    };
+
+    // 1. Only accounts with a forced reset have a code to redeem
+    let user = match Entity::find()
+        .filter(Column::EmailCanonical.eq(canonical_email(&form.email)))
+        .filter(Column::PasswordResetRequired.eq(true))
+        .filter(Column::Status.ne(UserStatus::Deleted))
+        .one(db.get_ref())
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return invalid_code(),
+        Err(e) => {
+            error!("Database error: {}", e);
+            return internal_error();
+        }
+    };
+    match verify_code(
+        redis.get_ref(),
+        PASSWORD_RESET,
+        user.id,
+        &form.code,
+        config.otp_max_attempts,
+    )
+    .await
+    {
+        Ok(OtpCheck::Valid) => (),
+        Ok(OtpCheck::Invalid) => {
+            warn!(
+                "Invalid password reset code for user {} from IP {}",
+                user.username, client_ip
+            );
+            return invalid_code();
+        }
+        Ok(OtpCheck::TooManyAttempts) => {
+            warn!(
+                "Too many password reset attempts for user {} from IP {}",
+                user.username, client_ip
+            );
+            return HttpResponse::TooManyRequests().json(
+                json!({"code":429,"message":"Too many attempts; ask an administrator for a new code"}),
+            );
+        }
+        Err(e) => {
+            error!("Redis error verifying password reset code: {}", e);
+            return internal_error();
+        }
+    }
+
+    // 2. Store the new password and record the reset
+    let password_hash = match hash_password(form.new_password.clone()).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            error!("{}", e);
+            return internal_error();
+        }
+    };
+    let username = user.username.clone();
+    let result: Result<(), DbErr> = async {
+        let txn = db.begin().await?;
+        audit_service::record(
+            &txn,
+            &user,
+            "user.password_reset",
+            Some(user.id),
+            json!({}),
+            client_ip,
+        )
+        .await?;
+        let mut active = user.into_active_model();
+        active.password = Set(Some(password_hash));
+        active.password_reset_required = Set(false);
+        active.update(&txn).await?;
+        txn.commit().await
+    }
+    .await;
+    if let Err(e) = result {
+        error!("Database error: {}", e);
+        return internal_error();
+    }
+
+    // 3. Sessions opened with the old password end
+    if let Err(e) = revoke_user_tokens(redis.get_ref(), &username).await {
+        error!("Redis error revoking sessions of {}: {}", username, e);
+    }
+    info!(
+        "User {} reset their password from IP {}",
+        username, client_ip
+    );
+    HttpResponse::Ok()
+        .json(json!({"code":200,"message":"Password changed; sign in with the new password"}))
+}
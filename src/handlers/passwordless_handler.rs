@@ -0,0 +1,501 @@
+// src/handlers/passwordless_handler.rs
+use crate::handlers::phone_handler::{requires_sms, sms_challenge};
+use crate::models::auth_model::{
+    self, Column, EmailOtpVerifyRequest, Entity, MagicLinkVerifyRequest, PasswordlessRequest,
+    UserStatus,
+};
+use crate::services::account_service::{cancel_deletion, change_status, status_refusal};
+use crate::utils::client_ip::ClientIp;
+use crate::utils::identifier::canonical_email;
+use crate::utils::jwt::encode_jwt;
+use crate::utils::mailer::{Email, Mailer};
+use crate::utils::otp::{OtpCheck, issue_code, start_cooldown, verify_code, within_daily_limit};
+use crate::utils::passwordless::{PasswordlessConfig, issue_magic_link, redeem_magic_link};
+use crate::utils::sms::{SmsConfig, SmsSender};
+use actix_web::{HttpResponse, Responder, post, rt, web};
+use chrono::Utc;
+use redis::aio::ConnectionManager;
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, IntoActiveModel, Set};
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+use url::Url;
+use validator::Validate;
+
+/// `otp` purpose of emailed sign-in codes.
+const EMAIL_LOGIN: &str = "email_login";
+/// Daily budget shared by sign-in links and codes.
+const EMAIL_CHANNEL: &str = "email";
+
+fn method_disabled() -> HttpResponse {
+    HttpResponse::NotFound()
+        .json(json!({"code":404,"message":"This sign-in method is not enabled"}))
+}
+
+/// Same answer whether or not the email belongs to an account, so the endpoint cannot be
+/// used to discover registered addresses.
+fn email_sent() -> HttpResponse {
+    HttpResponse::Accepted().json(json!({
+        "code":202,
+        "message":"If an account exists for this email, a message has been sent"
+    }))
+}
+
+fn internal_error() -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({"code":500,"message":"Internal server error"}))
+}
+
+/// Accounts awaiting email confirmation are included: an emailed code or link confirms it.
+async fn user_by_email(
+    db: &DatabaseConnection,
+    email: &str,
+) -> Result<Option<auth_model::Model>, DbErr> {
+    Entity::find()
+        .filter(Column::EmailCanonical.eq(canonical_email(email)))
+        .filter(Column::Status.ne(UserStatus::Deleted))
+        .one(db)
+        .await
+}
+
+/// Whether another `purpose` email may go to `user`: one per resend cooldown, and at most the
+/// daily limit across links and codes. Refused requests get the same answer as sent ones.
+async fn may_email(
+    redis: &ConnectionManager,
+    config: &PasswordlessConfig,
+    user: &auth_model::Model,
+    purpose: &str,
+) -> redis::RedisResult<bool> {
+    if !start_cooldown(redis, purpose, user.id, config.resend_cooldown).await? {
+        return Ok(false);
+    }
+    if !within_daily_limit(redis, EMAIL_CHANNEL, user.id, config.daily_limit).await? {
+        warn!(
+            "Daily sign-in email limit reached for user {}",
+            user.username
+        );
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Deliver in the background: the response must not wait on (or reveal) the mail relay.
+pub fn send_in_background(mailer: web::Data<Mailer>, email: Email) {
+    rt::spawn(async move {
+        let to = email.to.clone();
+        if let Err(e) = mailer.send(email).await {
+            error!("Failed to send email to {}: {}", to, e);
+        }
+    });
+}
+
+/// Issue the login JWT, or the SMS challenge for users with a second factor. Receiving the
+/// email proves control of the address, so it is marked verified as well and an account
+/// pending verification becomes active.
+async fn sign_in(
+    db: &DatabaseConnection,
+    redis: &ConnectionManager,
+    sms: web::Data<SmsSender>,
+    sms_config: &SmsConfig,
+    user: auth_model::Model,
+    method: &str,
+    client_ip: ClientIp,
+) -> HttpResponse {
+    let username = user.username.clone();
+    let mut user = user;
+    if user.email_verified_at.is_none() {
+        let mut active = user.clone().into_active_model();
+        active.email_verified_at = Set(Some(Utc::now().into()));
+        match active.update(db).await {
+            Ok(updated) => user = updated,
+            Err(e) => error!("Failed to mark email verified for {}: {}", username, e),
+        }
+    }
+    if user.status == UserStatus::PendingVerification {
+        user = match change_status(db, user, UserStatus::Active, None, None).await {
+            Ok(user) => {
+                info!("User {} confirmed their email; account active", username);
+                user
+            }
+            Err(e) => {
+                error!("Failed to activate {}: {}", username, e);
+                return internal_error();
+            }
+        };
+    }
+    if let Some(refusal) = status_refusal(&user) {
+        warn!(
+            "Sign-in with {} for {} user {} from IP {}",
+            method,
+            user.status.to_value(),
+            username,
+            client_ip
+        );
+        return refusal;
+    }
+
+    if requires_sms(&user) {
+        return sms_challenge(redis, sms, sms_config, &user).await;
+    }
+    if let Err(e) = cancel_deletion(db, user).await {
+        error!("Failed to restore account {}: {}", username, e);
+        return internal_error();
+    }
+
+    match encode_jwt(username.clone()) {
+        Ok(token) => {
+            info!(
+                "User {} logged in with {} from IP {}",
+                username, method, client_ip
+            );
+            HttpResponse::Ok().json(json!({"code":200,"message":"login successful","token":token}))
+        }
+        Err(e) => {
+            error!("JWT encoding error for user {}: {}", username, e);
+            internal_error()
+        }
+    }
+}
+
+//===============================
+// Actix-web Handlers
+//===============================
+#[post("/magic-link")]
+pub async fn request_magic_link(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    config: web::Data<PasswordlessConfig>,
+    mailer: web::Data<Mailer>,
+    form: web::Json<PasswordlessRequest>,
+) -> impl Responder {
+    debug!("request magic link checkpoint api.");
+    if !config.magic_link {
+        return method_disabled();
+    }
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+
+    // 1. Unknown addresses get the same answer, without an email
+    let user = match user_by_email(db.get_ref(), &form.email).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            debug!("Magic link requested for unknown email {}", form.email);
+            return email_sent();
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return internal_error();
+        }
+    };
+
+    // 2. Within the email budget, a fresh single-use token
+    match may_email(redis.get_ref(), &config, &user, "magic_link").await {
+        Ok(true) => (),
+        Ok(false) => return email_sent(),
+        Err(e) => {
+            error!("Redis error: {}", e);
+            return internal_error();
+        }
+    }
+    let token = match issue_magic_link(redis.get_ref(), user.id, config.magic_link_ttl).await {
+        Ok(token) => token,
+        Err(e) => {
+            error!("Redis error storing magic link: {}", e);
+            return internal_error();
+        }
+    };
+    let base = config.magic_link_url.as_deref().unwrap_or_default();
+    let mut link = match Url::parse(base) {
+        Ok(link) => link,
+        Err(e) => {
+            error!("Invalid MAGIC_LINK_URL {}: {}", base, e);
+            return internal_error();
+        }
+    };
+    link.query_pairs_mut().append_pair("token", &token);
+
+    send_in_background(
+        mailer,
+        Email {
+            to: user.email,
+            subject: "Your sign-in link".to_string(),
+            body: format!(
+                "Open this link to sign in:\n\n{}\n\nIt expires in {} minutes and works once. \
+                 If you did not ask for it, ignore this email.",
+                link,
+                config.magic_link_ttl / 60
+            ),
+        },
+    );
+    email_sent()
+}
+
+#[post("/magic-link/verify")]
+pub async fn verify_magic_link(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    config: web::Data<PasswordlessConfig>,
+    sms: web::Data<SmsSender>,
+    sms_config: web::Data<SmsConfig>,
+    client_ip: ClientIp,
+    form: web::Json<MagicLinkVerifyRequest>,
+) -> impl Responder {
+    debug!("verify magic link checkpoint api.");
+    if !config.magic_link {
+        return method_disabled();
+    }
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+
+    let user_id = match redeem_magic_link(redis.get_ref(), &form.token).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => {
+            warn!("Invalid or used magic link from IP {}", client_ip);
+            return HttpResponse::Unauthorized()
+                .json(json!({"code":401,"message":"Invalid or expired sign-in link"}));
+        }
+        Err(e) => {
+            error!("Redis error redeeming magic link: {}", e);
+            return internal_error();
+        }
+    };
+    match Entity::find_by_id(user_id)
+        .filter(Column::Status.ne(UserStatus::Deleted))
+        .one(db.get_ref())
+        .await
+    {
+        Ok(Some(user)) => {
+            sign_in(
+                db.get_ref(),
+                redis.get_ref(),
+                sms,
+                sms_config.get_ref(),
+                user,
+                "a magic link",
+                client_ip,
+            )
+            .await
+        }
+        Ok(None) => HttpResponse::Unauthorized()
+            .json(json!({"code":401,"message":"Invalid or expired sign-in link"})),
+        Err(e) => {
+            error!("Database error: {}", e);
+            internal_error()
+        }
+    }
+}
+
+#[post("/email-otp")]
+pub async fn request_email_otp(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    config: web::Data<PasswordlessConfig>,
+    mailer: web::Data<Mailer>,
+    form: web::Json<PasswordlessRequest>,
+) -> impl Responder {
+    debug!("request email otp checkpoint api.");
+    if !config.email_otp {
+        return method_disabled();
+    }
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+
+    let user = match user_by_email(db.get_ref(), &form.email).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            debug!("Email code requested for unknown email {}", form.email);
+            return email_sent();
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return internal_error();
+        }
+    };
+    // The cooldown also bounds how often the attempt counter can be reset with a new code.
+    match may_email(redis.get_ref(), &config, &user, EMAIL_LOGIN).await {
+        Ok(true) => (),
+        Ok(false) => return email_sent(),
+        Err(e) => {
+            error!("Redis error: {}", e);
+            return internal_error();
+        }
+    }
+    let code = match issue_code(redis.get_ref(), EMAIL_LOGIN, user.id, config.otp_ttl).await {
+        Ok(code) => code,
+        Err(e) => {
+            error!("Redis error storing email code: {}", e);
+            return internal_error();
+        }
+    };
+
+    send_in_background(
+        mailer,
+        Email {
+            to: user.email,
+            subject: "Your sign-in code".to_string(),
+            body: format!(
+                "Your sign-in code is {}\n\nIt expires in {} minutes. \
+                 If you did not ask for it, ignore this email.",
+                code,
+                config.otp_ttl / 60
+            ),
+        },
+    );
+    email_sent()
+}
+
+#[post("/email-otp/verify")]
+pub async fn verify_email_code(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    config: web::Data<PasswordlessConfig>,
+    sms: web::Data<SmsSender>,
+    sms_config: web::Data<SmsConfig>,
+    client_ip: ClientIp,
+    form: web::Json<EmailOtpVerifyRequest>,
+) -> impl Responder {
+    debug!("verify email otp checkpoint api.");
+    if !config.email_otp {
+        return method_disabled();
+    }
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    let invalid_code = || {
+        HttpResponse::Unauthorized().json(json!({"code":401,"message":"Invalid or expired code"}))
+    };
+
+    let user = match user_by_email(db.get_ref(), &form.email).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return invalid_code(),
+        Err(e) => {
+            error!("Database error: {}", e);
+            return internal_error();
+        }
+    };
+    match verify_code(
+        redis.get_ref(),
+        EMAIL_LOGIN,
+        user.id,
+        &form.code,
+        config.otp_max_attempts,
+    )
+    .await
+    {
+        Ok(OtpCheck::Valid) => {
+            sign_in(
+                db.get_ref(),
+                redis.get_ref(),
+                sms,
+                sms_config.get_ref(),
+                user,
+                "an email code",
+                client_ip,
+            )
+            .await
+        }
+        Ok(OtpCheck::Invalid) => {
+            warn!(
+                "Invalid email code for user {} from IP {}",
+                user.username, client_ip
+            );
+            invalid_code()
+        }
+        Ok(OtpCheck::TooManyAttempts) => {
+            warn!(
+                "Too many email code attempts for user {} from IP {}",
+                user.username, client_ip
+            );
+            HttpResponse::TooManyRequests()
+                .json(json!({"code":429,"message":"Too many attempts; request a new code"}))
+        }
+        Err(e) => {
+            error!("Redis error verifying email code: {}", e);
+            internal_error()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::auth_model::Role;
+    use crate::utils::saml::tests::redis_stand_in;
+
+    fn config(daily_limit: u64) -> PasswordlessConfig {
+        PasswordlessConfig {
+            magic_link: true,
+            email_otp: true,
+            magic_link_url: Some("https://app.example.com/magic-link".to_string()),
+            magic_link_ttl: 900,
+            otp_ttl: 600,
+            otp_max_attempts: 5,
+            resend_cooldown: 60,
+            daily_limit,
+            reset_code_ttl: 86400,
+        }
+    }
+
+    fn user() -> auth_model::Model {
+        let now = Utc::now().into();
+        auth_model::Model {
+            id: 42,
+            username: "jane".to_string(),
+            username_canonical: "jane".to_string(),
+            username_skeleton: "jane".to_string(),
+            password: None,
+            email: "jane@example.com".to_string(),
+            email_canonical: "jane@example.com".to_string(),
+            email_verified_at: Some(now),
+            phone: String::new(),
+            phone_verified_at: None,
+            pending_email: None,
+            pending_phone: None,
+            password_reset_required: false,
+            sms_mfa_enabled: false,
+            status: UserStatus::Active,
+            status_reason: None,
+            status_until: None,
+            purge_after: None,
+            role: Role::User,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[actix_web::test]
+    async fn links_and_codes_share_one_daily_email_budget() {
+        let redis = redis_stand_in().await;
+        let config = config(2);
+        let user = user();
+
+        assert!(
+            may_email(&redis, &config, &user, "magic_link")
+                .await
+                .unwrap()
+        );
+        // Within the resend cooldown
+        assert!(
+            !may_email(&redis, &config, &user, "magic_link")
+                .await
+                .unwrap()
+        );
+        assert!(
+            may_email(&redis, &config, &user, EMAIL_LOGIN)
+                .await
+                .unwrap()
+        );
+        // The third email of the day, whatever its kind
+        assert!(
+            !may_email(&redis, &config, &user, "password_reset")
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,409 @@
+// src/handlers/phone_handler.rs
+use crate::models::auth_model::{
+    self, CodeRequest, Column, Entity as User, SmsLoginRequest, SmsMfaRequest,
+};
+use crate::services::account_service::{cancel_deletion, status_refusal};
+use crate::utils::auth_middleware::AuthenticatedUser;
+use crate::utils::client_ip::ClientIp;
+use crate::utils::jwt::encode_jwt;
+use crate::utils::otp::{
+    OtpCheck, challenge_user, end_challenge, issue_code, start_challenge, start_cooldown,
+    verify_code, within_daily_limit,
+};
+use crate::utils::sms::{Sms, SmsConfig, SmsSender};
+use actix_web::{HttpResponse, Responder, post, rt, web};
+use chrono::Utc;
+use redis::aio::ConnectionManager;
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, IntoActiveModel, Set};
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+use validator::Validate;
+
+/// `otp` purposes of texted codes.
+const PHONE_VERIFY: &str = "phone_verify";
+const SMS_LOGIN: &str = "sms_login";
+/// Daily budget shared by every texted code.
+const SMS_CHANNEL: &str = "sms";
+
+pub enum SendOutcome {
+    Sent,
+    CoolingDown,
+    DailyLimit,
+}
+
+fn internal_error() -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({"code":500,"message":"Internal server error"}))
+}
+
+// The phone number can only be managed from a full login session, like API keys.
+async fn phone_owner(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+) -> Result<auth_model::Model, HttpResponse> {
+    if !user.is_full_login() {
+        return Err(HttpResponse::Forbidden().json(
+            json!({"code":403,"message":"The phone number can only be managed after a login"}),
+        ));
+    }
+
+    match User::find()
+        .filter(Column::Username.eq(&user.username))
+        .one(db)
+        .await
+    {
+        Ok(Some(owner)) => Ok(owner),
+        Ok(None) => Err(HttpResponse::Unauthorized()
+            .json(json!({"code":401,"message":"User no longer exists"}))),
+        Err(e) => {
+            error!("Database error: {}", e);
+            Err(internal_error())
+        }
+    }
+}
+
+/// Text a fresh `purpose` code for `user` to `to`, within the resend cooldown and the daily
+/// budget. Delivery runs in the background so the response never waits on the provider.
+pub async fn text_code(
+    redis: &ConnectionManager,
+    sender: web::Data<SmsSender>,
+    config: &SmsConfig,
+    user: &auth_model::Model,
+    to: &str,
+    purpose: &str,
+) -> redis::RedisResult<SendOutcome> {
+    if !start_cooldown(redis, purpose, user.id, config.resend_cooldown).await? {
+        return Ok(SendOutcome::CoolingDown);
+    }
+    if !within_daily_limit(redis, SMS_CHANNEL, user.id, config.daily_limit).await? {
+        warn!(
+            "Daily text message limit reached for user {}",
+            user.username
+        );
+        return Ok(SendOutcome::DailyLimit);
+    }
+    let code = issue_code(redis, purpose, user.id, config.otp_ttl).await?;
+
+    let sms = Sms {
+        to: to.to_string(),
+        body: match purpose {
+            SMS_LOGIN => format!("Your sign-in code is {}", code),
+            _ => format!("Your verification code is {}", code),
+        },
+    };
+    rt::spawn(async move {
+        let to = sms.to.clone();
+        if let Err(e) = sender.send(sms).await {
+            error!("Failed to send text message to {}: {}", to, e);
+        }
+    });
+    Ok(SendOutcome::Sent)
+}
+
+/// Whether signing in as `user` takes a texted code after the first factor.
+pub fn requires_sms(user: &auth_model::Model) -> bool {
+    user.sms_mfa_enabled && user.phone_verified_at.is_some()
+}
+
+/// Second step of a login: text a sign-in code and answer with the `mfa_token` that
+/// `/login/sms` exchanges, together with the code, for the login JWT.
+pub async fn sms_challenge(
+    redis: &ConnectionManager,
+    sender: web::Data<SmsSender>,
+    config: &SmsConfig,
+    user: &auth_model::Model,
+) -> HttpResponse {
+    // While cooling down, the code sent moments ago is still the one to enter.
+    match text_code(redis, sender, config, user, &user.phone, SMS_LOGIN).await {
+        Ok(SendOutcome::Sent | SendOutcome::CoolingDown) => (),
+        Ok(SendOutcome::DailyLimit) => {
+            return HttpResponse::TooManyRequests()
+                .json(json!({"code":429,"message":"Too many codes sent; try again later"}));
+        }
+        Err(e) => {
+            error!("Redis error sending sign-in code: {}", e);
+            return internal_error();
+        }
+    }
+    match start_challenge(redis, user.id, config.otp_ttl).await {
+        Ok(mfa_token) => {
+            info!(
+                "User {} passed the first factor; SMS code sent",
+                user.username
+            );
+            HttpResponse::Accepted().json(json!({
+                "code":202,
+                "message":"SMS code required",
+                "mfa":"sms",
+                "mfa_token":mfa_token
+            }))
+        }
+        Err(e) => {
+            error!("Redis error storing MFA challenge: {}", e);
+            internal_error()
+        }
+    }
+}
+
+//===============================
+// Actix-web Handlers
+//===============================
+#[post("/phone/send-code")]
+pub async fn send_phone_code(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    sender: web::Data<SmsSender>,
+    config: web::Data<SmsConfig>,
+    user: AuthenticatedUser,
+) -> impl Responder {
+    debug!("send phone code checkpoint api.");
+    let owner = match phone_owner(db.get_ref(), &user).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+    if owner.phone.is_empty() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"No phone number on the account"}));
+    }
+    if owner.phone_verified_at.is_some() {
+        return HttpResponse::Ok()
+            .json(json!({"code":200,"message":"Phone number already verified"}));
+    }
+
+    match text_code(
+        redis.get_ref(),
+        sender,
+        config.get_ref(),
+        &owner,
+        &owner.phone,
+        PHONE_VERIFY,
+    )
+    .await
+    {
+        Ok(SendOutcome::Sent) => {
+            info!("Phone verification code sent to user {}", owner.username);
+            HttpResponse::Accepted().json(json!({"code":202,"message":"Verification code sent"}))
+        }
+        Ok(SendOutcome::CoolingDown) => HttpResponse::TooManyRequests()
+            .json(json!({"code":429,"message":"Please wait before requesting another code"})),
+        Ok(SendOutcome::DailyLimit) => HttpResponse::TooManyRequests()
+            .json(json!({"code":429,"message":"Too many codes sent; try again later"})),
+        Err(e) => {
+            error!("Redis error sending verification code: {}", e);
+            internal_error()
+        }
+    }
+}
+
+#[post("/phone/verify")]
+pub async fn verify_phone(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    config: web::Data<SmsConfig>,
+    user: AuthenticatedUser,
+    form: web::Json<CodeRequest>,
+) -> impl Responder {
+    debug!("verify phone checkpoint api.");
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    let owner = match phone_owner(db.get_ref(), &user).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    match verify_code(
+        redis.get_ref(),
+        PHONE_VERIFY,
+        owner.id,
+        &form.code,
+        config.otp_max_attempts,
+    )
+    .await
+    {
+        Ok(OtpCheck::Valid) => (),
+        Ok(OtpCheck::Invalid) => {
+            warn!(
+                "Invalid phone verification code for user {}",
+                owner.username
+            );
+            return HttpResponse::BadRequest()
+                .json(json!({"code":400,"message":"Invalid or expired code"}));
+        }
+        Ok(OtpCheck::TooManyAttempts) => {
+            warn!(
+                "Too many phone verification attempts for user {}",
+                owner.username
+            );
+            return HttpResponse::TooManyRequests()
+                .json(json!({"code":429,"message":"Too many attempts; request a new code"}));
+        }
+        Err(e) => {
+            error!("Redis error verifying phone code: {}", e);
+            return internal_error();
+        }
+    }
+
+    let username = owner.username.clone();
+    let mut active = owner.into_active_model();
+    active.phone_verified_at = Set(Some(Utc::now().into()));
+    match active.update(db.get_ref()).await {
+        Ok(_) => {
+            info!("User {} verified their phone number", username);
+            HttpResponse::Ok().json(json!({"code":200,"message":"Phone number verified"}))
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            internal_error()
+        }
+    }
+}
+
+#[post("/mfa/sms")]
+pub async fn set_sms_mfa(
+    db: web::Data<DatabaseConnection>,
+    user: AuthenticatedUser,
+    form: web::Json<SmsMfaRequest>,
+) -> impl Responder {
+    debug!("set sms mfa checkpoint api.");
+    let owner = match phone_owner(db.get_ref(), &user).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+    if form.enabled && owner.phone_verified_at.is_none() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Verify your phone number first"}));
+    }
+
+    let username = owner.username.clone();
+    let mut active = owner.into_active_model();
+    active.sms_mfa_enabled = Set(form.enabled);
+    match active.update(db.get_ref()).await {
+        Ok(_) => {
+            info!(
+                "User {} turned the SMS second factor {}",
+                username,
+                if form.enabled { "on" } else { "off" }
+            );
+            HttpResponse::Ok().json(
+                json!({"code":200,"message":"SMS second factor updated","enabled":form.enabled}),
+            )
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            internal_error()
+        }
+    }
+}
+
+#[post("/login/sms")]
+pub async fn login_sms(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    config: web::Data<SmsConfig>,
+    client_ip: ClientIp,
+    form: web::Json<SmsLoginRequest>,
+) -> impl Responder {
+    debug!("login sms checkpoint api.");
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    let expired = || {
+        HttpResponse::Unauthorized()
+            .json(json!({"code":401,"message":"Invalid or expired MFA token; sign in again"}))
+    };
+
+    // 1. Resolve the pending challenge to its user
+    let user_id = match challenge_user(redis.get_ref(), &form.mfa_token).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return expired(),
+        Err(e) => {
+            error!("Redis error reading MFA challenge: {}", e);
+            return internal_error();
+        }
+    };
+    let user = match User::find_by_id(user_id).one(db.get_ref()).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return expired(),
+        Err(e) => {
+            error!("Database error: {}", e);
+            return internal_error();
+        }
+    };
+
+    // 2. Check the texted code; the challenge ends with the code
+    match verify_code(
+        redis.get_ref(),
+        SMS_LOGIN,
+        user.id,
+        &form.code,
+        config.otp_max_attempts,
+    )
+    .await
+    {
+        Ok(OtpCheck::Valid) => (),
+        Ok(OtpCheck::Invalid) => {
+            warn!(
+                "Invalid SMS sign-in code for user {} from IP {}",
+                user.username, client_ip
+            );
+            return HttpResponse::Unauthorized()
+                .json(json!({"code":401,"message":"Invalid or expired code"}));
+        }
+        Ok(OtpCheck::TooManyAttempts) => {
+            warn!(
+                "Too many SMS sign-in attempts for user {} from IP {}",
+                user.username, client_ip
+            );
+            if let Err(e) = end_challenge(redis.get_ref(), &form.mfa_token).await {
+                error!("Redis error ending MFA challenge: {}", e);
+            }
+            return HttpResponse::TooManyRequests()
+                .json(json!({"code":429,"message":"Too many attempts; sign in again"}));
+        }
+        Err(e) => {
+            error!("Redis error verifying SMS code: {}", e);
+            return internal_error();
+        }
+    }
+    if let Err(e) = end_challenge(redis.get_ref(), &form.mfa_token).await {
+        error!("Redis error ending MFA challenge: {}", e);
+    }
+
+    // 3. The account may have been suspended or closed since the first factor
+    if let Some(refusal) = status_refusal(&user) {
+        warn!(
+            "SMS sign-in for {} user {} from IP {}",
+            user.status.to_value(),
+            user.username,
+            client_ip
+        );
+        return refusal;
+    }
+
+    // 4. Signing in during the grace period cancels a deletion the owner asked for
+    let user = match cancel_deletion(db.get_ref(), user).await {
+        Ok(user) => user,
+        Err(e) => {
+            error!("Failed to restore account {}: {}", user_id, e);
+            return internal_error();
+        }
+    };
+
+    // 5. Issue the login token
+    match encode_jwt(user.username.clone()) {
+        Ok(token) => {
+            info!(
+                "User {} completed SMS sign-in from IP {}",
+                user.username, client_ip
+            );
+            HttpResponse::Ok().json(json!({"code":200,"message":"login successful","token":token}))
+        }
+        Err(e) => {
+            error!("JWT encoding error for user {}: {}", user.username, e);
+            internal_error()
+        }
+    }
+}
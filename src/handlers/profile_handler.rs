@@ -0,0 +1,520 @@
+// src/handlers/profile_handler.rs
+use crate::handlers::passwordless_handler::send_in_background;
+use crate::handlers::phone_handler::{SendOutcome, text_code};
+use crate::models::audit_model;
+use crate::models::auth_model::{self, CodeRequest, Column, Entity as User, UpdateProfileRequest};
+use crate::utils::auth_middleware::{AuthenticatedUser, PrincipalKind};
+use crate::utils::crypto::sha256_hex;
+use crate::utils::identifier::canonical_email;
+use crate::utils::impersonation::ImpersonationConfig;
+use crate::utils::mailer::{Email, Mailer};
+use crate::utils::otp::{OtpCheck, issue_code, start_cooldown, verify_code};
+use crate::utils::passwordless::PasswordlessConfig;
+use crate::utils::phone::normalize_phone;
+use crate::utils::sms::{Sms, SmsConfig, SmsSender};
+use actix_web::{HttpResponse, Responder, get, patch, post, rt, web};
+use chrono::{SubsecRound, Utc};
+use redis::aio::ConnectionManager;
+use sea_orm::entity::prelude::*;
+use sea_orm::error::SqlErr;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, IntoActiveModel, QueryOrder, Set};
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+use validator::Validate;
+
+/// `otp` purposes of the codes confirming a contact change.
+const EMAIL_CHANGE: &str = "email_change";
+const PHONE_CHANGE: &str = "phone_change";
+
+fn internal_error() -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({"code":500,"message":"Internal server error"}))
+}
+
+fn modified() -> HttpResponse {
+    HttpResponse::Conflict()
+        .json(json!({"code":409,"message":"Profile was modified; reload and retry"}))
+}
+
+/// Codes are bound to the address they were sent to: a code for an earlier pending value
+/// cannot confirm a later one.
+fn change_purpose(kind: &str, value: &str) -> String {
+    format!("{}:{}", kind, &sha256_hex(value)[..16])
+}
+
+async fn profile_owner(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+    scope: &str,
+) -> Result<auth_model::Model, HttpResponse> {
+    if user.kind != PrincipalKind::User {
+        return Err(HttpResponse::Forbidden()
+            .json(json!({"code":403,"message":"Service accounts have no profile"})));
+    }
+    if !user.has_scope(scope) {
+        return Err(HttpResponse::Forbidden()
+            .json(json!({"code":403,"message":format!("Missing scope {}", scope)})));
+    }
+    // Contact details receive reset codes and sign-in links, so support staff only look.
+    if user.impersonator.is_some() && scope != "profile:read" {
+        return Err(HttpResponse::Forbidden()
+            .json(json!({"code":403,"message":"Not available while impersonating"})));
+    }
+
+    match User::find()
+        .filter(Column::Username.eq(&user.username))
+        .one(db)
+        .await
+    {
+        Ok(Some(owner)) => Ok(owner),
+        Ok(None) => Err(HttpResponse::Unauthorized()
+            .json(json!({"code":401,"message":"User no longer exists"}))),
+        Err(e) => {
+            error!("Database error: {}", e);
+            Err(internal_error())
+        }
+    }
+}
+
+async fn email_taken(db: &DatabaseConnection, email: &str, owner: i32) -> Result<bool, DbErr> {
+    User::find()
+        .filter(Column::EmailCanonical.eq(canonical_email(email)))
+        .filter(Column::Id.ne(owner))
+        .count(db)
+        .await
+        .map(|n| n > 0)
+}
+
+async fn phone_taken(db: &DatabaseConnection, phone: &str, owner: i32) -> Result<bool, DbErr> {
+    User::find()
+        .filter(Column::Phone.eq(phone))
+        .filter(Column::Id.ne(owner))
+        .count(db)
+        .await
+        .map(|n| n > 0)
+}
+
+//===============================
+// Actix-web Handlers
+//===============================
+#[get("/profile")]
+pub async fn profile(db: web::Data<DatabaseConnection>, user: AuthenticatedUser) -> impl Responder {
+    debug!("profile checkpoint api.");
+    if user.kind == PrincipalKind::Service {
+        if !user.has_scope("profile:read") {
+            return HttpResponse::Forbidden()
+                .json(json!({"code":403,"message":"Missing scope profile:read"}));
+        }
+        return HttpResponse::Ok().json(
+            json!({"code":200,"message":"Profile fetched successfully","username":user.username,"kind":user.kind}),
+        );
+    }
+
+    match profile_owner(db.get_ref(), &user, "profile:read").await {
+        Ok(owner) => HttpResponse::Ok().json(json!({
+            "code":200,
+            "message":"Profile fetched successfully",
+            "username":user.username,
+            "kind":user.kind,
+            "profile":owner
+        })),
+        Err(response) => response,
+    }
+}
+
+/// Support staff who signed in as this user, when `IMPERSONATION_VISIBLE_TO_USER` is enabled.
+#[get("/profile/impersonations")]
+pub async fn list_impersonations(
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<ImpersonationConfig>,
+    user: AuthenticatedUser,
+) -> impl Responder {
+    debug!("list impersonations checkpoint api.");
+    if !config.visible_to_user {
+        return HttpResponse::NotFound().json(json!({"code":404,"message":"Not found"}));
+    }
+    let owner = match profile_owner(db.get_ref(), &user, "profile:read").await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    match audit_model::Entity::find()
+        .filter(audit_model::Column::TargetUserId.eq(owner.id))
+        .filter(audit_model::Column::Action.eq("user.impersonate"))
+        .order_by_desc(audit_model::Column::Id)
+        .all(db.get_ref())
+        .await
+    {
+        Ok(events) => {
+            let impersonations: Vec<_> = events
+                .into_iter()
+                .map(|event| {
+                    json!({
+                        "admin":event.actor,
+                        "reason":event.details.get("reason"),
+                        "started_at":event.created_at,
+                        "expires_at":event.details.get("expires_at")
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({
+                "code":200,
+                "message":"Impersonations fetched successfully",
+                "impersonations":impersonations
+            }))
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            internal_error()
+        }
+    }
+}
+
+/// Email and phone changes are staged in `pending_*` until confirmed with the code sent to the
+/// new address; the old address is told about the request. The username is the JWT subject and
+/// cannot be changed.
+#[patch("/profile")]
+#[allow(clippy::too_many_arguments)]
+pub async fn update_profile(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    mailer: web::Data<Mailer>,
+    passwordless: web::Data<PasswordlessConfig>,
+    sms: web::Data<SmsSender>,
+    sms_config: web::Data<SmsConfig>,
+    user: AuthenticatedUser,
+    form: web::Json<UpdateProfileRequest>,
+) -> impl Responder {
+    debug!("update profile checkpoint api.");
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    let owner = match profile_owner(db.get_ref(), &user, "profile:write").await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+    if owner.updated_at != form.updated_at {
+        return modified();
+    }
+
+    // 1. Keep only the fields that actually change
+    let email = form
+        .email
+        .as_deref()
+        .map(str::trim)
+        .filter(|email| canonical_email(email) != owner.email_canonical)
+        .map(str::to_string);
+    let phone = form
+        .phone
+        .as_deref()
+        .and_then(normalize_phone)
+        .filter(|phone| *phone != owner.phone);
+    if email.is_none() && phone.is_none() {
+        return HttpResponse::Ok()
+            .json(json!({"code":200,"message":"Profile unchanged","profile":owner}));
+    }
+
+    // 2. Addresses already used by another account are refused before anything is sent
+    if let Some(email) = &email {
+        match email_taken(db.get_ref(), email, owner.id).await {
+            Ok(false) => (),
+            Ok(true) => {
+                return HttpResponse::Conflict()
+                    .json(json!({"code":409,"message":"email already exists"}));
+            }
+            Err(e) => {
+                error!("Database error: {}", e);
+                return internal_error();
+            }
+        }
+    }
+    if let Some(phone) = &phone {
+        match phone_taken(db.get_ref(), phone, owner.id).await {
+            Ok(false) => (),
+            Ok(true) => {
+                return HttpResponse::Conflict()
+                    .json(json!({"code":409,"message":"phone already exists"}));
+            }
+            Err(e) => {
+                error!("Database error: {}", e);
+                return internal_error();
+            }
+        }
+    }
+
+    // 3. Stage the change, only if nobody else updated the row since it was read
+    // Postgres keeps microseconds; the value returned must match the stored one.
+    let now: DateTimeWithTimeZone = Utc::now().trunc_subsecs(6).into();
+    let mut update = User::update_many()
+        .col_expr(Column::UpdatedAt, Expr::value(now))
+        .filter(Column::Id.eq(owner.id))
+        .filter(Column::UpdatedAt.eq(form.updated_at));
+    if let Some(email) = &email {
+        update = update.col_expr(Column::PendingEmail, Expr::value(email.clone()));
+    }
+    if let Some(phone) = &phone {
+        update = update.col_expr(Column::PendingPhone, Expr::value(phone.clone()));
+    }
+    match update.exec(db.get_ref()).await {
+        Ok(result) if result.rows_affected == 1 => (),
+        Ok(_) => return modified(),
+        Err(e) => {
+            error!("Database error: {}", e);
+            return internal_error();
+        }
+    }
+
+    // 4. Send the confirmation codes and warn the current addresses
+    if let Some(email) = &email {
+        let purpose = change_purpose(EMAIL_CHANGE, &canonical_email(email));
+        // While cooling down, the code sent moments ago for this address is still valid.
+        match start_cooldown(
+            redis.get_ref(),
+            &purpose,
+            owner.id,
+            passwordless.resend_cooldown,
+        )
+        .await
+        {
+            Ok(true) => {
+                let code =
+                    match issue_code(redis.get_ref(), &purpose, owner.id, passwordless.otp_ttl)
+                        .await
+                    {
+                        Ok(code) => code,
+                        Err(e) => {
+                            error!("Redis error storing email change code: {}", e);
+                            return internal_error();
+                        }
+                    };
+                send_in_background(
+                    mailer.clone(),
+                    Email {
+                        to: email.clone(),
+                        subject: "Confirm your new email address".to_string(),
+                        body: format!(
+                            "Your confirmation code is {}\n\nIt expires in {} minutes. \
+                             If you did not ask for it, ignore this email.",
+                            code,
+                            passwordless.otp_ttl / 60
+                        ),
+                    },
+                );
+                send_in_background(
+                    mailer,
+                    Email {
+                        to: owner.email.clone(),
+                        subject: "Email change requested".to_string(),
+                        body: format!(
+                            "A change of your account email to {} was requested. It takes effect \
+                             once confirmed from the new address. If this was not you, change \
+                             your password.",
+                            email
+                        ),
+                    },
+                );
+            }
+            Ok(false) => (),
+            Err(e) => {
+                error!("Redis error: {}", e);
+                return internal_error();
+            }
+        }
+    }
+    if let Some(phone) = &phone {
+        let purpose = change_purpose(PHONE_CHANGE, phone);
+        match text_code(
+            redis.get_ref(),
+            sms.clone(),
+            sms_config.get_ref(),
+            &owner,
+            phone,
+            &purpose,
+        )
+        .await
+        {
+            Ok(SendOutcome::Sent) => {
+                if !owner.phone.is_empty() {
+                    let notice = Sms {
+                        to: owner.phone.clone(),
+                        body: "A change of your account phone number was requested. If this \
+                               was not you, change your password."
+                            .to_string(),
+                    };
+                    rt::spawn(async move {
+                        let to = notice.to.clone();
+                        if let Err(e) = sms.send(notice).await {
+                            error!("Failed to send text message to {}: {}", to, e);
+                        }
+                    });
+                }
+            }
+            Ok(SendOutcome::CoolingDown) => (),
+            Ok(SendOutcome::DailyLimit) => {
+                return HttpResponse::TooManyRequests().json(json!({
+                    "code":429,
+                    "message":"Too many codes sent; try again later",
+                    "updated_at":now
+                }));
+            }
+            Err(e) => {
+                error!("Redis error sending phone change code: {}", e);
+                return internal_error();
+            }
+        }
+    }
+
+    info!(
+        "User {} requested a contact change (email: {}, phone: {})",
+        owner.username,
+        email.is_some(),
+        phone.is_some()
+    );
+    HttpResponse::Accepted().json(json!({
+        "code":202,
+        "message":"Confirm the change with the code sent to the new address",
+        "pending_email":email,
+        "pending_phone":phone,
+        "updated_at":now
+    }))
+}
+
+#[post("/profile/email/confirm")]
+pub async fn confirm_email_change(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    config: web::Data<PasswordlessConfig>,
+    user: AuthenticatedUser,
+    form: web::Json<CodeRequest>,
+) -> impl Responder {
+    debug!("confirm email change checkpoint api.");
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    let owner = match profile_owner(db.get_ref(), &user, "profile:write").await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+    let Some(pending) = owner.pending_email.clone() else {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"No email change pending"}));
+    };
+
+    match verify_code(
+        redis.get_ref(),
+        &change_purpose(EMAIL_CHANGE, &canonical_email(&pending)),
+        owner.id,
+        &form.code,
+        config.otp_max_attempts,
+    )
+    .await
+    {
+        Ok(OtpCheck::Valid) => (),
+        Ok(OtpCheck::Invalid) => {
+            warn!("Invalid email change code for user {}", owner.username);
+            return HttpResponse::BadRequest()
+                .json(json!({"code":400,"message":"Invalid or expired code"}));
+        }
+        Ok(OtpCheck::TooManyAttempts) => {
+            warn!("Too many email change attempts for user {}", owner.username);
+            return HttpResponse::TooManyRequests()
+                .json(json!({"code":429,"message":"Too many attempts; request a new code"}));
+        }
+        Err(e) => {
+            error!("Redis error verifying email change code: {}", e);
+            return internal_error();
+        }
+    }
+
+    let username = owner.username.clone();
+    let mut active = owner.into_active_model();
+    active.email = Set(pending.clone());
+    active.email_verified_at = Set(Some(Utc::now().into()));
+    active.pending_email = Set(None);
+    match active.update(db.get_ref()).await {
+        Ok(_) => {
+            info!("User {} changed their email to {}", username, pending);
+            HttpResponse::Ok()
+                .json(json!({"code":200,"message":"Email address changed","email":pending}))
+        }
+        // Taken by another account since the change was requested.
+        Err(e) if matches!(e.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) => {
+            HttpResponse::Conflict().json(json!({"code":409,"message":"email already exists"}))
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            internal_error()
+        }
+    }
+}
+
+#[post("/profile/phone/confirm")]
+pub async fn confirm_phone_change(
+    db: web::Data<DatabaseConnection>,
+    redis: web::Data<ConnectionManager>,
+    config: web::Data<SmsConfig>,
+    user: AuthenticatedUser,
+    form: web::Json<CodeRequest>,
+) -> impl Responder {
+    debug!("confirm phone change checkpoint api.");
+    if let Err(e) = form.validate() {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+    let owner = match profile_owner(db.get_ref(), &user, "profile:write").await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+    let Some(pending) = owner.pending_phone.clone() else {
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"No phone change pending"}));
+    };
+
+    match verify_code(
+        redis.get_ref(),
+        &change_purpose(PHONE_CHANGE, &pending),
+        owner.id,
+        &form.code,
+        config.otp_max_attempts,
+    )
+    .await
+    {
+        Ok(OtpCheck::Valid) => (),
+        Ok(OtpCheck::Invalid) => {
+            warn!("Invalid phone change code for user {}", owner.username);
+            return HttpResponse::BadRequest()
+                .json(json!({"code":400,"message":"Invalid or expired code"}));
+        }
+        Ok(OtpCheck::TooManyAttempts) => {
+            warn!("Too many phone change attempts for user {}", owner.username);
+            return HttpResponse::TooManyRequests()
+                .json(json!({"code":429,"message":"Too many attempts; request a new code"}));
+        }
+        Err(e) => {
+            error!("Redis error verifying phone change code: {}", e);
+            return internal_error();
+        }
+    }
+
+    let username = owner.username.clone();
+    let mut active = owner.into_active_model();
+    active.phone = Set(pending.clone());
+    active.phone_verified_at = Set(Some(Utc::now().into()));
+    active.pending_phone = Set(None);
+    match active.update(db.get_ref()).await {
+        Ok(_) => {
+            info!("User {} changed their phone number", username);
+            HttpResponse::Ok()
+                .json(json!({"code":200,"message":"Phone number changed","phone":pending}))
+        }
+        Err(e) if matches!(e.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) => {
+            HttpResponse::Conflict().json(json!({"code":409,"message":"phone already exists"}))
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            internal_error()
+        }
+    }
+}
@@ -0,0 +1,202 @@
+// src/handlers/saml_handler.rs
+use crate::models::identity_model::SamlAcsForm;
+use crate::services::identity_service::{ExternalLogin, find_identity, sign_in_external};
+use crate::utils::client_ip::ClientIp;
+use crate::utils::saml::{
+    BINDING_COOKIE, SamlConfig, mark_assertion_used, start_saml_request, take_saml_request,
+};
+use crate::utils::sso::{Provisioning, binding_secret};
+use actix_web::{HttpRequest, HttpResponse, Responder, get, http::header, post, web};
+use redis::aio::ConnectionManager;
+use sea_orm::DatabaseConnection;
+use serde_json::json;
+use tracing::{debug, error, warn};
+
+fn unknown_tenant() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({"code":404,"message":"Unknown SAML tenant"}))
+}
+
+//===============================
+// Actix-web Handlers
+//===============================
+/// SP metadata for the tenant's IdP administrator.
+#[get("/{tenant}/metadata")]
+pub async fn saml_metadata(
+    config: web::Data<SamlConfig>,
+    path: web::Path<String>,
+) -> impl Responder {
+    debug!("saml metadata checkpoint api.");
+    let Some(tenant) = config.tenant(&path) else {
+        return unknown_tenant();
+    };
+    HttpResponse::Ok()
+        .content_type("application/samlmetadata+xml")
+        .body(tenant.metadata())
+}
+
+#[get("/{tenant}/login")]
+pub async fn saml_login(
+    config: web::Data<SamlConfig>,
+    redis: web::Data<ConnectionManager>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    debug!("saml login checkpoint api.");
+    let Some(tenant) = config.tenant(&path) else {
+        return unknown_tenant();
+    };
+    let secret = binding_secret(&req, BINDING_COOKIE);
+    match start_saml_request(redis.get_ref(), &tenant.name, &secret).await {
+        Ok(request_id) => HttpResponse::Found()
+            .insert_header((header::LOCATION, tenant.authn_request_url(&request_id)))
+            .cookie(tenant.binding_cookie(&secret))
+            .finish(),
+        Err(e) => {
+            error!("Redis error storing SAML request: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+/// Assertion Consumer Service (HTTP-POST binding).
+#[post("/{tenant}/acs")]
+pub async fn saml_acs(
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<SamlConfig>,
+    redis: web::Data<ConnectionManager>,
+    client_ip: ClientIp,
+    path: web::Path<String>,
+    form: web::Form<SamlAcsForm>,
+    req: HttpRequest,
+) -> impl Responder {
+    debug!("saml acs checkpoint api.");
+    let Some(tenant) = config.tenant(&path) else {
+        return unknown_tenant();
+    };
+    let rejected = || {
+        HttpResponse::Unauthorized().json(json!({"code":401,"message":"SAML response rejected"}))
+    };
+
+    // 1. Signature, issuer, audience, recipient and validity window
+    let assertion = match tenant.validate_response(&form.saml_response) {
+        Ok(assertion) => assertion,
+        Err(e) => {
+            warn!(
+                "SAML response for tenant {} rejected from IP {}: {}",
+                tenant.name, client_ip, e
+            );
+            return rejected();
+        }
+    };
+
+    // 2. It must answer a request we made to this browser, and each assertion is accepted once
+    let Some(cookie) = req.cookie(BINDING_COOKIE) else {
+        warn!(
+            "SAML response for tenant {} without a binding cookie from IP {}",
+            tenant.name, client_ip
+        );
+        return rejected();
+    };
+    match take_saml_request(redis.get_ref(), &assertion.in_response_to).await {
+        Ok(Some(request))
+            if request.tenant == tenant.name && request.is_bound_to(cookie.value()) => {}
+        Ok(_) => {
+            warn!(
+                "SAML response for tenant {} answers unknown request {} from IP {}",
+                tenant.name, assertion.in_response_to, client_ip
+            );
+            return rejected();
+        }
+        Err(e) => {
+            error!("Redis error loading SAML request: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    }
+    match mark_assertion_used(redis.get_ref(), &tenant.name, &assertion).await {
+        Ok(true) => (),
+        Ok(false) => {
+            warn!(
+                "Replayed SAML assertion {} for tenant {} from IP {}",
+                assertion.assertion_id, tenant.name, client_ip
+            );
+            return rejected();
+        }
+        Err(e) => {
+            error!("Redis error recording SAML assertion: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    }
+
+    // 3. Sign in the linked (or newly provisioned) user
+    let provider = tenant.provider_name();
+    let linked = match find_identity(db.get_ref(), &provider, &assertion.identity.subject).await {
+        Ok(linked) => linked,
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    };
+    let login = ExternalLogin {
+        provider: &provider,
+        identity: assertion.identity,
+        may_provision: tenant.provisioning == Provisioning::Create,
+    };
+    sign_in_external(db.get_ref(), login, linked, client_ip).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::auth_route::configure_routes;
+    use crate::utils::crypto::random_hex;
+    use crate::utils::saml::BINDING_COOKIE;
+    use crate::utils::saml::tests::{
+        encoded_response, idp_key, outstanding_request, redis_stand_in, saml_config,
+    };
+    use actix_web::cookie::Cookie;
+    use actix_web::http::StatusCode;
+    use actix_web::{App, test, web};
+    use sea_orm::{DatabaseBackend, MockDatabase};
+
+    #[actix_web::test]
+    async fn acs_only_accepts_responses_in_the_browser_that_signed_in() {
+        let redis = redis_stand_in().await;
+        let idp = idp_key();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(
+                    MockDatabase::new(DatabaseBackend::Postgres).into_connection(),
+                ))
+                .app_data(web::Data::new(redis.clone()))
+                .app_data(web::Data::new(saml_config(&idp)))
+                .configure(configure_routes),
+        )
+        .await;
+        let secret = random_hex(32);
+        let post = |cookie: Option<&str>| {
+            let mut request = test::TestRequest::post()
+                .uri("/api/v1/auth/saml/corp/acs")
+                .set_form([("SAMLResponse", encoded_response(&idp, "_a1"))]);
+            if let Some(value) = cookie {
+                request = request.cookie(Cookie::new(BINDING_COOKIE, value.to_string()));
+            }
+            request.to_request()
+        };
+        outstanding_request(&redis, &secret).await;
+
+        // A response lured into another browser is refused.
+        let response = test::call_service(&app, post(None)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&app, post(Some(&random_hex(32)))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // The browser that started the sign-in gets through to the account lookup, which the
+        // empty stand-in database fails.
+        outstanding_request(&redis, &secret).await;
+        let response = test::call_service(&app, post(Some(&secret))).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
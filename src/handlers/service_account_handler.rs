@@ -0,0 +1,159 @@
+// src/handlers/service_account_handler.rs
+use crate::models::service_account_model::{
+    ActiveModel, Column, CreateServiceAccountRequest, Entity,
+};
+use crate::utils::auth_middleware::AdminUser;
+use crate::utils::crypto::sha256_hex;
+use crate::utils::oauth::generate_client_credentials;
+use actix_web::{HttpResponse, Responder, get, post, web};
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, IntoActiveModel, QueryOrder, Set};
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+use validator::Validate;
+
+//===============================
+// Actix-web Handlers
+//===============================
+#[post("/service-accounts")]
+pub async fn create_service_account(
+    db: web::Data<DatabaseConnection>,
+    admin: AdminUser,
+    form: web::Json<CreateServiceAccountRequest>,
+) -> impl Responder {
+    debug!("create service account checkpoint api.");
+    if let Err(e) = form.validate() {
+        warn!("Validation error during service account creation: {:?}", e);
+        return HttpResponse::BadRequest()
+            .json(json!({"code":400,"message":"Validation error","errors":e}));
+    }
+
+    // 1. Names and certificate subjects are unique identities
+    let form = form.into_inner();
+    let mut taken = Column::Name.eq(&form.name);
+    if let Some(subject) = &form.certificate_subject {
+        taken = taken.or(Column::CertificateSubject.eq(subject));
+    }
+    match Entity::find().filter(taken).one(db.get_ref()).await {
+        Ok(Some(_)) => {
+            return HttpResponse::Conflict().json(
+                json!({"code":409,"message":"Service account name or certificate subject already exists"}),
+            );
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    }
+
+    // 2. Create with fresh credentials; only the secret's hash is stored
+    let (client_id, client_secret) = generate_client_credentials();
+    let new_account = ActiveModel {
+        name: Set(form.name),
+        certificate_subject: Set(form.certificate_subject),
+        client_id: Set(Some(client_id)),
+        client_secret_hash: Set(Some(sha256_hex(&client_secret))),
+        allowed_scopes: Set(form.scopes.join(" ")),
+        active: Set(true),
+        ..Default::default()
+    };
+
+    match new_account.insert(db.get_ref()).await {
+        Ok(res) => {
+            info!(
+                "Admin {} created service account {}",
+                admin.0.username, res.name
+            );
+            HttpResponse::Created().json(json!({
+                "code":201,
+                "message":"Service account created, store the secret now as it will not be shown again",
+                "service_account":res,
+                "client_secret":client_secret,
+            }))
+        }
+        Err(e) => {
+            error!("Database insertion error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+#[get("/service-accounts")]
+pub async fn list_service_accounts(
+    db: web::Data<DatabaseConnection>,
+    _admin: AdminUser,
+) -> impl Responder {
+    debug!("list service accounts checkpoint api.");
+    match Entity::find()
+        .order_by_asc(Column::Name)
+        .all(db.get_ref())
+        .await
+    {
+        Ok(accounts) => HttpResponse::Ok().json(json!({
+            "code":200,
+            "message":"Service accounts fetched successfully",
+            "service_accounts":accounts,
+        })),
+        Err(e) => {
+            error!("Database error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+/// Replace the client secret. The old secret stops working immediately; tokens already
+/// issued stay valid until they expire.
+#[post("/service-accounts/{id}/rotate-secret")]
+pub async fn rotate_service_account_secret(
+    db: web::Data<DatabaseConnection>,
+    admin: AdminUser,
+    path: web::Path<i32>,
+) -> impl Responder {
+    debug!("rotate service account secret checkpoint api.");
+    let account = match Entity::find_by_id(path.into_inner())
+        .one(db.get_ref())
+        .await
+    {
+        Ok(Some(account)) => account,
+        Ok(None) => {
+            return HttpResponse::NotFound()
+                .json(json!({"code":404,"message":"Service account not found"}));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    };
+
+    // Certificate-only accounts get their first client ID here.
+    let (client_id, client_secret) = generate_client_credentials();
+    let client_id = account.client_id.clone().unwrap_or(client_id);
+    let mut rotated = account.into_active_model();
+    rotated.client_id = Set(Some(client_id));
+    rotated.client_secret_hash = Set(Some(sha256_hex(&client_secret)));
+
+    match rotated.update(db.get_ref()).await {
+        Ok(res) => {
+            info!(
+                "Admin {} rotated the secret of service account {}",
+                admin.0.username, res.name
+            );
+            HttpResponse::Ok().json(json!({
+                "code":200,
+                "message":"Secret rotated, store it now as it will not be shown again",
+                "service_account":res,
+                "client_secret":client_secret,
+            }))
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
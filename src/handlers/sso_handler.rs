@@ -0,0 +1,427 @@
+// src/handlers/sso_handler.rs
+use crate::models::auth_model::{self, Entity as User};
+use crate::models::identity_model::{
+    ActiveModel, Column, Entity, Model as Identity, SsoCallbackQuery,
+};
+use crate::services::identity_service::{
+    ExternalIdentity, ExternalLogin, find_identity, sign_in_external,
+};
+use crate::utils::auth_middleware::AuthenticatedUser;
+use crate::utils::client_ip::ClientIp;
+use crate::utils::sso::{
+    BINDING_COOKIE, SsoConfig, SsoProvider, SsoState, binding_secret, store_sso_state,
+    take_sso_state,
+};
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, get, http::header, post, web};
+use redis::aio::ConnectionManager;
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, PaginatorTrait, QueryOrder, Set};
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+
+// Linking changes how an account can be signed in to, so it needs an interactive session.
+async fn session_user(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+) -> Result<auth_model::Model, HttpResponse> {
+    if !user.is_full_login() {
+        return Err(HttpResponse::Forbidden().json(
+            json!({"code":403,"message":"Identities can only be managed after an interactive login"}),
+        ));
+    }
+
+    match User::find()
+        .filter(auth_model::Column::Username.eq(&user.username))
+        .one(db)
+        .await
+    {
+        Ok(Some(owner)) => Ok(owner),
+        Ok(None) => Err(HttpResponse::Unauthorized()
+            .json(json!({"code":401,"message":"User no longer exists"}))),
+        Err(e) => {
+            error!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"})))
+        }
+    }
+}
+
+fn unknown_provider() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({"code":404,"message":"Unknown identity provider"}))
+}
+
+async fn start(
+    config: &SsoConfig,
+    redis: &ConnectionManager,
+    provider: &SsoProvider,
+    link_user_id: Option<i32>,
+    binding_secret: &str,
+) -> Result<String, HttpResponse> {
+    let state = SsoState::new(&provider.name, link_user_id, binding_secret);
+    let state_token = match store_sso_state(redis, &state).await {
+        Ok(token) => token,
+        Err(e) => {
+            error!("Redis error storing SSO state: {}", e);
+            return Err(HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"})));
+        }
+    };
+    let redirect_uri = config.callback_url(&provider.name);
+    provider
+        .authorization_url(&redirect_uri, &state, &state_token)
+        .await
+        .map_err(|e| {
+            error!("Identity provider {} unavailable: {}", provider.name, e);
+            HttpResponse::BadGateway()
+                .json(json!({"code":502,"message":"Identity provider unavailable"}))
+        })
+}
+
+//===============================
+// Actix-web Handlers
+//===============================
+#[get("/sso/{provider}/login")]
+pub async fn sso_login(
+    config: web::Data<SsoConfig>,
+    redis: web::Data<ConnectionManager>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    debug!("sso login checkpoint api.");
+    let Some(provider) = config.provider(&path) else {
+        return unknown_provider();
+    };
+    let secret = binding_secret(&req, BINDING_COOKIE);
+    match start(&config, redis.get_ref(), provider, None, &secret).await {
+        Ok(url) => HttpResponse::Found()
+            .insert_header((header::LOCATION, url))
+            .cookie(config.binding_cookie(&secret))
+            .finish(),
+        Err(response) => response,
+    }
+}
+
+/// Start linking an external account to the signed-in user. Returns the URL to open in the
+/// browser, since a redirect cannot carry the `Authorization` header; the request must come
+/// from that browser so it receives the binding cookie.
+#[post("/sso/{provider}/link")]
+pub async fn sso_link(
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<SsoConfig>,
+    redis: web::Data<ConnectionManager>,
+    user: AuthenticatedUser,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    debug!("sso link checkpoint api.");
+    let owner = match session_user(db.get_ref(), &user).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+    let Some(provider) = config.provider(&path) else {
+        return unknown_provider();
+    };
+    let secret = binding_secret(&req, BINDING_COOKIE);
+    match start(&config, redis.get_ref(), provider, Some(owner.id), &secret).await {
+        Ok(url) => HttpResponse::Ok()
+            .cookie(config.binding_cookie(&secret))
+            .json(json!({
+                "code":200,
+                "message":"Open authorization_url to link the account",
+                "authorization_url":url,
+            })),
+        Err(response) => response,
+    }
+}
+
+#[get("/sso/{provider}/callback")]
+pub async fn sso_callback(
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<SsoConfig>,
+    redis: web::Data<ConnectionManager>,
+    client_ip: ClientIp,
+    path: web::Path<String>,
+    query: web::Query<SsoCallbackQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    debug!("sso callback checkpoint api.");
+    let Some(provider) = config.provider(&path) else {
+        return unknown_provider();
+    };
+
+    // 1. The state must be ours, unused, issued for this provider and to this browser
+    let state = match query.state.as_deref() {
+        Some(token) => take_sso_state(redis.get_ref(), token).await,
+        None => Ok(None),
+    };
+    let cookie = req.cookie(BINDING_COOKIE);
+    let state = match state {
+        Ok(Some(state))
+            if state.provider == provider.name
+                && state.is_bound_to(cookie.as_ref().map(|c| c.value())) =>
+        {
+            state
+        }
+        Ok(_) => {
+            warn!(
+                "SSO callback for {} with invalid state from IP {}",
+                provider.name, client_ip
+            );
+            return HttpResponse::BadRequest()
+                .json(json!({"code":400,"message":"Invalid or expired sign-in attempt"}));
+        }
+        Err(e) => {
+            error!("Redis error loading SSO state: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    };
+
+    // 2. Exchange the code and verify who signed in
+    let code = match (&query.code, &query.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            warn!(
+                "SSO sign-in with {} failed at the provider: {:?} {:?}",
+                provider.name, error, query.error_description
+            );
+            return HttpResponse::BadRequest().json(json!({
+                "code":400,
+                "message":"Sign-in was cancelled or failed at the identity provider",
+                "error":error,
+            }));
+        }
+    };
+    let redirect_uri = config.callback_url(&provider.name);
+    let identity = match provider.exchange_code(code, &redirect_uri, &state).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            warn!("SSO code exchange with {} failed: {}", provider.name, e);
+            return HttpResponse::BadGateway()
+                .json(json!({"code":502,"message":"Identity provider error"}));
+        }
+    };
+
+    let linked = match find_identity(db.get_ref(), &provider.name, &identity.subject).await {
+        Ok(linked) => linked,
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    };
+
+    // 3. Link to the user who started the flow, or sign in
+    match state.link_user_id {
+        Some(user_id) => link(db.get_ref(), provider, identity, linked, user_id).await,
+        None => {
+            let may_provision = provider.may_provision(&identity);
+            let login = ExternalLogin {
+                provider: &provider.name,
+                identity,
+                may_provision,
+            };
+            sign_in_external(db.get_ref(), login, linked, client_ip).await
+        }
+    }
+}
+
+async fn link(
+    db: &DatabaseConnection,
+    provider: &SsoProvider,
+    identity: ExternalIdentity,
+    linked: Option<Identity>,
+    user_id: i32,
+) -> HttpResponse {
+    if let Some(existing) = linked {
+        if existing.user_id != user_id {
+            warn!(
+                "User {} tried to link {} identity already linked to user {}",
+                user_id, provider.name, existing.user_id
+            );
+            return HttpResponse::Conflict().json(
+                json!({"code":409,"message":"This account is already linked to another user"}),
+            );
+        }
+        return HttpResponse::Ok()
+            .json(json!({"code":200,"message":"Account already linked","identity":existing}));
+    }
+
+    let new_identity = ActiveModel {
+        user_id: Set(user_id),
+        provider: Set(provider.name.clone()),
+        subject: Set(identity.subject),
+        email: Set(identity.email),
+        ..Default::default()
+    };
+    match new_identity.insert(db).await {
+        Ok(res) => {
+            info!("User {} linked a {} identity", user_id, provider.name);
+            HttpResponse::Created()
+                .json(json!({"code":201,"message":"Account linked","identity":res}))
+        }
+        Err(e) => {
+            error!("Database insertion error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+#[get("/identities")]
+pub async fn list_identities(
+    db: web::Data<DatabaseConnection>,
+    user: AuthenticatedUser,
+) -> impl Responder {
+    debug!("list identities checkpoint api.");
+    let owner = match session_user(db.get_ref(), &user).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    match Entity::find()
+        .filter(Column::UserId.eq(owner.id))
+        .order_by_asc(Column::Id)
+        .all(db.get_ref())
+        .await
+    {
+        Ok(identities) => HttpResponse::Ok().json(
+            json!({"code":200,"message":"Identities fetched successfully","identities":identities}),
+        ),
+        Err(e) => {
+            error!("Database error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+#[delete("/identities/{id}")]
+pub async fn unlink_identity(
+    db: web::Data<DatabaseConnection>,
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+) -> impl Responder {
+    debug!("unlink identity checkpoint api.");
+    let owner = match session_user(db.get_ref(), &user).await {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+
+    let identity_id = path.into_inner();
+    let identity = match Entity::find_by_id(identity_id)
+        .filter(Column::UserId.eq(owner.id))
+        .one(db.get_ref())
+        .await
+    {
+        Ok(Some(identity)) => identity,
+        Ok(None) => {
+            return HttpResponse::NotFound()
+                .json(json!({"code":404,"message":"Identity not found"}));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    };
+
+    // An account without a password must keep at least one way to sign in.
+    if owner.password.is_none() {
+        match Entity::find()
+            .filter(Column::UserId.eq(owner.id))
+            .count(db.get_ref())
+            .await
+        {
+            Ok(count) if count <= 1 => {
+                return HttpResponse::Conflict().json(json!({
+                    "code":409,
+                    "message":"Cannot unlink the only sign-in method of an account without a password"
+                }));
+            }
+            Ok(_) => (),
+            Err(e) => {
+                error!("Database error: {}", e);
+                return HttpResponse::InternalServerError()
+                    .json(json!({"code":500,"message":"Internal server error"}));
+            }
+        }
+    }
+
+    match identity.delete(db.get_ref()).await {
+        Ok(_) => {
+            info!("User {} unlinked identity {}", owner.username, identity_id);
+            HttpResponse::Ok().json(json!({"code":200,"message":"Identity unlinked"}))
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sso::tests::test_provider;
+    use actix_web::http::StatusCode;
+    use sea_orm::{DatabaseBackend, MockDatabase};
+
+    fn identity() -> ExternalIdentity {
+        ExternalIdentity {
+            subject: "248289761001".to_string(),
+            email: Some("jane@example.com".to_string()),
+            email_verified: true,
+            preferred_username: None,
+            phone: None,
+        }
+    }
+
+    fn linked_identity(user_id: i32) -> Identity {
+        let now = chrono::Utc::now().into();
+        Identity {
+            id: 7,
+            user_id,
+            provider: "mock".to_string(),
+            subject: "248289761001".to_string(),
+            email: Some("jane@example.com".to_string()),
+            last_login_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[actix_web::test]
+    async fn links_a_new_identity_to_the_signed_in_user() {
+        let provider = test_provider("https://idp.example.com");
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![linked_identity(42)]])
+            .into_connection();
+
+        let response = link(&db, &provider, identity(), None, 42).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let log = format!("{:?}", db.into_transaction_log());
+        assert!(log.contains("INSERT INTO"), "{}", log);
+    }
+
+    #[actix_web::test]
+    async fn relinking_the_same_user_changes_nothing() {
+        let provider = test_provider("https://idp.example.com");
+        let db = MockDatabase::new(DatabaseBackend::Postgres).into_connection();
+
+        let response = link(&db, &provider, identity(), Some(linked_identity(42)), 42).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(db.into_transaction_log().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn refuses_identities_linked_to_another_user() {
+        let provider = test_provider("https://idp.example.com");
+        let db = MockDatabase::new(DatabaseBackend::Postgres).into_connection();
+
+        let response = link(&db, &provider, identity(), Some(linked_identity(41)), 42).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(db.into_transaction_log().is_empty());
+    }
+}
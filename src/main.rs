@@ -1,9 +1,10 @@
 // src/main.rs
-use actix_web::{App, HttpServer, web};
+use actix_web::{App, HttpServer, middleware::from_fn, web};
 use dotenv::dotenv;
 use sea_orm::{Database, DatabaseConnection};
 use std::env;
-use tracing::info;
+use std::sync::Arc;
+use tracing::{error, info};
 use tracing_actix_web::TracingLogger;
 use tracing_subscriber::EnvFilter;
 mod handlers;
@@ -12,6 +13,24 @@ mod routes;
 mod services;
 mod utils;
 use redis::{Client as RedisClient, aio::ConnectionManager};
+use services::auth_service::AuthBackends;
+use utils::account_deletion::{AccountDeletionConfig, purge_deleted_accounts};
+use utils::account_export::AccountExportConfig;
+use utils::client_ip::TrustedProxies;
+use utils::impersonation::ImpersonationConfig;
+use utils::mailer::Mailer;
+use utils::oauth::OAuthConfig;
+use utils::oidc::OidcProvider;
+use utils::passwordless::PasswordlessConfig;
+use utils::proxy_protocol;
+use utils::saml::SamlConfig;
+use utils::security_headers::{SecurityConfig, security_headers, validate_host};
+use utils::shutdown::{BackgroundWorkers, ShutdownConfig, ShutdownState, wait_for_signal};
+use utils::sms::{SmsConfig, SmsSender};
+use utils::sso::SsoConfig;
+use utils::tls::{
+    CertReloader, HANDSHAKE_TIMEOUT, TlsConfig, capture_peer_certificate, watch_certificates,
+};
 
 // Configure a global tracing subscriber with env-level filtering.
 fn init_tracing() {
@@ -49,17 +68,145 @@ async fn main() -> std::io::Result<()> {
         .unwrap_or_else(|_| "8080".to_string())
         .parse()
         .expect("PORT must be a valid u16");
-    info!("Server running at http://{}:{}", host, port);
+    // Checked here so a malformed region fails at startup, not on the first registration.
+    utils::phone::default_region();
+    let shutdown_config = ShutdownConfig::from_env();
+    let security_config = SecurityConfig::from_env();
+    let trusted_proxies = TrustedProxies::from_env();
+    let proxy_protocol = env::var("PROXY_PROTOCOL")
+        .map(|v| v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    if proxy_protocol && trusted_proxies.is_empty() {
+        panic!("PROXY_PROTOCOL requires TRUSTED_PROXIES to list the load balancer addresses");
+    }
+    let tls_config = TlsConfig::from_env();
+    let scheme = if tls_config.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    info!("Server running at {}://{}:{}", scheme, host, port);
+
+    // Shutdown state is shared by readiness checks, the signal listener and background workers.
+    let shutdown_state = Arc::new(ShutdownState::new());
+    let mut workers = BackgroundWorkers::new(shutdown_state.clone());
+
+    let rustls_config = match tls_config {
+        Some(tls_config) => {
+            let reloader = Arc::new(CertReloader::new(tls_config.clone())?);
+            let server_config = tls_config.server_config(reloader.clone())?;
+            workers.spawn("tls-cert-reloader", move |signal| {
+                watch_certificates(reloader, signal)
+            });
+            Some(server_config)
+        }
+        None => None,
+    };
+
     let db_data = web::Data::new(db);
+    let account_deletion_config = AccountDeletionConfig::from_env();
+    let purge_db = db_data.clone().into_inner();
+    let purge_interval = account_deletion_config.purge_interval;
+    workers.spawn("account-purger", move |signal| {
+        purge_deleted_accounts(purge_db, purge_interval, signal)
+    });
+
+    let db_pool = db_data.clone();
     let redis_data = web::Data::new(redis_conn);
-    HttpServer::new(move || {
-        App::new()
+    let shutdown_data = web::Data::from(shutdown_state.clone());
+    let security_data = web::Data::new(security_config);
+    let proxies_data = web::Data::new(trusted_proxies.clone());
+    let oauth_data = web::Data::new(OAuthConfig::from_env());
+    let oidc_data = OidcProvider::from_env()?.map(web::Data::new);
+    if let Some(oidc) = &oidc_data {
+        info!("OpenID Connect enabled for issuer {}", oidc.issuer);
+    }
+    let sso_config = SsoConfig::from_env();
+    if !sso_config.is_empty() {
+        info!("Single sign-on providers configured");
+    }
+    let sso_data = web::Data::new(sso_config);
+    let saml_config = SamlConfig::from_env()?;
+    if !saml_config.is_empty() {
+        info!("SAML tenants configured");
+    }
+    let saml_data = web::Data::new(saml_config);
+    let auth_backends_data = web::Data::new(AuthBackends::from_env()?);
+    let mailer_data = web::Data::new(Mailer::from_env()?);
+    let passwordless_data = web::Data::new(PasswordlessConfig::from_env());
+    let sms_data = web::Data::new(SmsSender::from_env());
+    let sms_config_data = web::Data::new(SmsConfig::from_env());
+    let impersonation_data = web::Data::new(ImpersonationConfig::from_env());
+    let account_deletion_data = web::Data::new(account_deletion_config);
+    let account_export_data = web::Data::new(AccountExportConfig::from_env());
+    let app_factory = move || {
+        let mut app = App::new()
             .app_data(db_data.clone())
             .app_data(redis_data.clone())
+            .app_data(shutdown_data.clone())
+            .app_data(security_data.clone())
+            .app_data(proxies_data.clone())
+            .app_data(oauth_data.clone())
+            .app_data(sso_data.clone())
+            .app_data(saml_data.clone())
+            .app_data(auth_backends_data.clone())
+            .app_data(mailer_data.clone())
+            .app_data(passwordless_data.clone())
+            .app_data(sms_data.clone())
+            .app_data(sms_config_data.clone())
+            .app_data(impersonation_data.clone())
+            .app_data(account_deletion_data.clone())
+            .app_data(account_export_data.clone());
+        if let Some(oidc) = &oidc_data {
+            app = app.app_data(oidc.clone());
+        }
+        // The last wrap runs first: security headers also cover the bad-Host rejections.
+        app.wrap(from_fn(validate_host))
+            .wrap(from_fn(security_headers))
             .wrap(TracingLogger::default())
+            .configure(routes::health_route::configure_routes)
             .configure(routes::auth_route::configure_routes)
-    })
-    .bind((host.as_str(), port))?
-    .run()
-    .await
+            .configure(routes::oauth_route::configure_routes)
+            .configure(routes::admin_route::configure_routes)
+    };
+
+    let shutdown_signal = wait_for_signal(shutdown_state.clone(), shutdown_config.drain_delay);
+    let server_result = if proxy_protocol {
+        // HttpServer cannot strip the PROXY header, so build the listener on actix-server directly.
+        info!("Expecting PROXY protocol headers from trusted proxies");
+        let listener = std::net::TcpListener::bind((host.as_str(), port))?;
+        let rustls_config = rustls_config.map(Arc::new);
+        actix_server::Server::build()
+            .listen("http-proxy-protocol", listener, move || {
+                proxy_protocol::http_service(
+                    app_factory(),
+                    trusted_proxies.clone(),
+                    rustls_config.clone(),
+                )
+            })?
+            .shutdown_timeout(shutdown_config.request_timeout)
+            .shutdown_signal(shutdown_signal)
+            .run()
+            .await
+    } else {
+        let server = HttpServer::new(app_factory)
+            .on_connect(capture_peer_certificate)
+            .tls_handshake_timeout(HANDSHAKE_TIMEOUT)
+            .shutdown_timeout(shutdown_config.request_timeout)
+            .shutdown_signal(shutdown_signal);
+        let server = match rustls_config {
+            Some(config) => server.bind_rustls_0_23((host.as_str(), port), config)?,
+            None => server.bind((host.as_str(), port))?,
+        };
+        server.run().await
+    };
+
+    // In-flight requests have finished (or timed out); flush workers, then release connections.
+    info!("HTTP server stopped, waiting for background workers");
+    workers.shutdown(shutdown_config.worker_timeout).await;
+    if let Err(e) = db_pool.close_by_ref().await {
+        error!("Failed to close database pool: {}", e);
+    }
+    info!("Shutdown complete");
+    server_result
 }
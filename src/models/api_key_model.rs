@@ -0,0 +1,115 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::{
+    ActiveModelBehavior, ConnectionTrait, DeriveEntityModel, DeriveRelation, EnumIter, Set,
+};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+/// Scopes a personal API key can be granted.
+pub const API_KEY_SCOPES: &[&str] = &["profile:read", "profile:write"];
+
+//===============================
+// ORM Entity Definition
+//===============================
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "api_keys")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    /// Public part of the key, used to find the row without scanning hashes.
+    #[sea_orm(unique)]
+    pub prefix: String,
+    #[serde(skip)]
+    pub key_hash: String,
+    /// Space-separated scope list.
+    pub scopes: String,
+    pub expires_at: Option<DateTimeWithTimeZone>,
+    pub last_used_at: Option<DateTimeWithTimeZone>,
+    pub last_used_ip: Option<String>,
+    pub revoked_at: Option<DateTimeWithTimeZone>,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+impl Model {
+    pub fn scope_list(&self) -> Vec<String> {
+        self.scopes.split_whitespace().map(str::to_string).collect()
+    }
+}
+
+//===============================
+// Relations
+//===============================
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::auth_model::Entity",
+        from = "Column::UserId",
+        to = "super::auth_model::Column::Id",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<super::auth_model::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+//===============================
+// Active Model Behavior
+//===============================
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if insert {
+            self.created_at = Set(chrono::Utc::now().into());
+        }
+        self.updated_at = Set(chrono::Utc::now().into());
+        Ok(self)
+    }
+}
+
+//================================
+// Data Transfer Objects (DTOs)
+//================================
+#[derive(Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(
+        min = 1,
+        max = 64,
+        message = "Name must be between 1 and 64 characters"
+    ))]
+    pub name: String,
+    #[validate(custom(function = "validate_scopes"))]
+    pub scopes: Vec<String>,
+    #[validate(custom(function = "validate_expiry"))]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+fn validate_scopes(scopes: &[String]) -> Result<(), ValidationError> {
+    if !scopes.is_empty() && scopes.iter().all(|s| API_KEY_SCOPES.contains(&s.as_str())) {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("invalid_scope");
+        err.message =
+            Some(format!("Scopes must be a non-empty subset of {:?}", API_KEY_SCOPES).into());
+        Err(err)
+    }
+}
+
+fn validate_expiry(expires_at: &chrono::DateTime<chrono::Utc>) -> Result<(), ValidationError> {
+    if *expires_at > chrono::Utc::now() {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("expiry_in_past");
+        err.message = Some("Expiry must be in the future".into());
+        Err(err)
+    }
+}
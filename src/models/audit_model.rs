@@ -0,0 +1,44 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveModelBehavior, ConnectionTrait, DeriveEntityModel, Set};
+use serde::{Deserialize, Serialize};
+
+//===============================
+// ORM Entity Definition
+//===============================
+/// One entry of the append-only audit trail of privileged actions.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "audit_events")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    /// User who acted; kept as a plain id so events outlive the account.
+    pub actor_id: Option<i32>,
+    /// Username of the actor at the time of the event.
+    pub actor: String,
+    /// Dotted action name, e.g. `user.suspend`.
+    pub action: String,
+    pub target_user_id: Option<i32>,
+    /// Action parameters (reason, filters, ...); never secrets.
+    pub details: Json,
+    pub ip: Option<String>,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+//===============================
+// Active Model Behavior
+//===============================
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if insert {
+            self.created_at = Set(chrono::Utc::now().into());
+        }
+        Ok(self)
+    }
+}
@@ -1,7 +1,12 @@
 // src/models/auth_model.rs
+use crate::utils::identifier::{
+    canonical_email, canonical_username, normalize_username, username_problem, username_skeleton,
+};
+use crate::utils::phone::normalize_phone;
 use sea_orm::entity::prelude::*;
 use sea_orm::{
-    ActiveModelBehavior, ConnectionTrait, DeriveEntityModel, DeriveRelation, EnumIter, Set,
+    ActiveModelBehavior, ActiveValue, Condition, ConnectionTrait, DeriveEntityModel,
+    DeriveRelation, EnumIter, Set,
 };
 use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
@@ -9,6 +14,61 @@ use validator::{Validate, ValidationError};
 //===============================
 // ORM Entity Definition
 //===============================
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[sea_orm(string_value = "user")]
+    User,
+    /// May use the `/api/v1/admin` endpoints.
+    #[sea_orm(string_value = "admin")]
+    Admin,
+}
+
+/// Where an account is in its lifecycle. Only `active` accounts (and suspensions past their
+/// `status_until`) can sign in; every other status has its own login error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(32))")]
+#[serde(rename_all = "snake_case")]
+pub enum UserStatus {
+    /// The email address must be confirmed first; an emailed sign-in code or link does so.
+    #[sea_orm(string_value = "pending_verification")]
+    PendingVerification,
+    #[sea_orm(string_value = "active")]
+    Active,
+    /// Barred by an administrator, with a `status_reason` and optionally until `status_until`.
+    #[sea_orm(string_value = "suspended")]
+    Suspended,
+    /// Barred for security reasons until an administrator unlocks it.
+    #[sea_orm(string_value = "locked")]
+    Locked,
+    /// Closed by its owner or an administrator; can be reactivated. Owners who close their
+    /// account reactivate it by signing in before `purge_after`.
+    #[sea_orm(string_value = "deactivated")]
+    Deactivated,
+    /// Erased. The row stays as a tombstone so the username is not reused.
+    #[sea_orm(string_value = "deleted")]
+    Deleted,
+}
+
+impl UserStatus {
+    /// Allowed lifecycle transitions. `deleted` is final.
+    pub fn can_become(self, next: UserStatus) -> bool {
+        use UserStatus::*;
+        match (self, next) {
+            (Deleted, _) => false,
+            (_, Deleted) => true,
+            (PendingVerification, Active | Suspended | Deactivated) => true,
+            (Active, Suspended | Locked | Deactivated) => true,
+            // Suspended to suspended changes the reason or the end date.
+            (Suspended, Active | Suspended | Locked | Deactivated) => true,
+            (Locked, Active | Suspended | Deactivated) => true,
+            (Deactivated, Active) => true,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "auth_users")]
 pub struct Model {
@@ -16,13 +76,48 @@ pub struct Model {
     pub id: i32,
     #[sea_orm(unique)]
     pub username: String,
+    /// Case-folded `username` for lookups; derived on save.
+    #[sea_orm(unique)]
     #[serde(skip)]
-    pub password: String,
+    pub username_canonical: String,
+    /// Confusable skeleton of `username`; new usernames must not share one.
+    #[serde(skip)]
+    pub username_skeleton: String,
+    /// Argon2 hash. `None` for accounts provisioned from an identity provider, which cannot
+    /// use password login.
+    #[serde(skip)]
+    pub password: Option<String>,
     #[sea_orm(unique)]
     pub email: String,
+    /// Case-folded `email` for lookups; derived on save.
+    #[sea_orm(unique)]
+    #[serde(skip)]
+    pub email_canonical: String,
+    /// Set once the user proved control of `email`; reported as the OIDC `email_verified` claim.
+    pub email_verified_at: Option<DateTimeWithTimeZone>,
+    /// E.164 (`+66812345678`), unique among users that have one; empty when unknown.
     pub phone: String,
-    #[sea_orm(default_value = true)]
-    pub active: bool,
+    /// Set once the user entered a code texted to `phone`; cleared when the number changes.
+    pub phone_verified_at: Option<DateTimeWithTimeZone>,
+    /// Email and phone number requested through `PATCH /profile`, applied once confirmed with
+    /// the code sent to them.
+    pub pending_email: Option<String>,
+    pub pending_phone: Option<String>,
+    /// Set by an administrator: password login is refused until the emailed reset code is used.
+    #[sea_orm(default_value = false)]
+    pub password_reset_required: bool,
+    /// Password and email sign-ins also require a code texted to the verified `phone`.
+    #[sea_orm(default_value = false)]
+    pub sms_mfa_enabled: bool,
+    pub status: UserStatus,
+    /// Why the account was suspended or locked, shown in the login error.
+    pub status_reason: Option<String>,
+    /// End of a suspension; the account can sign in again afterwards.
+    pub status_until: Option<DateTimeWithTimeZone>,
+    /// When a `deactivated` account closed by its owner is erased. Signing in before then
+    /// cancels the deletion.
+    pub purge_after: Option<DateTimeWithTimeZone>,
+    pub role: Role,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
 }
@@ -31,7 +126,24 @@ pub struct Model {
 // Relations
 //===============================
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(has_many = "super::api_key_model::Entity")]
+    ApiKeys,
+    #[sea_orm(has_many = "super::identity_model::Entity")]
+    Identities,
+}
+
+impl Related<super::api_key_model::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ApiKeys.def()
+    }
+}
+
+impl Related<super::identity_model::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Identities.def()
+    }
+}
 
 //===============================
 // Active Model Behavior
@@ -44,29 +156,79 @@ impl ActiveModelBehavior for ActiveModel {
     {
         if insert {
             self.created_at = Set(chrono::Utc::now().into());
-            self.active = Set(true);
+            if self.status.is_not_set() {
+                self.status = Set(UserStatus::Active);
+            }
+            if self.role.is_not_set() {
+                self.role = Set(Role::User);
+            }
+        }
+        if let ActiveValue::Set(username) = &self.username {
+            self.username_canonical = Set(canonical_username(username));
+            self.username_skeleton = Set(username_skeleton(username));
+        }
+        if let ActiveValue::Set(email) = &self.email {
+            self.email_canonical = Set(canonical_email(email));
         }
         self.updated_at = Set(chrono::Utc::now().into());
         Ok(self)
     }
 }
 
+//===============================
+// Account Status
+//===============================
+impl Model {
+    /// The status in force now: a suspension past its `status_until` counts as active.
+    pub fn effective_status(&self) -> UserStatus {
+        match (self.status, self.status_until) {
+            (UserStatus::Suspended, Some(until)) if until <= chrono::Utc::now() => {
+                UserStatus::Active
+            }
+            (status, _) => status,
+        }
+    }
+
+    /// Closed by its owner and still within the grace period, so signing in restores it.
+    pub fn deletion_pending(&self) -> bool {
+        self.status == UserStatus::Deactivated
+            && self.purge_after.is_some_and(|at| at > chrono::Utc::now())
+    }
+}
+
+/// Filter for accounts whose credentials are accepted, matching `effective_status() == Active`.
+pub fn can_sign_in() -> Condition {
+    Condition::any()
+        .add(Column::Status.eq(UserStatus::Active))
+        .add(
+            Condition::all()
+                .add(Column::Status.eq(UserStatus::Suspended))
+                .add(Column::StatusUntil.lte(chrono::Utc::now())),
+        )
+}
+
 //================================
 // Data Transfer Objects (DTOs)
 //================================
 #[derive(Deserialize, Validate)]
 pub struct LoginRequest {
-    pub username: String,
+    /// Username, email or phone number, as enabled by `LOGIN_IDENTIFIERS`.
+    #[serde(alias = "username")]
+    #[validate(length(min = 1, max = 254, message = "Identifier is required"))]
+    pub identifier: String,
     pub password: String,
 }
 
 #[derive(Deserialize, Validate)]
 pub struct RegisterRequest {
-    #[validate(length(
-        min = 3,
-        max = 30,
-        message = "Username must be between 3 and 30 characters"
-    ))]
+    #[validate(
+        length(
+            min = 3,
+            max = 30,
+            message = "Username must be between 3 and 30 characters"
+        ),
+        custom(function = "validate_username")
+    )]
     pub username: String,
     #[validate(custom(function = "validate_password"))]
     pub password: String,
@@ -76,6 +238,143 @@ pub struct RegisterRequest {
     pub phone: String,
 }
 
+/// Body of `/magic-link` and `/email-otp`.
+#[derive(Deserialize, Validate)]
+pub struct PasswordlessRequest {
+    #[validate(email(message = "Invalid email format"))]
+    pub email: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct MagicLinkVerifyRequest {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct EmailOtpVerifyRequest {
+    #[validate(email(message = "Invalid email format"))]
+    pub email: String,
+    #[validate(length(equal = 6, message = "Code must be 6 digits"))]
+    pub code: String,
+}
+
+/// A code sent by text or email: `/phone/verify` and the profile change confirmations.
+#[derive(Deserialize, Validate)]
+pub struct CodeRequest {
+    #[validate(length(equal = 6, message = "Code must be 6 digits"))]
+    pub code: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct SmsMfaRequest {
+    pub enabled: bool,
+}
+
+/// Second step of a login that requires a texted code.
+#[derive(Deserialize, Validate)]
+pub struct SmsLoginRequest {
+    #[validate(length(min = 1, message = "MFA token is required"))]
+    pub mfa_token: String,
+    #[validate(length(equal = 6, message = "Code must be 6 digits"))]
+    pub code: String,
+}
+
+/// `/password/reset`: the emailed code and the new password.
+#[derive(Deserialize, Validate)]
+pub struct PasswordResetRequest {
+    #[validate(email(message = "Invalid email format"))]
+    pub email: String,
+    #[validate(length(equal = 6, message = "Code must be 6 digits"))]
+    pub code: String,
+    #[validate(custom(function = "validate_password"))]
+    pub new_password: String,
+}
+
+/// `DELETE /account`. Accounts with a password confirm with it; the others with a code, which
+/// a first request without one sends to the verified phone (SMS MFA) or the email address.
+#[derive(Deserialize, Validate)]
+pub struct DeleteAccountRequest {
+    pub password: Option<String>,
+    #[validate(length(equal = 6, message = "Code must be 6 digits"))]
+    pub code: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    /// The same JSON document, gzip-compressed.
+    Gzip,
+    /// A `.zip` archive holding the JSON document as `account-export.json`.
+    Zip,
+}
+
+/// Query of `GET /account/export/{token}`.
+#[derive(Deserialize)]
+pub struct ExportDownloadQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// `PATCH /profile`. `updated_at` is the value last read from `GET /profile`; the update is
+/// refused if the profile changed since.
+#[derive(Deserialize, Validate)]
+pub struct UpdateProfileRequest {
+    #[validate(email(message = "Invalid email format"))]
+    pub email: Option<String>,
+    #[validate(custom(function = "validate_phone"))]
+    pub phone: Option<String>,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserSort {
+    CreatedAt,
+    Username,
+    Email,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Query of `GET /admin/users`. `q` matches part of the username or email, case-insensitively.
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct ListUsersQuery {
+    #[validate(range(min = 1, message = "Page starts at 1"))]
+    pub page: Option<u64>,
+    #[validate(range(min = 1, max = 100, message = "per_page must be between 1 and 100"))]
+    pub per_page: Option<u64>,
+    pub status: Option<UserStatus>,
+    pub created_from: Option<DateTimeWithTimeZone>,
+    pub created_to: Option<DateTimeWithTimeZone>,
+    #[validate(length(min = 1, max = 254, message = "Search must be 1 to 254 characters"))]
+    pub q: Option<String>,
+    pub sort: Option<UserSort>,
+    pub order: Option<SortOrder>,
+}
+
+/// `POST /admin/users/{id}/suspend`. Without `until` the suspension lasts until lifted.
+#[derive(Deserialize, Validate)]
+pub struct SuspendUserRequest {
+    #[validate(length(min = 1, max = 500, message = "Reason must be 1 to 500 characters"))]
+    pub reason: String,
+    pub until: Option<DateTimeWithTimeZone>,
+}
+
+/// `POST /admin/users/{id}/impersonate`. The reason is kept in the audit trail.
+#[derive(Deserialize, Validate)]
+pub struct ImpersonateUserRequest {
+    #[validate(length(min = 1, max = 500, message = "Reason must be 1 to 500 characters"))]
+    pub reason: String,
+}
+
 //===============================
 // From Trait Implementation
 //===============================
@@ -83,7 +382,7 @@ impl From<(RegisterRequest, String)> for ActiveModel {
     fn from((data, hashed_password): (RegisterRequest, String)) -> Self {
         Self {
             username: Set(data.username),
-            password: Set(hashed_password),
+            password: Set(Some(hashed_password)),
             email: Set(data.email),
             phone: Set(data.phone),
             ..Default::default()
@@ -91,6 +390,17 @@ impl From<(RegisterRequest, String)> for ActiveModel {
     }
 }
 
+fn validate_username(username: &str) -> Result<(), ValidationError> {
+    match username_problem(&normalize_username(username)) {
+        None => Ok(()),
+        Some(problem) => {
+            let mut err = ValidationError::new("username_format");
+            err.message = Some(problem.into());
+            Err(err)
+        }
+    }
+}
+
 fn validate_password(password: &str) -> Result<(), ValidationError> {
     let has_upper = password.chars().any(|c| c.is_ascii_uppercase());
     let has_lower = password.chars().any(|c| c.is_ascii_lowercase());
@@ -108,14 +418,12 @@ fn validate_password(password: &str) -> Result<(), ValidationError> {
 }
 
 fn validate_phone(phone: &str) -> Result<(), ValidationError> {
-    let len = phone.chars().count();
-    let all_digits = phone.chars().all(|c| c.is_ascii_digit());
-
-    if all_digits && (10..=15).contains(&len) {
+    if normalize_phone(phone).is_some() {
         Ok(())
     } else {
         let mut err = ValidationError::new("phone_format");
-        err.message = Some("Phone number must be digits only".into());
+        err.message =
+            Some("Invalid phone number; include the country code, e.g. +14155550123".into());
         Err(err)
     }
 }
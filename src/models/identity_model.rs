@@ -0,0 +1,83 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::{
+    ActiveModelBehavior, ConnectionTrait, DeriveEntityModel, DeriveRelation, EnumIter, Set,
+};
+use serde::{Deserialize, Serialize};
+
+//===============================
+// ORM Entity Definition
+//===============================
+/// An account at an external identity provider, linked to a local user.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "identities")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub user_id: i32,
+    /// Provider name from `SSO_PROVIDERS`.
+    pub provider: String,
+    /// The provider's stable user id (`sub`). Unique per provider.
+    pub subject: String,
+    /// Email reported by the provider at the last sign-in, for display only.
+    pub email: Option<String>,
+    pub last_login_at: Option<DateTimeWithTimeZone>,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+//===============================
+// Relations
+//===============================
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::auth_model::Entity",
+        from = "Column::UserId",
+        to = "super::auth_model::Column::Id",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<super::auth_model::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+//===============================
+// Active Model Behavior
+//===============================
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if insert {
+            self.created_at = Set(chrono::Utc::now().into());
+        }
+        self.updated_at = Set(chrono::Utc::now().into());
+        Ok(self)
+    }
+}
+
+//================================
+// Data Transfer Objects (DTOs)
+//================================
+/// Query the identity provider redirects back with.
+#[derive(Deserialize)]
+pub struct SsoCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// HTTP-POST binding form the IdP's browser redirect submits to the ACS endpoint. `RelayState`
+/// is not used: sign-in always returns a token rather than resuming a page.
+#[derive(Deserialize)]
+pub struct SamlAcsForm {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+}
@@ -1 +1,6 @@
+pub mod api_key_model;
+pub mod audit_model;
 pub mod auth_model;
+pub mod identity_model;
+pub mod oauth_client_model;
+pub mod service_account_model;
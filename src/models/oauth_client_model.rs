@@ -0,0 +1,215 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::{
+    ActiveModelBehavior, ConnectionTrait, DeriveEntityModel, DeriveRelation, EnumIter, Set,
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use validator::{Validate, ValidationError};
+
+/// Scopes an OAuth client can be allowed to request. The OpenID Connect scopes select
+/// identity claims; the others are API permissions carried by access tokens.
+pub const OAUTH_SCOPES: &[&str] = &[
+    "openid",
+    "profile",
+    "email",
+    "phone",
+    "profile:read",
+    "profile:write",
+];
+
+//===============================
+// ORM Entity Definition
+//===============================
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "oauth_clients")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub owner_id: i32,
+    #[sea_orm(unique)]
+    pub client_id: String,
+    /// `None` for public clients (SPAs, native apps), which authenticate with PKCE only.
+    #[serde(skip)]
+    pub client_secret_hash: Option<String>,
+    pub name: String,
+    /// Space-separated list of exact redirect URIs.
+    #[sea_orm(column_type = "Text")]
+    pub redirect_uris: String,
+    /// Space-separated scopes the client may request.
+    pub allowed_scopes: String,
+    pub confidential: bool,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+impl Model {
+    /// OAuth 2.1 requires exact string matching of redirect URIs.
+    pub fn has_redirect_uri(&self, uri: &str) -> bool {
+        self.redirect_uris.split_whitespace().any(|r| r == uri)
+    }
+
+    pub fn allows_scope(&self, scope: &str) -> bool {
+        self.allowed_scopes.split_whitespace().any(|s| s == scope)
+    }
+}
+
+//===============================
+// Relations
+//===============================
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::auth_model::Entity",
+        from = "Column::OwnerId",
+        to = "super::auth_model::Column::Id",
+        on_delete = "Cascade"
+    )]
+    Owner,
+}
+
+impl Related<super::auth_model::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Owner.def()
+    }
+}
+
+//===============================
+// Active Model Behavior
+//===============================
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if insert {
+            self.created_at = Set(chrono::Utc::now().into());
+        }
+        self.updated_at = Set(chrono::Utc::now().into());
+        Ok(self)
+    }
+}
+
+//================================
+// Data Transfer Objects (DTOs)
+//================================
+#[derive(Deserialize, Validate)]
+pub struct RegisterClientRequest {
+    #[validate(length(
+        min = 1,
+        max = 64,
+        message = "Name must be between 1 and 64 characters"
+    ))]
+    pub name: String,
+    #[validate(custom(function = "validate_redirect_uris"))]
+    pub redirect_uris: Vec<String>,
+    #[validate(custom(function = "validate_scopes"))]
+    pub scopes: Vec<String>,
+    /// Confidential clients receive a secret; public clients rely on PKCE alone.
+    pub confidential: bool,
+}
+
+/// Query of `GET /oauth/authorize`; also the body of the consent `POST`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuthorizeRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    /// Echoed in the ID token so the client can bind it to its session.
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ConsentRequest {
+    #[serde(flatten)]
+    pub authorize: AuthorizeRequest,
+    pub approved: bool,
+}
+
+/// Form body of `POST /oauth/token`.
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub device_code: Option<String>,
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// Form body of `POST /oauth/device_authorization` (RFC 8628 §3.1).
+#[derive(Deserialize)]
+pub struct DeviceAuthorizationRequest {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceLookupQuery {
+    pub user_code: String,
+}
+
+/// The signed-in user's answer to a device's request.
+#[derive(Deserialize)]
+pub struct DeviceApprovalRequest {
+    pub user_code: String,
+    pub approved: bool,
+}
+
+/// Form body of `POST /oauth/introspect` (RFC 7662) and `POST /oauth/revoke` (RFC 7009).
+/// `token_type_hint` is accepted but unused: JWTs and refresh tokens are told apart by format.
+#[derive(Deserialize)]
+pub struct TokenActionRequest {
+    pub token: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+fn validate_redirect_uris(uris: &[String]) -> Result<(), ValidationError> {
+    let valid = !uris.is_empty() && uris.iter().all(|uri| is_valid_redirect_uri(uri));
+    if valid {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("redirect_uri");
+        err.message = Some(
+            "Redirect URIs must be absolute without fragment: https, loopback http or a private-use scheme"
+                .into(),
+        );
+        Err(err)
+    }
+}
+
+/// https anywhere, plain http only on loopback, or a reverse-domain private-use scheme
+/// (`com.example.app:/callback`) for native apps.
+fn is_valid_redirect_uri(uri: &str) -> bool {
+    let Ok(url) = Url::parse(uri) else {
+        return false;
+    };
+    if url.fragment().is_some() || uri.contains(char::is_whitespace) {
+        return false;
+    }
+    match url.scheme() {
+        "https" => url.host().is_some(),
+        "http" => matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]")),
+        scheme => scheme.contains('.'),
+    }
+}
+
+fn validate_scopes(scopes: &[String]) -> Result<(), ValidationError> {
+    if !scopes.is_empty() && scopes.iter().all(|s| OAUTH_SCOPES.contains(&s.as_str())) {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("invalid_scope");
+        err.message =
+            Some(format!("Scopes must be a non-empty subset of {:?}", OAUTH_SCOPES).into());
+        Err(err)
+    }
+}
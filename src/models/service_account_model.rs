@@ -0,0 +1,104 @@
+use sea_orm::entity::prelude::*;
+use sea_orm::{
+    ActiveModelBehavior, ConnectionTrait, DeriveEntityModel, DeriveRelation, EnumIter, Set,
+};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+/// Scopes a service account can be allowed to request with the client-credentials grant.
+pub const SERVICE_ACCOUNT_SCOPES: &[&str] = &["profile:read", "profile:write"];
+
+//===============================
+// ORM Entity Definition
+//===============================
+/// A non-human caller (batch job, internal service).
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "service_accounts")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(unique)]
+    pub name: String,
+    /// Identity matched against a client certificate: a SAN (DNS name, URI or email)
+    /// or the subject common name.
+    #[sea_orm(unique)]
+    pub certificate_subject: Option<String>,
+    /// Identifier for the OAuth client-credentials grant; `None` for certificate-only accounts.
+    #[sea_orm(unique)]
+    pub client_id: Option<String>,
+    #[serde(skip)]
+    pub client_secret_hash: Option<String>,
+    /// Space-separated scopes the account may request.
+    pub allowed_scopes: String,
+    #[sea_orm(default_value = true)]
+    pub active: bool,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+impl Model {
+    pub fn allows_scope(&self, scope: &str) -> bool {
+        self.allowed_scopes.split_whitespace().any(|s| s == scope)
+    }
+}
+
+//===============================
+// Relations
+//===============================
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+//===============================
+// Active Model Behavior
+//===============================
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if insert {
+            self.created_at = Set(chrono::Utc::now().into());
+        }
+        self.updated_at = Set(chrono::Utc::now().into());
+        Ok(self)
+    }
+}
+
+//================================
+// Data Transfer Objects (DTOs)
+//================================
+#[derive(Deserialize, Validate)]
+pub struct CreateServiceAccountRequest {
+    #[validate(length(
+        min = 3,
+        max = 64,
+        message = "Name must be between 3 and 64 characters"
+    ))]
+    pub name: String,
+    #[validate(custom(function = "validate_scopes"))]
+    pub scopes: Vec<String>,
+    /// Optionally also accept a client certificate issued for this identity.
+    #[validate(length(min = 1, max = 255, message = "Certificate subject must not be empty"))]
+    pub certificate_subject: Option<String>,
+}
+
+fn validate_scopes(scopes: &[String]) -> Result<(), ValidationError> {
+    if !scopes.is_empty()
+        && scopes
+            .iter()
+            .all(|s| SERVICE_ACCOUNT_SCOPES.contains(&s.as_str()))
+    {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("invalid_scope");
+        err.message = Some(
+            format!(
+                "Scopes must be a non-empty subset of {:?}",
+                SERVICE_ACCOUNT_SCOPES
+            )
+            .into(),
+        );
+        Err(err)
+    }
+}
@@ -0,0 +1,32 @@
+// src/routes/admin_route.rs
+use crate::handlers::admin_user_handler::{
+    delete_user, force_password_reset, get_user, impersonate_user, list_users,
+    revoke_user_sessions, suspend_user, unsuspend_user,
+};
+use crate::handlers::service_account_handler::{
+    create_service_account, list_service_accounts, rotate_service_account_secret,
+};
+use crate::utils::cors::CorsConfig;
+use actix_web::{http::header, middleware::DefaultHeaders, web};
+pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api/v1/admin")
+            .wrap(
+                DefaultHeaders::new()
+                    .add((header::CACHE_CONTROL, "no-store"))
+                    .add((header::PRAGMA, "no-cache")),
+            )
+            .wrap(CorsConfig::for_scope("ADMIN").build())
+            .service(create_service_account)
+            .service(list_service_accounts)
+            .service(rotate_service_account_secret)
+            .service(list_users)
+            .service(get_user)
+            .service(suspend_user)
+            .service(unsuspend_user)
+            .service(force_password_reset)
+            .service(revoke_user_sessions)
+            .service(delete_user)
+            .service(impersonate_user),
+    );
+}
@@ -1,13 +1,122 @@
 // src/routes/auth_route.rs
-use crate::handlers::auth_handler::{index, login, logout, profile, register};
-use actix_web::web;
+use crate::handlers::account_handler::{delete_account, download_export, request_export};
+use crate::handlers::api_key_handler::{create_api_key, list_api_keys, revoke_api_key};
+use crate::handlers::auth_handler::{index, login, logout, register};
+use crate::handlers::password_handler::reset_password;
+use crate::handlers::passwordless_handler::{
+    request_email_otp, request_magic_link, verify_email_code, verify_magic_link,
+};
+use crate::handlers::phone_handler::{login_sms, send_phone_code, set_sms_mfa, verify_phone};
+use crate::handlers::profile_handler::{
+    confirm_email_change, confirm_phone_change, list_impersonations, profile, update_profile,
+};
+use crate::handlers::saml_handler::{saml_acs, saml_login, saml_metadata};
+use crate::handlers::sso_handler::{
+    list_identities, sso_callback, sso_link, sso_login, unlink_identity,
+};
+use crate::utils::cors::CorsConfig;
+use actix_web::{http::header, middleware::DefaultHeaders, web};
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(index);
+    // The IdP sends the browser back with a cross-origin form POST carrying its own Origin,
+    // so the SAML routes stay out of the CORS-checked scope below. Registered first, since
+    // that scope would otherwise claim their paths.
+    cfg.service(
+        web::scope("/api/v1/auth/saml")
+            .wrap(
+                DefaultHeaders::new()
+                    .add((header::CACHE_CONTROL, "no-store"))
+                    .add((header::PRAGMA, "no-cache")),
+            )
+            // SAML responses carry the IdP certificate and easily exceed the 16 KiB default.
+            .app_data(web::FormConfig::default().limit(256 * 1024))
+            .service(saml_metadata)
+            .service(saml_login)
+            .service(saml_acs),
+    );
     cfg.service(
         web::scope("/api/v1/auth")
+            // Credentials and tokens must never be cached by browsers or proxies.
+            .wrap(
+                DefaultHeaders::new()
+                    .add((header::CACHE_CONTROL, "no-store"))
+                    .add((header::PRAGMA, "no-cache")),
+            )
+            .wrap(CorsConfig::for_scope("AUTH").build())
             .service(register)
             .service(login)
+            .service(login_sms)
+            .service(reset_password)
             .service(logout)
-            .service(profile),
+            .service(request_magic_link)
+            .service(verify_magic_link)
+            .service(request_email_otp)
+            .service(verify_email_code)
+            .service(profile)
+            .service(update_profile)
+            .service(list_impersonations)
+            .service(confirm_email_change)
+            .service(confirm_phone_change)
+            .service(delete_account)
+            .service(request_export)
+            .service(download_export)
+            .service(send_phone_code)
+            .service(verify_phone)
+            .service(set_sms_mfa)
+            .service(create_api_key)
+            .service(list_api_keys)
+            .service(revoke_api_key)
+            .service(sso_login)
+            .service(sso_link)
+            .service(sso_callback)
+            .service(list_identities)
+            .service(unlink_identity),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::saml::tests::{idp_key, redis_stand_in, saml_config};
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::{App, test};
+    use sea_orm::{DatabaseBackend, MockDatabase};
+
+    const IDP_ORIGIN: &str = "https://idp.corp.example.com";
+
+    #[actix_web::test]
+    async fn saml_acs_accepts_form_posts_from_the_idp_origin() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres).into_connection();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .app_data(web::Data::new(redis_stand_in().await))
+                .app_data(web::Data::new(saml_config(&idp_key())))
+                .configure(configure_routes),
+        )
+        .await;
+
+        let request = test::TestRequest::post()
+            .uri("/api/v1/auth/saml/corp/acs")
+            .insert_header((header::ORIGIN, IDP_ORIGIN))
+            .set_form([("SAMLResponse", "PHNhbWxwOlJlc3BvbnNlLz4=")])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        // The handler itself turned the (unsigned) response down.
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-store"
+        );
+
+        // The rest of the auth scope still refuses that origin.
+        let request = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/api/v1/auth/login")
+            .insert_header((header::ORIGIN, IDP_ORIGIN))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,6 @@
+// src/routes/health_route.rs
+use crate::handlers::health_handler::{live, ready};
+use actix_web::web;
+pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/health").service(live).service(ready));
+}
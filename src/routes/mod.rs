@@ -1 +1,4 @@
+pub mod admin_route;
 pub mod auth_route;
+pub mod health_route;
+pub mod oauth_route;
@@ -0,0 +1,37 @@
+// src/routes/oauth_route.rs
+use crate::handlers::oauth_handler::{
+    approve, authorize, device_approve, device_authorization, device_lookup, introspect, jwks,
+    list_clients, openid_configuration, register_client, revoke, token, userinfo,
+};
+use crate::utils::cors::CorsConfig;
+use actix_web::{http::header, middleware::DefaultHeaders, web};
+pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/oauth")
+            // Token responses must not be cached (RFC 6749 §5.1).
+            .wrap(
+                DefaultHeaders::new()
+                    .add((header::CACHE_CONTROL, "no-store"))
+                    .add((header::PRAGMA, "no-cache")),
+            )
+            .wrap(CorsConfig::for_scope("OAUTH").build())
+            .service(register_client)
+            .service(list_clients)
+            .service(authorize)
+            .service(approve)
+            .service(token)
+            .service(device_authorization)
+            .service(device_lookup)
+            .service(device_approve)
+            .service(introspect)
+            .service(revoke)
+            .service(userinfo),
+    );
+    // Discovery documents are public and fetched cross-origin by browser-based clients.
+    cfg.service(
+        web::scope("/.well-known")
+            .wrap(CorsConfig::for_scope("OAUTH").build())
+            .service(openid_configuration)
+            .service(jwks),
+    );
+}
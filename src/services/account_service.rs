@@ -0,0 +1,222 @@
+// src/services/account_service.rs
+use crate::models::auth_model::{self, Column, Entity, UserStatus};
+use crate::models::{api_key_model, audit_model, identity_model, oauth_client_model};
+use crate::services::audit_service;
+use crate::utils::client_ip::ClientIp;
+use actix_web::HttpResponse;
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ConnectionTrait, DatabaseConnection, IntoActiveModel, Set, TransactionTrait,
+};
+use serde_json::json;
+use std::fmt;
+use tracing::{error, info};
+
+//===============================
+// Status Transitions
+//===============================
+#[derive(Debug)]
+pub enum StatusError {
+    /// The lifecycle does not allow moving from the first status to the second.
+    Transition(UserStatus, UserStatus),
+    Database(DbErr),
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatusError::Transition(from, to) => write!(
+                f,
+                "cannot change status from {} to {}",
+                from.to_value(),
+                to.to_value()
+            ),
+            StatusError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+/// Move `user` to `status`. `reason` and `until` only apply to suspensions and locks; any other
+/// status clears them. Any change also cancels a deletion the owner scheduled.
+pub async fn change_status<C: ConnectionTrait>(
+    db: &C,
+    user: auth_model::Model,
+    status: UserStatus,
+    reason: Option<String>,
+    until: Option<DateTimeWithTimeZone>,
+) -> Result<auth_model::Model, StatusError> {
+    let current = user.effective_status();
+    if !current.can_become(status) {
+        return Err(StatusError::Transition(current, status));
+    }
+    let (reason, until) = match status {
+        UserStatus::Suspended => (reason, until),
+        UserStatus::Locked => (reason, None),
+        _ => (None, None),
+    };
+
+    let mut active = user.into_active_model();
+    active.status = Set(status);
+    active.status_reason = Set(reason);
+    active.status_until = Set(until);
+    active.purge_after = Set(None);
+    active.update(db).await.map_err(StatusError::Database)
+}
+
+//===============================
+// Account Deletion
+//===============================
+/// Deactivate `user` at their own request; the purge job erases the account after
+/// `purge_after` unless they sign in first.
+pub async fn schedule_deletion<C: ConnectionTrait>(
+    db: &C,
+    user: auth_model::Model,
+    purge_after: DateTimeWithTimeZone,
+) -> Result<auth_model::Model, StatusError> {
+    let user = change_status(db, user, UserStatus::Deactivated, None, None).await?;
+    let mut active = user.into_active_model();
+    active.purge_after = Set(Some(purge_after));
+    active.update(db).await.map_err(StatusError::Database)
+}
+
+/// Call where a sign-in issues its token: signing in during the grace period cancels the
+/// owner's deletion. Other accounts are returned unchanged.
+pub async fn cancel_deletion<C: ConnectionTrait>(
+    db: &C,
+    user: auth_model::Model,
+) -> Result<auth_model::Model, StatusError> {
+    if !user.deletion_pending() {
+        return Ok(user);
+    }
+    let user = change_status(db, user, UserStatus::Active, None, None).await?;
+    info!(
+        "User {} signed in during the grace period; account deletion cancelled",
+        user.username
+    );
+    Ok(user)
+}
+
+/// Erase `user` for good. Linked identities, API keys and OAuth clients are deleted, the IP
+/// addresses in the user's own audit events are dropped, and the row is kept as a `deleted`
+/// tombstone holding only the username, so it cannot be registered again. Runs in its own
+/// transaction, nested as a savepoint when `db` already is one.
+pub async fn purge_account<C: TransactionTrait>(
+    db: &C,
+    user: auth_model::Model,
+) -> Result<(), DbErr> {
+    let txn = db.begin().await?;
+    identity_model::Entity::delete_many()
+        .filter(identity_model::Column::UserId.eq(user.id))
+        .exec(&txn)
+        .await?;
+    api_key_model::Entity::delete_many()
+        .filter(api_key_model::Column::UserId.eq(user.id))
+        .exec(&txn)
+        .await?;
+    oauth_client_model::Entity::delete_many()
+        .filter(oauth_client_model::Column::OwnerId.eq(user.id))
+        .exec(&txn)
+        .await?;
+    audit_model::Entity::update_many()
+        .col_expr(audit_model::Column::Ip, Expr::value(Option::<String>::None))
+        .filter(audit_model::Column::ActorId.eq(user.id))
+        .exec(&txn)
+        .await?;
+    audit_service::record(
+        &txn,
+        &user,
+        "user.purge",
+        Some(user.id),
+        json!({}),
+        ClientIp(None),
+    )
+    .await?;
+
+    let id = user.id;
+    let mut tombstone = user.into_active_model();
+    // `.invalid` is reserved (RFC 2606); the id keeps the unique column unique.
+    tombstone.email = Set(format!("deleted-{}@invalid", id));
+    tombstone.email_verified_at = Set(None);
+    tombstone.phone = Set(String::new());
+    tombstone.phone_verified_at = Set(None);
+    tombstone.pending_email = Set(None);
+    tombstone.pending_phone = Set(None);
+    tombstone.password = Set(None);
+    tombstone.password_reset_required = Set(false);
+    tombstone.sms_mfa_enabled = Set(false);
+    tombstone.status = Set(UserStatus::Deleted);
+    tombstone.status_reason = Set(None);
+    tombstone.status_until = Set(None);
+    tombstone.purge_after = Set(None);
+    tombstone.update(&txn).await?;
+    txn.commit().await
+}
+
+/// Purge every account whose grace period has ended; returns how many were erased. A failure
+/// is logged and the account retried on the next run.
+pub async fn purge_due_accounts(db: &DatabaseConnection) -> Result<usize, DbErr> {
+    let due = Entity::find()
+        .filter(Column::Status.eq(UserStatus::Deactivated))
+        .filter(Column::PurgeAfter.lte(Utc::now()))
+        .all(db)
+        .await?;
+
+    let mut purged = 0;
+    for user in due {
+        let username = user.username.clone();
+        match purge_account(db, user).await {
+            Ok(()) => {
+                info!("Account {} purged after its grace period", username);
+                purged += 1;
+            }
+            Err(e) => error!("Failed to purge account {}: {}", username, e),
+        }
+    }
+    Ok(purged)
+}
+
+//===============================
+// Login Errors
+//===============================
+/// The error to answer a sign-in with once the credentials were accepted, or `None` if the
+/// account may sign in. Only called after authentication, so the status is never revealed to
+/// someone who does not hold the credentials.
+pub fn status_refusal(user: &auth_model::Model) -> Option<HttpResponse> {
+    let status = user.effective_status();
+    match status {
+        UserStatus::Active => None,
+        UserStatus::PendingVerification => Some(HttpResponse::Forbidden().json(json!({
+            "code":403,
+            "message":"Confirm your email address first: sign in with an emailed code or link",
+            "status":status
+        }))),
+        UserStatus::Suspended => Some(HttpResponse::Forbidden().json(json!({
+            "code":403,
+            "message":"Account suspended",
+            "status":status,
+            "reason":user.status_reason,
+            "until":user.status_until
+        }))),
+        UserStatus::Locked => Some(
+            HttpResponse::build(actix_web::http::StatusCode::LOCKED).json(json!({
+                "code":423,
+                "message":"Account locked; contact support",
+                "status":status,
+                "reason":user.status_reason
+            })),
+        ),
+        // The sign-in restores it; see `cancel_deletion`.
+        UserStatus::Deactivated if user.deletion_pending() => None,
+        UserStatus::Deactivated => Some(HttpResponse::Forbidden().json(json!({
+            "code":403,
+            "message":"Account deactivated",
+            "status":status
+        }))),
+        UserStatus::Deleted => Some(
+            HttpResponse::Gone()
+                .json(json!({"code":410,"message":"Account deleted","status":status})),
+        ),
+    }
+}
@@ -0,0 +1,34 @@
+// src/services/audit_service.rs
+use crate::models::audit_model::ActiveModel;
+use crate::models::auth_model;
+use crate::utils::client_ip::ClientIp;
+use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveModelTrait, ConnectionTrait, Set};
+use serde_json::Value;
+
+//===============================
+// Audit Trail
+//===============================
+/// Append an event. Pass the transaction that performs the action, so the action and its
+/// record are committed together or not at all.
+pub async fn record<C: ConnectionTrait>(
+    db: &C,
+    actor: &auth_model::Model,
+    action: &str,
+    target_user_id: Option<i32>,
+    details: Value,
+    client_ip: ClientIp,
+) -> Result<(), DbErr> {
+    ActiveModel {
+        actor_id: Set(Some(actor.id)),
+        actor: Set(actor.username.clone()),
+        action: Set(action.to_string()),
+        target_user_id: Set(target_user_id),
+        details: Set(details),
+        ip: Set(client_ip.0.map(|ip| ip.to_string())),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map(|_| ())
+}
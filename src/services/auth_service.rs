@@ -1 +1,647 @@
+// src/services/auth_service.rs
+use crate::models::auth_model::{self, Column, Entity as User};
+use crate::models::identity_model::{self, Model as Identity};
+use crate::services::identity_service::{available_phone, find_identity};
+use crate::utils::identifier::{
+    canonical_email, canonical_username, normalize_username, username_problem, username_skeleton,
+};
+use crate::utils::ldap::{DirectoryUser, LdapDirectory};
+use crate::utils::phone::normalize_phone;
+use actix_web::web;
+use argon2::password_hash::SaltString;
+use argon2::{
+    Argon2,
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, rand_core::OsRng},
+};
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use sea_orm::{
+    ActiveModelTrait, Condition, DatabaseConnection, IntoActiveModel, PaginatorTrait, Set,
+    TransactionTrait,
+};
+use std::env;
+use std::io;
+use tracing::{debug, error, info, warn};
 
+/// `identities.provider` value for directory accounts.
+const LDAP_PROVIDER: &str = "ldap";
+
+/// Argon2 hash (default parameters) of a discarded random password. Checked when the login
+/// identifier matches no one, so unknown and known identifiers take as long to reject.
+const DUMMY_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$UbBOjFSr5vAjib8/4jguLQ$VW2SI2L2wNfUC50IwXuDJjiBC9aPV8ZNmrYEiHOiIUg";
+
+//===============================
+// Authentication Backends
+//===============================
+#[derive(Debug)]
+pub enum AuthFailure {
+    /// Unknown user or wrong password; the next backend is tried.
+    InvalidCredentials,
+    /// The password was right but the account cannot sign in this way.
+    Refused(&'static str),
+    /// The backend could not answer (database or directory unreachable).
+    Unavailable(String),
+}
+
+/// A way to check a login identifier and password for `/login`, resolving to the local user.
+#[async_trait::async_trait]
+pub trait AuthBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn authenticate(
+        &self,
+        db: &DatabaseConnection,
+        identifier: &str,
+        password: &str,
+    ) -> Result<auth_model::Model, AuthFailure>;
+}
+
+/// Backends tried in order by `/login`, from `AUTH_BACKENDS` (`local`, `ldap`; default `local`).
+pub struct AuthBackends(Vec<Box<dyn AuthBackend>>);
+
+impl AuthBackends {
+    pub fn from_env() -> io::Result<Self> {
+        let names = env::var("AUTH_BACKENDS").unwrap_or_else(|_| "local".to_string());
+        let mut backends: Vec<Box<dyn AuthBackend>> = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match name {
+                "local" => backends.push(Box::new(LocalBackend {
+                    identifiers: LoginIdentifiers::from_env(),
+                })),
+                "ldap" => backends.push(Box::new(LdapBackend {
+                    directory: LdapDirectory::from_env()?,
+                })),
+                other => panic!("AUTH_BACKENDS must list local or ldap, got {}", other),
+            }
+        }
+        if backends.is_empty() {
+            panic!("AUTH_BACKENDS must list at least one backend");
+        }
+        Ok(Self(backends))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn AuthBackend> {
+        self.0.iter().map(|backend| backend.as_ref())
+    }
+}
+
+//===============================
+// Login Identifiers
+//===============================
+/// What the local backend accepts as the login identifier, from `LOGIN_IDENTIFIERS`
+/// (`username`, `email`, `phone`; default `username,email`). Phone numbers must be verified.
+#[derive(Clone, Copy, Debug)]
+pub struct LoginIdentifiers {
+    username: bool,
+    email: bool,
+    phone: bool,
+}
+
+impl LoginIdentifiers {
+    pub fn from_env() -> Self {
+        let names = env::var("LOGIN_IDENTIFIERS").unwrap_or_else(|_| "username,email".to_string());
+        let mut identifiers = Self {
+            username: false,
+            email: false,
+            phone: false,
+        };
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match name {
+                "username" => identifiers.username = true,
+                "email" => identifiers.email = true,
+                "phone" => identifiers.phone = true,
+                other => panic!(
+                    "LOGIN_IDENTIFIERS must list username, email or phone, got {}",
+                    other
+                ),
+            }
+        }
+        if !(identifiers.username || identifiers.email || identifiers.phone) {
+            panic!("LOGIN_IDENTIFIERS must list at least one identifier");
+        }
+        identifiers
+    }
+
+    /// The active user `identifier` names, compared through the same canonical forms as
+    /// registration. Should it match several users, a username wins over an email, and an
+    /// email over a phone number.
+    async fn find_user(
+        &self,
+        db: &DatabaseConnection,
+        identifier: &str,
+    ) -> Result<Option<auth_model::Model>, DbErr> {
+        let username = canonical_username(identifier);
+        let email = canonical_email(identifier);
+        let mut condition = Condition::any();
+        if self.username {
+            condition = condition.add(Column::UsernameCanonical.eq(&username));
+        }
+        if self.email && identifier.contains('@') {
+            condition = condition.add(Column::EmailCanonical.eq(&email));
+        }
+        if self.phone
+            && let Some(phone) = normalize_phone(identifier)
+        {
+            condition = condition.add(
+                Condition::all()
+                    .add(Column::Phone.eq(phone))
+                    .add(Column::PhoneVerifiedAt.is_not_null()),
+            );
+        }
+        if condition.is_empty() {
+            return Ok(None);
+        }
+
+        let users = User::find().filter(condition).all(db).await?;
+        Ok(users.into_iter().min_by_key(|user| {
+            if self.username && user.username_canonical == username {
+                0
+            } else if self.email && user.email_canonical == email {
+                1
+            } else {
+                2
+            }
+        }))
+    }
+}
+
+//===============================
+// Local Argon2 Passwords
+//===============================
+pub struct LocalBackend {
+    identifiers: LoginIdentifiers,
+}
+
+#[async_trait::async_trait]
+impl AuthBackend for LocalBackend {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn authenticate(
+        &self,
+        db: &DatabaseConnection,
+        identifier: &str,
+        password: &str,
+    ) -> Result<auth_model::Model, AuthFailure> {
+        // 1. Database Query (Async I/O Bound)
+        // This runs on the main async thread pool. It yields control while waiting for the DB.
+        let user = match self.identifiers.find_user(db, identifier).await {
+            Ok(user) => user,
+            Err(e) => return Err(AuthFailure::Unavailable(format!("Database error: {}", e))),
+        };
+
+        // 2. Prepare Data for the Blocking Thread
+        // We must clone the data because we are sending it to a separate thread.
+        // Rust requires 'Owned' data to be moved into the closure, as references cannot safe-cross thread boundaries here.
+        // Unknown identifiers and accounts without a password (provisioned from an identity
+        // provider or directory) are checked against the dummy hash, so every rejection costs
+        // the same Argon2 time.
+        let password_input = password.to_string();
+        let password_hash_stored = match user.as_ref().and_then(|user| user.password.clone()) {
+            Some(hash) => hash,
+            None => {
+                debug!("Local login: no password login for {}", identifier);
+                DUMMY_HASH.to_string()
+            }
+        };
+
+        // 3. CPU Intensive Task (Argon2 Verification)
+        // We offload this to `web::block`, which runs on a separate thread pool dedicated to blocking operations.
+        // This prevents the main async worker threads from freezing during the heavy calculation.
+        // Blocking Thread / Sync
+        let verify_result = web::block(move || {
+            // --- Inside Blocking Thread ---
+
+            // 3.1 Parse the stored hash string into a PasswordHash object
+            let parsed_hash = match PasswordHash::new(&password_hash_stored) {
+                Ok(hash) => hash,
+                Err(e) => return Err(format!("Password hash parsing error: {}", e)),
+            };
+
+            // 3.2 Verify the input password against the stored hash
+            match Argon2::default().verify_password(password_input.as_bytes(), &parsed_hash) {
+                Ok(_) => Ok(()),
+                Err(_) => Err("Invalid password".to_string()),
+            }
+        })
+        .await;
+
+        // 4. Handle the Nested Result (Unwrapping the layers)
+        match verify_result {
+            // Outer Layer (Ok) + Inner Layer (Ok): Password verification succeeded.
+            Ok(Ok(())) => match user {
+                Some(user) if user.password_reset_required => Err(AuthFailure::Refused(
+                    "Password reset required; use the code sent to your email",
+                )),
+                user => user.ok_or(AuthFailure::InvalidCredentials),
+            },
+            // Inner Layer (Err): Logic error (Wrong password or Malformed hash).
+            Ok(Err(err_msg)) if err_msg.contains("parsing error") => {
+                Err(AuthFailure::Unavailable(err_msg))
+            }
+            Ok(Err(_)) => {
+                debug!("Local login: invalid password for {}", identifier);
+                Err(AuthFailure::InvalidCredentials)
+            }
+            // Outer Layer (Err): The thread pool failed to execute the task (e.g., Pool overloaded or Cancelled).
+            Err(e) => Err(AuthFailure::Unavailable(format!(
+                "Blocking execution error (Thread pool issue): {}",
+                e
+            ))),
+        }
+    }
+}
+
+/// Whether `password` matches the stored Argon2 `hash`, checked on the blocking pool. Used to
+/// confirm sensitive actions of a signed-in user; logins go through the backends above.
+pub async fn verify_password(password: String, hash: String) -> Result<bool, String> {
+    web::block(move || {
+        let parsed_hash =
+            PasswordHash::new(&hash).map_err(|e| format!("Password hash parsing error: {}", e))?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok())
+    })
+    .await
+    .map_err(|e| format!("Blocking execution error: {}", e))?
+}
+
+/// Argon2 hash of a new password, computed on the blocking pool like the checks above.
+pub async fn hash_password(password: String) -> Result<String, String> {
+    web::block(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| format!("Password hashing error: {}", e))
+    })
+    .await
+    .map_err(|e| format!("Blocking execution error: {}", e))?
+}
+
+//===============================
+// LDAP / Active Directory Bind
+//===============================
+/// Binds against the directory, then provisions or updates the matching local user just in
+/// time. Directory users are linked through `identities` with provider `ldap`.
+pub struct LdapBackend {
+    directory: LdapDirectory,
+}
+
+#[async_trait::async_trait]
+impl AuthBackend for LdapBackend {
+    fn name(&self) -> &'static str {
+        LDAP_PROVIDER
+    }
+
+    async fn authenticate(
+        &self,
+        db: &DatabaseConnection,
+        username: &str,
+        password: &str,
+    ) -> Result<auth_model::Model, AuthFailure> {
+        let entry = match self.directory.authenticate(username, password).await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => {
+                debug!("LDAP login: bind failed for {}", username);
+                return Err(AuthFailure::InvalidCredentials);
+            }
+            Err(e) => return Err(AuthFailure::Unavailable(format!("LDAP error: {}", e))),
+        };
+        let role = self.directory.role_for(&entry.groups);
+
+        let linked = find_identity(db, LDAP_PROVIDER, &entry.subject)
+            .await
+            .map_err(database_error)?;
+        let user = match linked {
+            Some(linked) => update_directory_user(db, linked, entry, role).await?,
+            None => provision_directory_user(db, username, entry, role).await?,
+        };
+        Ok(user)
+    }
+}
+
+fn database_error(e: DbErr) -> AuthFailure {
+    AuthFailure::Unavailable(format!("Database error: {}", e))
+}
+
+/// Copy the directory's email, phone and role onto the linked user.
+async fn update_directory_user(
+    db: &DatabaseConnection,
+    linked: Identity,
+    entry: DirectoryUser,
+    role: Option<auth_model::Role>,
+) -> Result<auth_model::Model, AuthFailure> {
+    let user = User::find_by_id(linked.user_id)
+        .one(db)
+        .await
+        .map_err(database_error)?
+        .ok_or(AuthFailure::InvalidCredentials)?;
+
+    let mut active = user.clone().into_active_model();
+    let mut stored_email = user.email.clone();
+    if let Some(email) = entry.email.clone().filter(|email| *email != user.email) {
+        let taken = User::find()
+            .filter(Column::EmailCanonical.eq(canonical_email(&email)))
+            .filter(Column::Id.ne(user.id))
+            .count(db)
+            .await
+            .map_err(database_error)?;
+        if taken == 0 {
+            active.email = Set(email.clone());
+            stored_email = email;
+        } else {
+            warn!(
+                "Directory email {} of user {} belongs to another account; not updated",
+                email, user.username
+            );
+        }
+    }
+    // The directory only vouches for its own address, not one it could not replace.
+    if user.email_verified_at.is_none() && entry.email.as_deref() == Some(stored_email.as_str()) {
+        active.email_verified_at = Set(Some(Utc::now().into()));
+    }
+    let phone = available_phone(db, entry.phone.as_deref(), Some(user.id))
+        .await
+        .map_err(database_error)?;
+    if let Some(phone) = phone.filter(|phone| *phone != user.phone) {
+        // A new number has to be verified again before it can be a second factor.
+        if user.sms_mfa_enabled {
+            warn!(
+                "Directory changed the phone of user {}; SMS second factor turned off",
+                user.username
+            );
+        }
+        active.phone = Set(phone);
+        active.phone_verified_at = Set(None);
+        active.sms_mfa_enabled = Set(false);
+    }
+    if let Some(role) = role
+        && role != user.role
+    {
+        info!(
+            "User {} role changed from {:?} to {:?} by directory groups",
+            user.username, user.role, role
+        );
+        active.role = Set(role);
+    }
+    let user = if active.is_changed() {
+        active.update(db).await.map_err(database_error)?
+    } else {
+        user
+    };
+
+    let mut identity = linked.into_active_model();
+    identity.email = Set(entry.email);
+    identity.last_login_at = Set(Some(Utc::now().into()));
+    if let Err(e) = identity.update(db).await {
+        error!("Failed to record LDAP sign-in: {}", e);
+    }
+    Ok(user)
+}
+
+/// Create a passwordless user and its `ldap` identity on first sign-in. A local account that
+/// already holds the username or email is never taken over.
+async fn provision_directory_user(
+    db: &DatabaseConnection,
+    username: &str,
+    entry: DirectoryUser,
+    role: Option<auth_model::Role>,
+) -> Result<auth_model::Model, AuthFailure> {
+    let Some(email) = entry.email.clone() else {
+        warn!("Directory entry {} has no email address", entry.dn);
+        return Err(AuthFailure::Refused(
+            "The directory entry has no email address",
+        ));
+    };
+    // The directory accepts whatever the user typed at login; store the NFKC form and apply
+    // the registration rules to it.
+    let username = normalize_username(username);
+    if let Some(problem) = username_problem(&username) {
+        warn!("LDAP provisioning for {} refused: {}", entry.dn, problem);
+        return Err(AuthFailure::Refused(problem));
+    }
+    let taken = User::find()
+        .filter(
+            sea_orm::Condition::any()
+                .add(Column::UsernameCanonical.eq(canonical_username(&username)))
+                .add(Column::UsernameSkeleton.eq(username_skeleton(&username)))
+                .add(Column::EmailCanonical.eq(canonical_email(&email))),
+        )
+        .count(db)
+        .await
+        .map_err(database_error)?;
+    if taken > 0 {
+        warn!(
+            "LDAP provisioning for {} refused: username or email {} already registered",
+            entry.dn, email
+        );
+        return Err(AuthFailure::Refused(
+            "A local account with this username or email already exists",
+        ));
+    }
+
+    let phone = available_phone(db, entry.phone.as_deref(), None)
+        .await
+        .map_err(database_error)?;
+
+    let txn = db.begin().await.map_err(database_error)?;
+    let new_user = auth_model::ActiveModel {
+        username: Set(username),
+        password: Set(None),
+        email: Set(email),
+        email_verified_at: Set(Some(Utc::now().into())),
+        phone: Set(phone.unwrap_or_default()),
+        role: Set(role.unwrap_or(auth_model::Role::User)),
+        ..Default::default()
+    };
+    let user = new_user.insert(&txn).await.map_err(database_error)?;
+    let new_identity = identity_model::ActiveModel {
+        user_id: Set(user.id),
+        provider: Set(LDAP_PROVIDER.to_string()),
+        subject: Set(entry.subject),
+        email: Set(entry.email),
+        last_login_at: Set(Some(Utc::now().into())),
+        ..Default::default()
+    };
+    new_identity.insert(&txn).await.map_err(database_error)?;
+    txn.commit().await.map_err(database_error)?;
+
+    info!(
+        "Provisioned user {} (ID {}) from the directory entry {}",
+        user.username, user.id, entry.dn
+    );
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::auth_model::{Role, UserStatus};
+    use crate::utils::ldap::tests::{JANE_PASSWORD, directory};
+    use sea_orm::{DatabaseBackend, MockDatabase, Value};
+    use std::collections::BTreeMap;
+
+    fn count(n: i64) -> Vec<BTreeMap<&'static str, Value>> {
+        vec![BTreeMap::from([("num_items", Value::BigInt(Some(n)))])]
+    }
+
+    fn user(id: i32, username: &str, role: Role) -> auth_model::Model {
+        let now = Utc::now().into();
+        let email = format!("{}@example.com", username);
+        auth_model::Model {
+            id,
+            username: username.to_string(),
+            username_canonical: canonical_username(username),
+            username_skeleton: username_skeleton(username),
+            password: None,
+            email_canonical: canonical_email(&email),
+            email,
+            email_verified_at: Some(now),
+            phone: String::new(),
+            phone_verified_at: None,
+            pending_email: None,
+            pending_phone: None,
+            password_reset_required: false,
+            sms_mfa_enabled: false,
+            status: UserStatus::Active,
+            status_reason: None,
+            status_until: None,
+            purge_after: None,
+            role,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn identity(user_id: i32) -> Identity {
+        let now = Utc::now().into();
+        Identity {
+            id: 3,
+            user_id,
+            provider: LDAP_PROVIDER.to_string(),
+            subject: "jane".to_string(),
+            email: Some("jane@example.com".to_string()),
+            last_login_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// The SQL `db` ran, with identifier quotes unescaped.
+    fn statements(db: DatabaseConnection) -> String {
+        format!("{:?}", db.into_transaction_log()).replace("\\\"", "\"")
+    }
+
+    async fn backend() -> LdapBackend {
+        LdapBackend {
+            directory: directory().await.0,
+        }
+    }
+
+    #[actix_web::test]
+    async fn provisions_directory_users_with_their_group_role() {
+        let backend = backend().await;
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<Identity>::new()])
+            .append_query_results([count(0)])
+            .append_query_results([vec![user(42, "jane", Role::Admin)]])
+            .append_query_results([vec![identity(42)]])
+            .into_connection();
+
+        let user = backend
+            .authenticate(&db, "jane", JANE_PASSWORD)
+            .await
+            .unwrap();
+        assert_eq!(user.id, 42);
+        let log = statements(db);
+        assert!(log.contains(r#"INSERT INTO "auth_users""#), "{}", log);
+        assert!(log.contains(r#"String(Some("admin"))"#), "{}", log);
+        assert!(log.contains(r#"INSERT INTO "identities""#), "{}", log);
+    }
+
+    #[actix_web::test]
+    async fn never_takes_over_a_local_account() {
+        let backend = backend().await;
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<Identity>::new()])
+            // A local user already holds the username or the email.
+            .append_query_results([count(1)])
+            .into_connection();
+
+        let failure = backend
+            .authenticate(&db, "jane", JANE_PASSWORD)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                failure,
+                AuthFailure::Refused("A local account with this username or email already exists")
+            ),
+            "{:?}",
+            failure
+        );
+        let log = statements(db);
+        assert!(!log.contains("INSERT"), "{}", log);
+    }
+
+    #[actix_web::test]
+    async fn updates_the_role_of_linked_users() {
+        let backend = backend().await;
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![identity(42)]])
+            .append_query_results([vec![user(42, "jane", Role::User)]])
+            .append_query_results([vec![user(42, "jane", Role::Admin)]])
+            .append_query_results([vec![identity(42)]])
+            .into_connection();
+
+        let user = backend
+            .authenticate(&db, "jane", JANE_PASSWORD)
+            .await
+            .unwrap();
+        assert_eq!(user.role, Role::Admin);
+        let log = statements(db);
+        assert!(log.contains(r#"UPDATE "auth_users" SET "role""#), "{}", log);
+        assert!(!log.contains("INSERT"), "{}", log);
+    }
+
+    #[actix_web::test]
+    async fn only_verifies_the_email_the_directory_lists() {
+        let backend = backend().await;
+        let mut unverified = user(42, "jane", Role::Admin);
+        unverified.email = "jane.old@example.com".to_string();
+        unverified.email_verified_at = None;
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![identity(42)]])
+            .append_query_results([vec![unverified]])
+            // Another account holds the directory's address, so the old one stays.
+            .append_query_results([count(1)])
+            .append_query_results([vec![identity(42)]])
+            .into_connection();
+
+        backend
+            .authenticate(&db, "jane", JANE_PASSWORD)
+            .await
+            .unwrap();
+        let log = statements(db);
+        assert!(!log.contains(r#"UPDATE "auth_users""#), "{}", log);
+    }
+
+    #[actix_web::test]
+    async fn wrong_directory_passwords_are_invalid_credentials() {
+        let backend = backend().await;
+        let db = MockDatabase::new(DatabaseBackend::Postgres).into_connection();
+
+        let failure = backend
+            .authenticate(&db, "jane", "wrong")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(failure, AuthFailure::InvalidCredentials),
+            "{:?}",
+            failure
+        );
+        assert_eq!(statements(db), "[]");
+    }
+}
@@ -0,0 +1,75 @@
+// src/services/export_service.rs
+use crate::models::auth_model;
+use crate::models::{api_key_model, audit_model, identity_model, oauth_client_model};
+use crate::utils::oauth::refresh_grants_of;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use sea_orm::entity::prelude::*;
+use sea_orm::{Condition, DatabaseConnection, QueryOrder};
+use serde_json::{Value, json};
+
+//===============================
+// Data Export
+//===============================
+/// Everything held about `user`, as the JSON document of a data export. Secrets (password and
+/// key hashes) are left out. Login tokens are stateless JWTs and not stored, so the sessions
+/// listed are the OAuth refresh tokens still alive.
+pub async fn collect_account_data(
+    db: &DatabaseConnection,
+    redis: &ConnectionManager,
+    user: &auth_model::Model,
+) -> Result<Value, String> {
+    let database = |e: DbErr| format!("Database error: {}", e);
+    let identities = identity_model::Entity::find()
+        .filter(identity_model::Column::UserId.eq(user.id))
+        .order_by_asc(identity_model::Column::Id)
+        .all(db)
+        .await
+        .map_err(database)?;
+    let api_keys = api_key_model::Entity::find()
+        .filter(api_key_model::Column::UserId.eq(user.id))
+        .order_by_asc(api_key_model::Column::Id)
+        .all(db)
+        .await
+        .map_err(database)?;
+    let oauth_clients = oauth_client_model::Entity::find()
+        .filter(oauth_client_model::Column::OwnerId.eq(user.id))
+        .order_by_asc(oauth_client_model::Column::Id)
+        .all(db)
+        .await
+        .map_err(database)?;
+    let audit_events = audit_model::Entity::find()
+        .filter(
+            Condition::any()
+                .add(audit_model::Column::ActorId.eq(user.id))
+                .add(audit_model::Column::TargetUserId.eq(user.id)),
+        )
+        .order_by_asc(audit_model::Column::Id)
+        .all(db)
+        .await
+        .map_err(database)?;
+
+    let sessions: Vec<Value> = refresh_grants_of(redis, &user.username)
+        .await
+        .map_err(|e| format!("Redis error: {}", e))?
+        .into_iter()
+        .map(|(grant, expires_in)| {
+            json!({
+                "client_id":grant.client_id,
+                "scope":grant.scope,
+                "signed_in_at":DateTime::from_timestamp(grant.auth_time, 0),
+                "expires_in":expires_in
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "exported_at":Utc::now(),
+        "account":user,
+        "sessions":sessions,
+        "identities":identities,
+        "api_keys":api_keys,
+        "oauth_clients":oauth_clients,
+        "audit_events":audit_events
+    }))
+}
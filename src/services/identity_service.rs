@@ -0,0 +1,431 @@
+// src/services/identity_service.rs
+use crate::models::auth_model::{self, Entity as User};
+use crate::models::identity_model::{ActiveModel, Column, Entity, Model as Identity};
+use crate::services::account_service::{cancel_deletion, status_refusal};
+use crate::utils::client_ip::ClientIp;
+use crate::utils::crypto::random_hex;
+use crate::utils::identifier::{canonical_email, canonical_username, username_skeleton};
+use crate::utils::jwt::encode_jwt;
+use crate::utils::phone::normalize_phone;
+use actix_web::HttpResponse;
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use sea_orm::{
+    ActiveModelTrait, DatabaseConnection, IntoActiveModel, PaginatorTrait, Set, TransactionTrait,
+};
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+
+//===============================
+// External Identities
+//===============================
+/// An account at an external identity provider (OpenID Connect, OAuth 2.0 or SAML) that
+/// signed in, with the attributes mapped onto `auth_users` when provisioning.
+#[derive(Debug)]
+pub struct ExternalIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub phone: Option<String>,
+}
+
+/// A verified external sign-in. `provider` is the `identities.provider` value.
+pub struct ExternalLogin<'a> {
+    pub provider: &'a str,
+    pub identity: ExternalIdentity,
+    /// Whether an unlinked identity may create a new account.
+    pub may_provision: bool,
+}
+
+pub async fn find_identity(
+    db: &DatabaseConnection,
+    provider: &str,
+    subject: &str,
+) -> Result<Option<Identity>, DbErr> {
+    Entity::find()
+        .filter(Column::Provider.eq(provider))
+        .filter(Column::Subject.eq(subject))
+        .one(db)
+        .await
+}
+
+/// A provider- or directory-supplied number in E.164, or `None` when it does not parse or
+/// already belongs to a user other than `owner`. Such numbers are dropped rather than
+/// failing the sign-in.
+pub async fn available_phone(
+    db: &DatabaseConnection,
+    phone: Option<&str>,
+    owner: Option<i32>,
+) -> Result<Option<String>, DbErr> {
+    let Some(raw) = phone.filter(|phone| !phone.trim().is_empty()) else {
+        return Ok(None);
+    };
+    let Some(phone) = normalize_phone(raw) else {
+        debug!("Ignoring unparseable phone number {}", raw);
+        return Ok(None);
+    };
+    let mut taken = User::find().filter(auth_model::Column::Phone.eq(&phone));
+    if let Some(owner) = owner {
+        taken = taken.filter(auth_model::Column::Id.ne(owner));
+    }
+    if taken.count(db).await? > 0 {
+        warn!("Phone number {} belongs to another account; ignored", phone);
+        return Ok(None);
+    }
+    Ok(Some(phone))
+}
+
+/// Sign in as the user `linked` to the external account, or provision one when allowed,
+/// and issue a login JWT as `/login` does.
+pub async fn sign_in_external(
+    db: &DatabaseConnection,
+    login: ExternalLogin<'_>,
+    linked: Option<Identity>,
+    client_ip: ClientIp,
+) -> HttpResponse {
+    let ExternalLogin {
+        provider,
+        identity,
+        may_provision,
+    } = login;
+    let (user, created) = match linked {
+        Some(linked) => {
+            let user = match User::find_by_id(linked.user_id).one(db).await {
+                Ok(Some(user)) => user,
+                Ok(None) => {
+                    return HttpResponse::Unauthorized()
+                        .json(json!({"code":401,"message":"User no longer exists"}));
+                }
+                Err(e) => {
+                    error!("Database error: {}", e);
+                    return HttpResponse::InternalServerError()
+                        .json(json!({"code":500,"message":"Internal server error"}));
+                }
+            };
+            let mut active = linked.into_active_model();
+            active.email = Set(identity.email);
+            active.last_login_at = Set(Some(Utc::now().into()));
+            if let Err(e) = active.update(db).await {
+                error!("Failed to record external sign-in: {}", e);
+            }
+            (user, false)
+        }
+        None => {
+            if !may_provision {
+                warn!(
+                    "Sign-in with unlinked {} identity {} from IP {}",
+                    provider, identity.subject, client_ip
+                );
+                return HttpResponse::Forbidden().json(json!({
+                    "code":403,
+                    "message":"No account is linked to this identity; sign in and link it first"
+                }));
+            }
+            match provision(db, provider, identity).await {
+                Ok(user) => (user, true),
+                Err(response) => return response,
+            }
+        }
+    };
+
+    if let Some(refusal) = status_refusal(&user) {
+        warn!(
+            "External sign-in for {} user {}",
+            user.status.to_value(),
+            user.username
+        );
+        return refusal;
+    }
+    // Signing in during the grace period cancels a deletion the owner asked for.
+    let user = match cancel_deletion(db, user).await {
+        Ok(user) => user,
+        Err(e) => {
+            error!("Failed to restore account: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}));
+        }
+    };
+
+    match encode_jwt(user.username.clone()) {
+        Ok(token) => {
+            info!(
+                "User {} signed in with {} from IP {}",
+                user.username, provider, client_ip
+            );
+            if created {
+                HttpResponse::Created()
+                    .json(json!({"code":201,"message":"Account created","token":token}))
+            } else {
+                HttpResponse::Ok()
+                    .json(json!({"code":200,"message":"login successful","token":token}))
+            }
+        }
+        Err(e) => {
+            error!("JWT encoding error for user {}: {}", user.username, e);
+            HttpResponse::InternalServerError()
+                .json(json!({"code":500,"message":"Internal server error"}))
+        }
+    }
+}
+
+/// Just-in-time provisioning: a passwordless user plus the identity, in one transaction.
+/// Never attaches to an existing account by email, since the provider's claim alone does not
+/// prove the local account belongs to the same person.
+async fn provision(
+    db: &DatabaseConnection,
+    provider: &str,
+    identity: ExternalIdentity,
+) -> Result<auth_model::Model, HttpResponse> {
+    let internal_error = |e: DbErr| {
+        error!("Database error during provisioning: {}", e);
+        HttpResponse::InternalServerError()
+            .json(json!({"code":500,"message":"Internal server error"}))
+    };
+
+    let Some(email) = identity.email.clone() else {
+        return Err(HttpResponse::Forbidden().json(
+            json!({"code":403,"message":"The identity provider did not share an email address"}),
+        ));
+    };
+    let email_taken = User::find()
+        .filter(auth_model::Column::EmailCanonical.eq(canonical_email(&email)))
+        .count(db)
+        .await
+        .map_err(internal_error)?;
+    if email_taken > 0 {
+        warn!(
+            "Provisioning from {} refused: email {} already registered",
+            provider, email
+        );
+        return Err(HttpResponse::Conflict().json(json!({
+            "code":409,
+            "message":"An account with this email already exists; sign in and link the identity"
+        })));
+    }
+    let username = available_username(db, &identity, &email)
+        .await
+        .map_err(internal_error)?;
+    let phone = available_phone(db, identity.phone.as_deref(), None)
+        .await
+        .map_err(internal_error)?;
+
+    let txn = db.begin().await.map_err(internal_error)?;
+    let new_user = auth_model::ActiveModel {
+        username: Set(username),
+        password: Set(None),
+        email: Set(email),
+        email_verified_at: Set(identity.email_verified.then(|| Utc::now().into())),
+        phone: Set(phone.unwrap_or_default()),
+        ..Default::default()
+    };
+    let user = new_user.insert(&txn).await.map_err(internal_error)?;
+    let new_identity = ActiveModel {
+        user_id: Set(user.id),
+        provider: Set(provider.to_string()),
+        subject: Set(identity.subject),
+        email: Set(identity.email),
+        last_login_at: Set(Some(Utc::now().into())),
+        ..Default::default()
+    };
+    new_identity.insert(&txn).await.map_err(internal_error)?;
+    txn.commit().await.map_err(internal_error)?;
+
+    info!(
+        "Provisioned user {} (ID {}) from {}",
+        user.username, user.id, provider
+    );
+    Ok(user)
+}
+
+/// Username derived from the provider's username or the email local part, restricted to
+/// `[A-Za-z0-9._-]` and suffixed with random hex until it is free.
+async fn available_username(
+    db: &DatabaseConnection,
+    identity: &ExternalIdentity,
+    email: &str,
+) -> Result<String, DbErr> {
+    let source = identity
+        .preferred_username
+        .as_deref()
+        .unwrap_or_else(|| email.split('@').next().unwrap_or_default());
+    let mut base: String = source
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        .take(24)
+        .collect();
+    if base.len() < 3 {
+        base = "user".to_string();
+    }
+
+    let mut candidate = base.clone();
+    loop {
+        let taken = User::find()
+            .filter(
+                sea_orm::Condition::any()
+                    .add(auth_model::Column::UsernameCanonical.eq(canonical_username(&candidate)))
+                    .add(auth_model::Column::UsernameSkeleton.eq(username_skeleton(&candidate))),
+            )
+            .count(db)
+            .await?;
+        if taken == 0 {
+            return Ok(candidate);
+        }
+        candidate = format!("{}-{}", base, random_hex(2));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::auth_model::{Role, UserStatus};
+    use actix_web::http::StatusCode;
+    use sea_orm::{DatabaseBackend, MockDatabase, Value};
+    use std::collections::BTreeMap;
+    use std::sync::Once;
+
+    fn set_jwt_secret() {
+        static INIT: Once = Once::new();
+        // SAFETY: set once, before any test reads it, and never changed afterwards.
+        INIT.call_once(|| unsafe { std::env::set_var("JWT_SECRET", "test-jwt-secret") });
+    }
+
+    fn count(n: i64) -> Vec<BTreeMap<&'static str, Value>> {
+        vec![BTreeMap::from([("num_items", Value::BigInt(Some(n)))])]
+    }
+
+    fn user(id: i32, username: &str, email: &str) -> auth_model::Model {
+        let now = Utc::now().into();
+        auth_model::Model {
+            id,
+            username: username.to_string(),
+            username_canonical: canonical_username(username),
+            username_skeleton: username_skeleton(username),
+            password: None,
+            email: email.to_string(),
+            email_canonical: canonical_email(email),
+            email_verified_at: Some(now),
+            phone: String::new(),
+            phone_verified_at: None,
+            pending_email: None,
+            pending_phone: None,
+            password_reset_required: false,
+            sms_mfa_enabled: false,
+            status: UserStatus::Active,
+            status_reason: None,
+            status_until: None,
+            purge_after: None,
+            role: Role::User,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn linked_identity(user_id: i32) -> Identity {
+        let now = Utc::now().into();
+        Identity {
+            id: 7,
+            user_id,
+            provider: "mock".to_string(),
+            subject: "248289761001".to_string(),
+            email: Some("jane@example.com".to_string()),
+            last_login_at: Some(now),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn login(may_provision: bool) -> ExternalLogin<'static> {
+        ExternalLogin {
+            provider: "mock",
+            identity: ExternalIdentity {
+                subject: "248289761001".to_string(),
+                email: Some("jane@example.com".to_string()),
+                email_verified: true,
+                preferred_username: Some("jane".to_string()),
+                phone: None,
+            },
+            may_provision,
+        }
+    }
+
+    fn client_ip() -> ClientIp {
+        ClientIp(Some("203.0.113.7".parse().unwrap()))
+    }
+
+    /// The SQL `db` ran, with identifier quotes unescaped.
+    fn statements(db: DatabaseConnection) -> String {
+        format!("{:?}", db.into_transaction_log()).replace("\\\"", "\"")
+    }
+
+    #[actix_web::test]
+    async fn provisions_a_user_on_first_sign_in() {
+        set_jwt_secret();
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            // Email and username are free
+            .append_query_results([count(0)])
+            .append_query_results([count(0)])
+            .append_query_results([vec![user(42, "jane", "jane@example.com")]])
+            .append_query_results([vec![linked_identity(42)]])
+            .into_connection();
+
+        let response = sign_in_external(&db, login(true), None, client_ip()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let log = statements(db);
+        assert!(log.contains(r#"INSERT INTO "auth_users""#), "{}", log);
+        assert!(log.contains(r#"INSERT INTO "identities""#), "{}", log);
+    }
+
+    #[actix_web::test]
+    async fn refuses_to_provision_over_an_existing_email() {
+        set_jwt_secret();
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([count(1)])
+            .into_connection();
+
+        let response = sign_in_external(&db, login(true), None, client_ip()).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let log = statements(db);
+        assert!(!log.contains("INSERT"), "{}", log);
+    }
+
+    #[actix_web::test]
+    async fn refuses_unlinked_identities_without_provisioning() {
+        let db = MockDatabase::new(DatabaseBackend::Postgres).into_connection();
+
+        let response = sign_in_external(&db, login(false), None, client_ip()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(statements(db), "[]");
+    }
+
+    #[actix_web::test]
+    async fn signs_in_the_linked_user() {
+        set_jwt_secret();
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![user(42, "jane", "jane@example.com")]])
+            .append_query_results([vec![linked_identity(42)]])
+            .into_connection();
+
+        let response =
+            sign_in_external(&db, login(false), Some(linked_identity(42)), client_ip()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let log = statements(db);
+        assert!(!log.contains(r#"INSERT INTO "auth_users""#), "{}", log);
+        assert!(log.contains(r#"UPDATE "identities""#), "{}", log);
+    }
+
+    #[actix_web::test]
+    async fn refuses_linked_users_that_cannot_sign_in() {
+        set_jwt_secret();
+        let mut suspended = user(42, "jane", "jane@example.com");
+        suspended.status = UserStatus::Suspended;
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![suspended]])
+            .append_query_results([vec![linked_identity(42)]])
+            .into_connection();
+
+        let response =
+            sign_in_external(&db, login(false), Some(linked_identity(42)), client_ip()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -1 +1,5 @@
+pub mod account_service;
+pub mod audit_service;
 pub mod auth_service;
+pub mod export_service;
+pub mod identity_service;
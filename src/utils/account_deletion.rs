@@ -0,0 +1,62 @@
+// src/utils/account_deletion.rs
+use crate::services::account_service::purge_due_accounts;
+use crate::utils::shutdown::ShutdownSignal;
+use sea_orm::DatabaseConnection;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+//===============================
+// Account Deletion Configuration
+//===============================
+/// Self-service deletion: the account is deactivated at once and purged after a grace period.
+#[derive(Clone, Debug)]
+pub struct AccountDeletionConfig {
+    /// Seconds between the request and the purge; signing in meanwhile cancels it.
+    pub grace_period: u64,
+    /// Seconds between two runs of the purge job.
+    pub purge_interval: u64,
+}
+
+impl AccountDeletionConfig {
+    pub fn from_env() -> Self {
+        Self {
+            grace_period: seconds("ACCOUNT_DELETION_GRACE_PERIOD", 30 * 86400),
+            purge_interval: seconds("ACCOUNT_PURGE_INTERVAL", 3600),
+        }
+    }
+}
+
+fn seconds(key: &str, default: u64) -> u64 {
+    env::var(key)
+        .map(|v| {
+            v.parse()
+                .unwrap_or_else(|_| panic!("{} must be a number of seconds", key))
+        })
+        .unwrap_or(default)
+}
+
+//===============================
+// Purge Worker
+//===============================
+/// Erase accounts whose grace period has ended, every `purge_interval` seconds until shutdown.
+pub async fn purge_deleted_accounts(
+    db: Arc<DatabaseConnection>,
+    purge_interval: u64,
+    mut shutdown: ShutdownSignal,
+) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(purge_interval.max(1)));
+    loop {
+        tokio::select! {
+            _ = shutdown.triggered() => break,
+            _ = ticker.tick() => (),
+        }
+
+        match purge_due_accounts(db.as_ref()).await {
+            Ok(0) => (),
+            Ok(purged) => info!("Purged {} deleted accounts", purged),
+            Err(e) => error!("Account purge failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+// src/utils/account_export.rs
+use crate::utils::crypto::{random_hex, sha256_hex};
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use std::env;
+
+/// Stored under a download token until the background job replaces it with the archive.
+const PENDING: &str = "pending";
+
+//===============================
+// Account Export Configuration
+//===============================
+/// Data subject access requests: an archive of everything held about the caller, built in the
+/// background and downloaded once through a link that expires.
+#[derive(Clone, Debug)]
+pub struct AccountExportConfig {
+    /// Seconds the download link stays valid.
+    pub link_ttl: u64,
+    /// Minimum seconds between two exports of the same account.
+    pub cooldown: u64,
+}
+
+impl AccountExportConfig {
+    pub fn from_env() -> Self {
+        Self {
+            link_ttl: seconds("ACCOUNT_EXPORT_TTL", 86400),
+            cooldown: seconds("ACCOUNT_EXPORT_COOLDOWN", 3600),
+        }
+    }
+}
+
+fn seconds(key: &str, default: u64) -> u64 {
+    env::var(key)
+        .map(|v| {
+            v.parse()
+                .unwrap_or_else(|_| panic!("{} must be a number of seconds", key))
+        })
+        .unwrap_or(default)
+}
+
+//===============================
+// Download Links
+//===============================
+pub enum ExportState {
+    /// Unknown, expired or already downloaded.
+    Missing,
+    Pending,
+    Ready(String),
+}
+
+fn export_key(token: &str) -> String {
+    format!("account_export:{}", sha256_hex(token))
+}
+
+/// Reserve a download token while the archive is built. Only its hash is kept.
+pub async fn start_export(redis: &ConnectionManager, ttl: u64) -> redis::RedisResult<String> {
+    let token = random_hex(32);
+    let mut conn = redis.clone();
+    conn.set_ex::<_, _, ()>(export_key(&token), PENDING, ttl)
+        .await?;
+    Ok(token)
+}
+
+/// Store the finished archive; the link stays valid `ttl` seconds from now.
+pub async fn finish_export(
+    redis: &ConnectionManager,
+    token: &str,
+    archive: &str,
+    ttl: u64,
+) -> redis::RedisResult<()> {
+    let mut conn = redis.clone();
+    conn.set_ex(export_key(token), archive, ttl).await
+}
+
+/// Drop a link whose archive could not be built.
+pub async fn discard_export(redis: &ConnectionManager, token: &str) -> redis::RedisResult<()> {
+    let mut conn = redis.clone();
+    conn.del(export_key(token)).await
+}
+
+/// Hand out the archive once. `GETDEL` keeps the link single use under concurrent downloads.
+pub async fn take_export(
+    redis: &ConnectionManager,
+    token: &str,
+) -> redis::RedisResult<ExportState> {
+    let key = export_key(token);
+    let mut conn = redis.clone();
+    let stored: Option<String> = conn.get(&key).await?;
+    match stored.as_deref() {
+        None => return Ok(ExportState::Missing),
+        Some(PENDING) => return Ok(ExportState::Pending),
+        Some(_) => (),
+    }
+    let archive: Option<String> = conn.get_del(&key).await?;
+    Ok(archive.map_or(ExportState::Missing, ExportState::Ready))
+}
@@ -0,0 +1,34 @@
+// src/utils/api_key.rs
+use crate::utils::crypto::{random_hex, sha256_hex};
+
+/// Every personal API key starts with this marker, which tells it apart from a JWT
+/// in the `Authorization: Bearer` header.
+pub const API_KEY_MARKER: &str = "ak_";
+
+pub struct GeneratedApiKey {
+    /// Full key, returned to the user exactly once.
+    pub key: String,
+    pub prefix: String,
+    pub hash: String,
+}
+
+/// Generate a key of the form `ak_<prefix>_<secret>`.
+pub fn generate_api_key() -> GeneratedApiKey {
+    let prefix = random_hex(6);
+    let secret = random_hex(32);
+    let key = format!("{}{}_{}", API_KEY_MARKER, prefix, secret);
+    let hash = hash_api_key(&key);
+    GeneratedApiKey { key, prefix, hash }
+}
+
+/// Keys carry 256 bits of randomness, so a fast hash is enough; no salt or work factor needed.
+pub fn hash_api_key(key: &str) -> String {
+    sha256_hex(key)
+}
+
+/// Extract the lookup prefix from a presented key.
+pub fn api_key_prefix(key: &str) -> Option<&str> {
+    let rest = key.strip_prefix(API_KEY_MARKER)?;
+    let (prefix, secret) = rest.split_once('_')?;
+    (!prefix.is_empty() && !secret.is_empty()).then_some(prefix)
+}
@@ -1,17 +1,68 @@
 // src/utils/auth_middleware.rs
-use crate::utils::jwt::decode_jwt;
+use crate::models::api_key_model::{Column as ApiKeyColumn, Entity as ApiKey};
+use crate::models::auth_model::{self, Column as UserColumn, Entity as User, Role};
+use crate::models::service_account_model::{
+    Column as ServiceAccountColumn, Entity as ServiceAccount,
+};
+use crate::services::audit_service;
+use crate::utils::api_key::{API_KEY_MARKER, api_key_prefix, hash_api_key};
+use crate::utils::client_ip::ClientIp;
+use crate::utils::crypto::constant_time_eq;
+use crate::utils::jwt::{Actor, Claims, decode_jwt};
+use crate::utils::revocation::{is_jwt_revoked, is_user_token_revoked};
+use crate::utils::tls::PeerCertificate;
 use actix_web::{
-    Error, FromRequest, HttpRequest, dev::Payload, error::ErrorInternalServerError,
-    error::ErrorUnauthorized, web,
+    Error, FromRequest, HttpRequest, dev::Payload, error::ErrorForbidden,
+    error::ErrorInternalServerError, error::ErrorUnauthorized, web,
 };
+use chrono::{Duration, Utc};
 use futures::future::LocalBoxFuture;
-use redis::AsyncCommands;
 use redis::aio::ConnectionManager;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter,
+    Set,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, warn};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrincipalKind {
+    /// A human user authenticated with a Bearer JWT or one of their API keys.
+    User,
+    /// A service account authenticated with a verified client certificate
+    /// or a client-credentials access token.
+    Service,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthenticatedUser {
+    /// Username for users, account name for service accounts.
     pub username: String,
+    pub kind: PrincipalKind,
+    /// Scopes granted to the credential (API key or OAuth access token);
+    /// `None` means unrestricted (password login).
+    pub scopes: Option<Vec<String>>,
+    /// Issue time of the presented JWT; for a password-login token this is when the user signed in.
+    pub issued_at: Option<i64>,
+    /// Administrator using an impersonation token to act as this user.
+    pub impersonator: Option<String>,
+}
+
+impl AuthenticatedUser {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.iter().any(|s| s == scope),
+            None => true,
+        }
+    }
+
+    /// Signed in with the user's own password login: not a service, a delegated credential
+    /// (API key, OAuth token) or an administrator impersonating the user.
+    pub fn is_full_login(&self) -> bool {
+        self.kind == PrincipalKind::User && self.scopes.is_none() && self.impersonator.is_none()
+    }
 }
 
 impl FromRequest for AuthenticatedUser {
@@ -24,11 +75,19 @@ impl FromRequest for AuthenticatedUser {
             .get("Authorization")
             .map(|header| header.to_owned());
         let redis = req.app_data::<web::Data<ConnectionManager>>().cloned();
+        let db = req.app_data::<web::Data<DatabaseConnection>>().cloned();
+        let peer_certificate = req.conn_data::<PeerCertificate>().cloned();
+        let client_ip = ClientIp::resolve(req);
+        let request_line = json!({"method": req.method().as_str(), "path": req.path()});
 
         Box::pin(async move {
-            let auth_header = match auth_header {
-                Some(header) => header,
-                None => return Err(ErrorUnauthorized("Authorization header missing")),
+            let auth_header = match (auth_header, peer_certificate) {
+                (Some(header), _) => header,
+                // Without a Bearer token, fall back to the verified client certificate.
+                (None, Some(certificate)) => {
+                    return authenticate_certificate(&certificate, db).await;
+                }
+                (None, None) => return Err(ErrorUnauthorized("Authorization header missing")),
             };
 
             let auth_str = match auth_header.to_str() {
@@ -41,6 +100,10 @@ impl FromRequest for AuthenticatedUser {
             }
 
             let token = &auth_str[7..]; // Skip "Bearer "
+            if token.starts_with(API_KEY_MARKER) {
+                return authenticate_api_key(token, db, client_ip).await;
+            }
+
             let claims = match decode_jwt(token) {
                 Ok(claims) => claims,
                 Err(_) => return Err(ErrorUnauthorized("Invalid or expired token")),
@@ -51,9 +114,7 @@ impl FromRequest for AuthenticatedUser {
                 None => return Err(ErrorInternalServerError("Redis not configured")),
             };
 
-            let mut conn = redis.get_ref().clone();
-            let blacklist_key = format!("bl:{}", token);
-            let is_blacklisted: bool = match conn.exists(blacklist_key).await {
+            let is_blacklisted = match is_jwt_revoked(redis.get_ref(), token).await {
                 Ok(res) => res,
                 Err(_) => return Err(ErrorInternalServerError("Redis query failed")),
             };
@@ -62,9 +123,329 @@ impl FromRequest for AuthenticatedUser {
                 return Err(ErrorUnauthorized("Token revoked"));
             }
 
+            let kind = claims.kind.unwrap_or(PrincipalKind::User);
+            if kind == PrincipalKind::User {
+                match is_user_token_revoked(redis.get_ref(), &claims.sub, claims.iat as i64).await {
+                    Ok(false) => (),
+                    Ok(true) => return Err(ErrorUnauthorized("Token revoked")),
+                    Err(_) => return Err(ErrorInternalServerError("Redis query failed")),
+                }
+            }
+
+            let impersonator = match &claims.act {
+                Some(actor) => Some(
+                    check_impersonation(actor, &claims, &redis, db, client_ip, request_line)
+                        .await?,
+                ),
+                None => None,
+            };
+
             Ok(AuthenticatedUser {
                 username: claims.sub,
+                kind,
+                // OAuth access tokens are limited to their granted scopes.
+                scopes: claims
+                    .scope
+                    .map(|scope| scope.split_whitespace().map(str::to_string).collect()),
+                issued_at: Some(claims.iat as i64),
+                impersonator,
             })
         })
     }
 }
+
+/// An active user with the admin role, signed in with a password login. The role is read
+/// from the database on every request, so revoking it takes effect immediately.
+pub struct AdminUser(pub auth_model::Model);
+
+impl FromRequest for AdminUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let authenticated = AuthenticatedUser::from_request(req, payload);
+        let db = req.app_data::<web::Data<DatabaseConnection>>().cloned();
+
+        Box::pin(async move {
+            let user = authenticated.await?;
+            // Delegated credentials (API keys, OAuth tokens, impersonation) never carry admin rights.
+            if !user.is_full_login() {
+                return Err(ErrorForbidden("Admin access requires a password login"));
+            }
+
+            let db = match db {
+                Some(db) => db,
+                None => return Err(ErrorInternalServerError("Database not configured")),
+            };
+            match User::find()
+                .filter(UserColumn::Username.eq(&user.username))
+                .filter(auth_model::can_sign_in())
+                .filter(UserColumn::Role.eq(Role::Admin))
+                .one(db.get_ref())
+                .await
+            {
+                Ok(Some(admin)) => Ok(AdminUser(admin)),
+                Ok(None) => {
+                    warn!("User {} denied access to an admin endpoint", user.username);
+                    Err(ErrorForbidden("Admin role required"))
+                }
+                Err(e) => {
+                    error!("Database error: {}", e);
+                    Err(ErrorInternalServerError("Database query failed"))
+                }
+            }
+        })
+    }
+}
+
+/// Map a client certificate onto an active service account by its SANs or subject CN.
+async fn authenticate_certificate(
+    certificate: &PeerCertificate,
+    db: Option<web::Data<DatabaseConnection>>,
+) -> Result<AuthenticatedUser, Error> {
+    let db = match db {
+        Some(db) => db,
+        None => return Err(ErrorInternalServerError("Database not configured")),
+    };
+
+    let identities = certificate.identities();
+    if identities.is_empty() {
+        return Err(ErrorUnauthorized(
+            "Client certificate has no usable identity",
+        ));
+    }
+
+    let accounts = match ServiceAccount::find()
+        .filter(ServiceAccountColumn::CertificateSubject.is_in(identities.clone()))
+        .filter(ServiceAccountColumn::Active.eq(true))
+        .all(db.get_ref())
+        .await
+    {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            error!("Database error: {}", e);
+            return Err(ErrorInternalServerError("Database query failed"));
+        }
+    };
+    // A certificate naming several service accounts identifies none of them.
+    let account = match <[_; 1]>::try_from(accounts) {
+        Ok([account]) => account,
+        Err(accounts) => {
+            warn!(
+                "Client certificate {:?} matches {} active service accounts",
+                identities,
+                accounts.len()
+            );
+            return Err(ErrorUnauthorized("Unknown client certificate"));
+        }
+    };
+
+    Ok(AuthenticatedUser {
+        username: account.name,
+        kind: PrincipalKind::Service,
+        scopes: None,
+        issued_at: None,
+        impersonator: None,
+    })
+}
+
+/// Resolve a personal API key to its owner, enforcing revocation and expiry.
+async fn authenticate_api_key(
+    key: &str,
+    db: Option<web::Data<DatabaseConnection>>,
+    client_ip: ClientIp,
+) -> Result<AuthenticatedUser, Error> {
+    let db = match db {
+        Some(db) => db,
+        None => return Err(ErrorInternalServerError("Database not configured")),
+    };
+
+    let prefix = match api_key_prefix(key) {
+        Some(prefix) => prefix,
+        None => return Err(ErrorUnauthorized("Invalid API key")),
+    };
+
+    let found = ApiKey::find()
+        .filter(ApiKeyColumn::Prefix.eq(prefix))
+        .find_also_related(User)
+        .filter(auth_model::can_sign_in())
+        .one(db.get_ref())
+        .await;
+
+    let (api_key, user) = match found {
+        Ok(Some((api_key, Some(user)))) => (api_key, user),
+        Ok(_) => return Err(ErrorUnauthorized("Invalid API key")),
+        Err(e) => {
+            error!("Database error: {}", e);
+            return Err(ErrorInternalServerError("Database query failed"));
+        }
+    };
+
+    let now = Utc::now();
+    if !constant_time_eq(&api_key.key_hash, &hash_api_key(key)) {
+        warn!("API key {} presented with a wrong secret", prefix);
+        return Err(ErrorUnauthorized("Invalid API key"));
+    }
+    if api_key.revoked_at.is_some() {
+        return Err(ErrorUnauthorized("API key revoked"));
+    }
+    if api_key.expires_at.is_some_and(|exp| exp < now) {
+        return Err(ErrorUnauthorized("API key expired"));
+    }
+
+    // Record usage at most once a minute to keep hot keys from writing on every request.
+    let stale = api_key
+        .last_used_at
+        .is_none_or(|used| now.signed_duration_since(used) > Duration::minutes(1));
+    let scopes = api_key.scope_list();
+    if stale {
+        let mut usage = api_key.into_active_model();
+        usage.last_used_at = Set(Some(now.into()));
+        usage.last_used_ip = Set(client_ip.0.map(|ip| ip.to_string()));
+        if let Err(e) = usage.update(db.get_ref()).await {
+            warn!("Failed to record API key usage: {}", e);
+        }
+    }
+
+    Ok(AuthenticatedUser {
+        username: user.username,
+        kind: PrincipalKind::User,
+        scopes: Some(scopes),
+        issued_at: None,
+        impersonator: None,
+    })
+}
+
+/// An impersonation token stays valid only while the administrator behind it is an active
+/// admin with live sessions, and every request made with it lands in the audit trail.
+async fn check_impersonation(
+    actor: &Actor,
+    claims: &Claims,
+    redis: &ConnectionManager,
+    db: Option<web::Data<DatabaseConnection>>,
+    client_ip: ClientIp,
+    request_line: serde_json::Value,
+) -> Result<String, Error> {
+    let db = match db {
+        Some(db) => db,
+        None => return Err(ErrorInternalServerError("Database not configured")),
+    };
+
+    let admin = match User::find()
+        .filter(UserColumn::Username.eq(&actor.sub))
+        .filter(auth_model::can_sign_in())
+        .filter(UserColumn::Role.eq(Role::Admin))
+        .one(db.get_ref())
+        .await
+    {
+        Ok(Some(admin)) => admin,
+        Ok(None) => {
+            warn!(
+                "Impersonation token of {} for {} refused: no longer an active admin",
+                actor.sub, claims.sub
+            );
+            return Err(ErrorUnauthorized("Token revoked"));
+        }
+        Err(e) => {
+            error!("Database error: {}", e);
+            return Err(ErrorInternalServerError("Database query failed"));
+        }
+    };
+    // Ending the administrator's own sessions ends their impersonations too.
+    match is_user_token_revoked(redis, &admin.username, claims.iat as i64).await {
+        Ok(false) => (),
+        Ok(true) => return Err(ErrorUnauthorized("Token revoked")),
+        Err(_) => return Err(ErrorInternalServerError("Redis query failed")),
+    }
+
+    let target = match User::find()
+        .filter(UserColumn::Username.eq(&claims.sub))
+        .one(db.get_ref())
+        .await
+    {
+        Ok(Some(target)) => target,
+        Ok(None) => return Err(ErrorUnauthorized("User no longer exists")),
+        Err(e) => {
+            error!("Database error: {}", e);
+            return Err(ErrorInternalServerError("Database query failed"));
+        }
+    };
+    if let Err(e) = audit_service::record(
+        db.get_ref(),
+        &admin,
+        "impersonation.request",
+        Some(target.id),
+        request_line,
+        client_ip,
+    )
+    .await
+    {
+        error!("Failed to record audit event: {}", e);
+        return Err(ErrorInternalServerError("Audit log unavailable"));
+    }
+    Ok(admin.username)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::service_account_model::Model as ServiceAccountModel;
+    use rcgen::generate_simple_self_signed;
+    use sea_orm::{DatabaseBackend, MockDatabase};
+
+    fn account(id: i32, name: &str, subject: &str) -> ServiceAccountModel {
+        let now = chrono::Utc::now().into();
+        ServiceAccountModel {
+            id,
+            name: name.to_string(),
+            certificate_subject: Some(subject.to_string()),
+            client_id: None,
+            client_secret_hash: None,
+            allowed_scopes: String::new(),
+            active: true,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// A certificate with the DNS SAN `billing.internal` and the common name
+    /// `rcgen self signed cert`.
+    fn certificate() -> PeerCertificate {
+        let key = generate_simple_self_signed(vec!["billing.internal".to_string()]).unwrap();
+        PeerCertificate(key.cert.der().clone())
+    }
+
+    fn database(accounts: Vec<ServiceAccountModel>) -> web::Data<DatabaseConnection> {
+        web::Data::new(
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([accounts])
+                .into_connection(),
+        )
+    }
+
+    #[actix_web::test]
+    async fn certificates_sign_in_their_service_account() {
+        let db = database(vec![account(1, "billing", "billing.internal")]);
+        let user = authenticate_certificate(&certificate(), Some(db))
+            .await
+            .unwrap();
+        assert_eq!(user.username, "billing");
+        assert!(matches!(user.kind, PrincipalKind::Service));
+    }
+
+    #[actix_web::test]
+    async fn refuses_certificates_matching_several_service_accounts() {
+        // One account registered under the SAN, another under the common name
+        let db = database(vec![
+            account(1, "billing", "billing.internal"),
+            account(2, "reports", "rcgen self signed cert"),
+        ]);
+        let error = authenticate_certificate(&certificate(), Some(db))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.as_response_error().status_code(),
+            actix_web::http::StatusCode::UNAUTHORIZED
+        );
+    }
+}
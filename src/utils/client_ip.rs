@@ -0,0 +1,155 @@
+// src/utils/client_ip.rs
+use actix_web::{
+    Error, FromRequest, HttpRequest,
+    dev::Payload,
+    http::header::{self, HeaderMap},
+    web,
+};
+use futures::future::{Ready, ready};
+use ipnet::IpNet;
+use serde::Serialize;
+use std::env;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+//===============================
+// Trusted Proxy Configuration
+//===============================
+/// Networks whose forwarding headers are believed. Configured with `TRUSTED_PROXIES`
+/// as a comma-separated list of CIDRs or bare addresses.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    pub fn from_env() -> Self {
+        let networks = env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                entry
+                    .parse::<IpNet>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                    .unwrap_or_else(|_| panic!("Invalid TRUSTED_PROXIES entry: {}", entry))
+            })
+            .collect();
+        Self { networks }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.networks.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Resolve the originating client address.
+    ///
+    /// Forwarding headers are only consulted when the direct peer is a trusted proxy. The chain
+    /// is then walked from the nearest hop outwards, skipping trusted proxies; the first address
+    /// that is not trusted is the client. Entries further left were written by the client itself
+    /// and are never believed.
+    pub fn resolve(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer = peer?.to_canonical();
+        if !self.contains(peer) {
+            return Some(peer);
+        }
+
+        let chain = forwarded_chain(headers);
+        let mut client = peer;
+        for hop in chain.iter().rev() {
+            match hop {
+                Some(ip) => {
+                    client = ip.to_canonical();
+                    if !self.contains(client) {
+                        break;
+                    }
+                }
+                // An obfuscated or unparseable hop ends the walk at the last known address.
+                None => break,
+            }
+        }
+        Some(client)
+    }
+}
+
+/// Collect the forwarding chain, preferring RFC 7239 `Forwarded` over `X-Forwarded-For`.
+/// Each entry is `None` when the hop could not be parsed as an IP address.
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<Option<IpAddr>> = headers
+        .get_all(header::FORWARDED)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value.trim().trim_matches('"')))
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|hop| parse_node(hop.trim()))
+        .collect()
+}
+
+/// Parse `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1` or `[2001:db8::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|ip| ip.parse().ok())
+        })
+}
+
+//===============================
+// Client IP Extractor
+//===============================
+/// The resolved client address. Every IP-dependent feature should take this extractor
+/// instead of reading `connection_info()` or forwarding headers directly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl ClientIp {
+    pub fn resolve(req: &HttpRequest) -> Self {
+        let peer = req.peer_addr().map(|addr| addr.ip());
+        let resolved = match req.app_data::<web::Data<TrustedProxies>>() {
+            Some(trusted) => trusted.resolve(peer, req.headers()),
+            None => peer,
+        };
+        ClientIp(resolved)
+    }
+}
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(ip) => write!(f, "{}", ip),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+impl FromRequest for ClientIp {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(ClientIp::resolve(req)))
+    }
+}
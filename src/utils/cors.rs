@@ -0,0 +1,91 @@
+// src/utils/cors.rs
+use actix_cors::Cors;
+use std::env;
+
+//===============================
+// CORS Configuration
+//===============================
+/// CORS policy for one route scope.
+///
+/// Each setting is read from `CORS_<SCOPE>_<NAME>` first and falls back to `CORS_<NAME>`,
+/// so a scope can override the deployment-wide defaults.
+#[derive(Clone, Debug)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age: Option<usize>,
+}
+
+impl CorsConfig {
+    pub fn for_scope(scope: &str) -> Self {
+        let allowed_origins = list(scope_var(scope, "ALLOWED_ORIGINS").unwrap_or_default());
+        let allowed_methods = list(
+            scope_var(scope, "ALLOWED_METHODS")
+                .unwrap_or_else(|| "GET,POST,PUT,PATCH,DELETE".to_string()),
+        );
+        let allowed_headers = list(
+            scope_var(scope, "ALLOWED_HEADERS")
+                .unwrap_or_else(|| "Authorization,Content-Type".to_string()),
+        );
+        let allow_credentials = scope_var(scope, "ALLOW_CREDENTIALS")
+            .map(|v| v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        let max_age = scope_var(scope, "MAX_AGE").map(|v| {
+            v.parse()
+                .unwrap_or_else(|_| panic!("CORS max age for scope {} must be seconds", scope))
+        });
+
+        if allow_credentials && allowed_origins.iter().any(|o| o == "*") {
+            panic!(
+                "CORS for scope {} cannot allow credentials with a wildcard origin",
+                scope
+            );
+        }
+
+        Self {
+            allowed_origins,
+            allowed_methods,
+            allowed_headers,
+            allow_credentials,
+            max_age,
+        }
+    }
+
+    /// Build the actix-cors middleware. With no origins configured, cross-origin requests are rejected.
+    pub fn build(&self) -> Cors {
+        let mut cors = Cors::default()
+            .allowed_methods(self.allowed_methods.iter().map(String::as_str))
+            .allowed_headers(self.allowed_headers.iter().map(String::as_str))
+            .max_age(self.max_age);
+
+        for origin in &self.allowed_origins {
+            cors = if origin == "*" {
+                cors.allow_any_origin()
+            } else {
+                cors.allowed_origin(origin)
+            };
+        }
+
+        if self.allow_credentials {
+            cors = cors.supports_credentials();
+        }
+        cors
+    }
+}
+
+fn scope_var(scope: &str, name: &str) -> Option<String> {
+    env::var(format!("CORS_{}_{}", scope, name))
+        .or_else(|_| env::var(format!("CORS_{}", name)))
+        .ok()
+}
+
+fn list(value: String) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,48 @@
+// src/utils/crypto.rs
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use sha2::{Digest, Sha256};
+
+/// Hex-encoded random string with `bytes` bytes of entropy.
+pub fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buf);
+    to_hex(&buf)
+}
+
+/// Random string of `len` characters drawn uniformly from `alphabet`. Bytes that would bias
+/// the modulo are discarded (rejection sampling).
+pub fn random_string(alphabet: &[u8], len: usize) -> String {
+    let limit = 256 - 256 % alphabet.len();
+    let mut out = String::with_capacity(len);
+    let mut byte = [0u8; 1];
+    while out.len() < len {
+        OsRng.fill_bytes(&mut byte);
+        if (byte[0] as usize) < limit {
+            out.push(alphabet[byte[0] as usize % alphabet.len()] as char);
+        }
+    }
+    out
+}
+
+/// Hex-encoded SHA-256. Suitable for high-entropy secrets (API keys, client secrets, refresh
+/// tokens) that need no salt or work factor; user-chosen passwords go through Argon2 instead.
+pub fn sha256_hex(value: &str) -> String {
+    to_hex(&Sha256::digest(value.as_bytes()))
+}
+
+/// Unpadded base64url SHA-256, as used by PKCE `S256`.
+pub fn sha256_base64url(value: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(value.as_bytes()))
+}
+
+/// Compare secrets without leaking the position of the first difference.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,52 @@
+// src/utils/identifier.rs
+use caseless::default_case_fold_str;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::{RestrictionLevel, RestrictionLevelDetection, skeleton};
+
+//===============================
+// Username and Email Normalization
+//===============================
+// Usernames and emails are displayed as entered but compared through canonical forms kept in
+// `username_canonical` / `email_canonical`: NFKC, then full Unicode case folding, so `Alice`,
+// `ALICE` and `ａｌｉｃｅ` (full-width) are one account. The migration crate applies the
+// same rules to existing rows.
+
+/// NFKC form a username is stored in.
+pub fn normalize_username(username: &str) -> String {
+    username.trim().nfkc().collect()
+}
+
+/// NFKC case-folded key usernames are looked up and kept unique by.
+pub fn canonical_username(username: &str) -> String {
+    fold(username.trim())
+}
+
+/// UTS #39 confusable skeleton: two usernames with the same skeleton look alike (`paypal`
+/// and `pаypal` with a Cyrillic `а`, `bill1` and `billl`), so a new one is refused.
+pub fn username_skeleton(username: &str) -> String {
+    skeleton(&canonical_username(username)).collect()
+}
+
+pub fn canonical_email(email: &str) -> String {
+    fold(email.trim())
+}
+
+fn fold(value: &str) -> String {
+    let nfkc: String = value.nfkc().collect();
+    default_case_fold_str(&nfkc).nfkc().collect()
+}
+
+/// Why `username` (already NFKC) cannot be registered: only letters, digits and `._-` are
+/// allowed, and letters must not mix scripts the way spoofed names do.
+pub fn username_problem(username: &str) -> Option<&'static str> {
+    if !username
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return Some("Username may only contain letters, digits, '.', '_' and '-'");
+    }
+    if !username.check_restriction_level(RestrictionLevel::HighlyRestrictive) {
+        return Some("Username mixes scripts or contains look-alike characters");
+    }
+    None
+}
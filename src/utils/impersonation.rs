@@ -0,0 +1,29 @@
+// src/utils/impersonation.rs
+use std::env;
+
+//===============================
+// Impersonation Configuration
+//===============================
+/// Support staff signing in as a user. Tokens carry the administrator in an RFC 8693 `act`
+/// claim, are refused by sensitive endpoints, and every request made with them is audited.
+#[derive(Clone, Debug)]
+pub struct ImpersonationConfig {
+    /// Lifetime of an impersonation token, in seconds.
+    pub ttl: u64,
+    /// Let users list the impersonations of their account at `/impersonations`.
+    pub visible_to_user: bool,
+}
+
+impl ImpersonationConfig {
+    pub fn from_env() -> Self {
+        Self {
+            ttl: env::var("IMPERSONATION_TTL")
+                .map(|v| {
+                    v.parse()
+                        .expect("IMPERSONATION_TTL must be a number of seconds")
+                })
+                .unwrap_or(900),
+            visible_to_user: env::var("IMPERSONATION_VISIBLE_TO_USER").is_ok_and(|v| v == "true"),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-// src/utils/jwt.rs
+use crate::utils::auth_middleware::PrincipalKind;
 use chrono::{Duration, Utc};
 use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
 use serde::{Deserialize, Serialize};
@@ -9,25 +9,122 @@ pub struct Claims {
     pub sub: String,
     pub iat: usize,
     pub exp: usize,
+    /// Set on OAuth access tokens; login tokens carry no audience.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    /// Space-separated scopes granted to an OAuth access token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// OAuth client the token was issued to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Principal type of `sub`; absent means a user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<PrincipalKind>,
+    /// Administrator acting as `sub` (RFC 8693 §4.1); only on impersonation tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: String,
+}
+
+/// Audience of access tokens accepted by this API.
+pub fn api_audience() -> String {
+    env::var("JWT_AUDIENCE").unwrap_or_else(|_| "rust-actix-web-api".to_string())
 }
 
 pub fn encode_jwt(username: String) -> Result<String, jsonwebtoken::errors::Error> {
-    let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
     let now = Utc::now();
     let exp = now + Duration::hours(24); // Token valid for 24 hours
     let claims = Claims {
         sub: username,
         iat: now.timestamp() as usize,
         exp: exp.timestamp() as usize,
+        aud: None,
+        scope: None,
+        client_id: None,
+        kind: None,
+        act: None,
     };
+    sign(&claims)
+}
+
+/// Issue a token that acts as `username` on behalf of the administrator `actor`.
+pub fn encode_impersonation_token(
+    username: String,
+    actor: String,
+    ttl: Duration,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+    let claims = Claims {
+        sub: username,
+        iat: now.timestamp() as usize,
+        exp: (now + ttl).timestamp() as usize,
+        aud: None,
+        scope: None,
+        client_id: None,
+        kind: None,
+        act: Some(Actor { sub: actor }),
+    };
+    sign(&claims)
+}
+
+/// Issue an OAuth access token for this API, limited to `scope`.
+pub fn encode_access_token(
+    username: String,
+    client_id: String,
+    scope: String,
+    ttl: Duration,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    sign(&access_claims(username, client_id, scope, ttl, None))
+}
+
+/// Issue a client-credentials access token for a service account.
+pub fn encode_service_token(
+    account_name: String,
+    client_id: String,
+    scope: String,
+    ttl: Duration,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let kind = Some(PrincipalKind::Service);
+    sign(&access_claims(account_name, client_id, scope, ttl, kind))
+}
+
+fn access_claims(
+    sub: String,
+    client_id: String,
+    scope: String,
+    ttl: Duration,
+    kind: Option<PrincipalKind>,
+) -> Claims {
+    let now = Utc::now();
+    Claims {
+        sub,
+        iat: now.timestamp() as usize,
+        exp: (now + ttl).timestamp() as usize,
+        aud: Some(api_audience()),
+        scope: Some(scope),
+        client_id: Some(client_id),
+        kind,
+        act: None,
+    }
+}
+
+fn sign<T: Serialize>(claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+    let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
     let header = Header::new(Algorithm::HS256);
-    encode(&header, &claims, &EncodingKey::from_secret(secret.as_ref()))
+    encode(&header, claims, &EncodingKey::from_secret(secret.as_ref()))
 }
 
 pub fn decode_jwt(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
     let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
     let mut validation = Validation::new(Algorithm::HS256);
     validation.leeway = 60; // 1 minute leeway
+    // Tokens minted for another audience (e.g. ID tokens) must not work as access tokens.
+    validation.set_audience(&[api_audience()]);
     let token_data = decode::<Claims>(
         token,
         &DecodingKey::from_secret(secret.as_ref()),
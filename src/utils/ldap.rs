@@ -0,0 +1,726 @@
+// src/utils/ldap.rs
+use crate::models::auth_model::Role;
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry, ldap_escape};
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, pem::PemObject};
+use rustls::{ClientConfig, RootCertStore};
+use std::env;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::{debug, warn};
+
+/// LDAP result code for a failed bind (wrong DN or password).
+const INVALID_CREDENTIALS: u32 = 49;
+
+//===============================
+// Directory Configuration
+//===============================
+/// Entry attributes copied onto `auth_users`.
+#[derive(Clone, Debug)]
+struct AttributeMap {
+    email: String,
+    phone: Option<String>,
+    /// Stable identifier stored as the `identities` subject; the DN when unset.
+    id: Option<String>,
+}
+
+/// An LDAP or Active Directory server users authenticate against with search-then-bind:
+/// the entry is found with the service account, then the user's password is checked by
+/// binding as that entry.
+pub struct LdapDirectory {
+    url: String,
+    settings: LdapConnSettings,
+    /// Service account used for searches; anonymous when `None`.
+    bind_dn: Option<String>,
+    bind_password: String,
+    user_base_dn: String,
+    /// Search filter with a `{username}` placeholder, e.g. `(sAMAccountName={username})`.
+    user_filter: String,
+    attributes: AttributeMap,
+    group_attribute: String,
+    /// Lowercased DNs of the groups whose members get the admin role. Empty disables role
+    /// mapping, leaving roles to be managed locally.
+    admin_groups: Vec<String>,
+    timeout: Duration,
+    /// Idle connections bound as the service account.
+    idle: Mutex<Vec<Ldap>>,
+    /// Caps the connections open to the directory at once.
+    slots: Semaphore,
+}
+
+/// An entry that proved its password.
+#[derive(Debug)]
+pub struct DirectoryUser {
+    pub dn: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub groups: Vec<String>,
+}
+
+impl LdapDirectory {
+    pub fn from_env() -> io::Result<Self> {
+        let required = |key: &str| {
+            env::var(key).unwrap_or_else(|_| panic!("{} must be set for the ldap backend", key))
+        };
+        let seconds = |key: &str, default: u64| {
+            env::var(key)
+                .map(|v| {
+                    v.parse()
+                        .unwrap_or_else(|_| panic!("{} must be a number of seconds", key))
+                })
+                .unwrap_or(default)
+        };
+
+        let url = required("LDAP_URL");
+        let timeout = Duration::from_secs(seconds("LDAP_TIMEOUT", 5));
+        let mut settings = LdapConnSettings::new()
+            .set_conn_timeout(timeout)
+            .set_starttls(env::var("LDAP_STARTTLS").is_ok_and(|v| v == "true"));
+        // Directories usually present a certificate from an internal CA.
+        if let Ok(path) = env::var("LDAP_CA_PATH") {
+            settings = settings.set_config(Arc::new(client_config(&path)?));
+        }
+
+        let pool_size = env::var("LDAP_POOL_SIZE")
+            .map(|v| v.parse().expect("LDAP_POOL_SIZE must be a number"))
+            .unwrap_or(8);
+        let user_filter =
+            env::var("LDAP_USER_FILTER").unwrap_or_else(|_| "(uid={username})".to_string());
+        if !user_filter.contains("{username}") {
+            panic!("LDAP_USER_FILTER must contain {{username}}");
+        }
+
+        Ok(Self {
+            url,
+            settings,
+            bind_dn: env::var("LDAP_BIND_DN").ok(),
+            bind_password: env::var("LDAP_BIND_PASSWORD").unwrap_or_default(),
+            user_base_dn: required("LDAP_USER_BASE_DN"),
+            user_filter,
+            attributes: AttributeMap {
+                email: env::var("LDAP_ATTRIBUTE_EMAIL").unwrap_or_else(|_| "mail".to_string()),
+                phone: env::var("LDAP_ATTRIBUTE_PHONE").ok(),
+                id: env::var("LDAP_ATTRIBUTE_ID").ok(),
+            },
+            group_attribute: env::var("LDAP_GROUP_ATTRIBUTE")
+                .unwrap_or_else(|_| "memberOf".to_string()),
+            admin_groups: env::var("LDAP_ADMIN_GROUPS")
+                .unwrap_or_default()
+                .split(';')
+                .map(|dn| dn.trim().to_ascii_lowercase())
+                .filter(|dn| !dn.is_empty())
+                .collect(),
+            timeout,
+            idle: Mutex::new(Vec::new()),
+            slots: Semaphore::new(pool_size),
+        })
+    }
+
+    /// Look `username` up and bind as its entry with `password`. `Ok(None)` when the user is
+    /// unknown, ambiguous or the password is wrong.
+    pub async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<Option<DirectoryUser>, LdapError> {
+        // An empty password is an unauthenticated bind, which servers accept for any DN.
+        if username.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+
+        // 1. Find the entry as the service account
+        let (mut ldap, _slot) = self.checkout().await?;
+        let filter = self
+            .user_filter
+            .replace("{username}", &ldap_escape(username));
+        let mut attributes = vec![
+            self.attributes.email.as_str(),
+            self.group_attribute.as_str(),
+        ];
+        attributes.extend(self.attributes.phone.as_deref());
+        attributes.extend(self.attributes.id.as_deref());
+        let (entries, _) = ldap
+            .with_timeout(self.timeout)
+            .search(&self.user_base_dn, Scope::Subtree, &filter, attributes)
+            .await?
+            .success()?;
+        let entry = match <[_; 1]>::try_from(entries) {
+            Ok([entry]) => SearchEntry::construct(entry),
+            Err(entries) => {
+                debug!(
+                    "LDAP search for {} matched {} entries",
+                    username,
+                    entries.len()
+                );
+                self.checkin(ldap);
+                return Ok(None);
+            }
+        };
+
+        // 2. Check the password by binding as the entry, then restore the service binding
+        // before the connection goes back to the pool.
+        let bound = ldap
+            .with_timeout(self.timeout)
+            .simple_bind(&entry.dn, password)
+            .await?;
+        self.service_bind(&mut ldap).await?;
+        self.checkin(ldap);
+        if bound.rc != 0 {
+            if bound.rc != INVALID_CREDENTIALS {
+                warn!("LDAP bind for {} refused: {}", entry.dn, bound);
+            }
+            return Ok(None);
+        }
+
+        let first = |name: &str| {
+            entry
+                .attrs
+                .get(name)
+                .and_then(|values| values.first().cloned())
+        };
+        Ok(Some(DirectoryUser {
+            subject: self
+                .attributes
+                .id
+                .as_deref()
+                .and_then(first)
+                .unwrap_or_else(|| entry.dn.clone()),
+            email: first(&self.attributes.email),
+            phone: self.attributes.phone.as_deref().and_then(first),
+            groups: entry
+                .attrs
+                .get(&self.group_attribute)
+                .cloned()
+                .unwrap_or_default(),
+            dn: entry.dn,
+        }))
+    }
+
+    /// Role for members of `groups`, or `None` when no group mapping is configured.
+    pub fn role_for(&self, groups: &[String]) -> Option<Role> {
+        if self.admin_groups.is_empty() {
+            return None;
+        }
+        let is_admin = groups.iter().any(|group| {
+            self.admin_groups
+                .contains(&group.trim().to_ascii_lowercase())
+        });
+        Some(if is_admin { Role::Admin } else { Role::User })
+    }
+
+    //===============================
+    // Connection Pool
+    //===============================
+    /// A connection bound as the service account, reused from the pool when one is idle.
+    /// The permit must be held for as long as the connection is in use.
+    async fn checkout(&self) -> Result<(Ldap, SemaphorePermit<'_>), LdapError> {
+        let slot = self
+            .slots
+            .acquire()
+            .await
+            .expect("the LDAP pool semaphore is never closed");
+        loop {
+            let pooled = self.idle.lock().expect("LDAP pool lock poisoned").pop();
+            match pooled {
+                Some(mut ldap) => {
+                    // Dropped by the server while idle
+                    if !ldap.is_closed() {
+                        return Ok((ldap, slot));
+                    }
+                }
+                None => break,
+            }
+        }
+
+        let (conn, mut ldap) =
+            LdapConnAsync::with_settings(self.settings.clone(), &self.url).await?;
+        ldap3::drive!(conn);
+        self.service_bind(&mut ldap).await?;
+        Ok((ldap, slot))
+    }
+
+    fn checkin(&self, ldap: Ldap) {
+        self.idle
+            .lock()
+            .expect("LDAP pool lock poisoned")
+            .push(ldap);
+    }
+
+    async fn service_bind(&self, ldap: &mut Ldap) -> Result<(), LdapError> {
+        let bind_dn = self.bind_dn.as_deref().unwrap_or_default();
+        ldap.with_timeout(self.timeout)
+            .simple_bind(bind_dn, &self.bind_password)
+            .await?
+            .success()?;
+        Ok(())
+    }
+}
+
+/// Client config trusting only the CA bundle at `path`.
+fn client_config(path: &str) -> io::Result<ClientConfig> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(path).map_err(|e| invalid(e.to_string()))? {
+        roots
+            .add(cert.map_err(|e| invalid(e.to_string()))?)
+            .map_err(|e| invalid(e.to_string()))?;
+    }
+    Ok(
+        ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid(e.to_string()))?
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ldap3::asn1::{PL, StructureTag, TagClass, parse_tag};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    pub(crate) const SERVICE_DN: &str = "cn=service,dc=example,dc=com";
+    const SERVICE_PASSWORD: &str = "service-password";
+    pub(crate) const JANE_PASSWORD: &str = "jane-password";
+    pub(crate) const ADMINS: &str = "cn=Admins,ou=groups,dc=example,dc=com";
+
+    struct Entry {
+        dn: &'static str,
+        password: &'static str,
+        attributes: Vec<(&'static str, Vec<&'static str>)>,
+    }
+
+    /// In-process directory speaking just enough LDAPv3 for search-then-bind: simple binds,
+    /// subtree searches with `&`, `|`, `!`, equality, substring and presence filters, and
+    /// unbinds.
+    pub(crate) struct DirectoryStandIn {
+        entries: Vec<Entry>,
+        /// DNs of the binds received, in order.
+        pub(crate) binds: Mutex<Vec<String>>,
+        pub(crate) connections: AtomicUsize,
+    }
+
+    impl DirectoryStandIn {
+        fn new() -> Self {
+            let person = |uid: &'static str, mail: &'static str, groups: Vec<&'static str>| {
+                vec![
+                    ("objectClass", vec!["top", "person"]),
+                    ("uid", vec![uid]),
+                    ("mail", vec![mail]),
+                    ("entryUUID", vec![uid]),
+                    ("memberOf", groups),
+                ]
+            };
+            let entries = vec![
+                Entry {
+                    dn: "uid=jane,ou=people,dc=example,dc=com",
+                    password: JANE_PASSWORD,
+                    attributes: person("jane", "jane@example.com", vec![ADMINS]),
+                },
+                Entry {
+                    dn: "uid=bob,ou=people,dc=example,dc=com",
+                    password: "bob-password",
+                    attributes: person("bob", "bob@example.com", vec![]),
+                },
+                // Two entries sharing a uid, e.g. across organisational units.
+                Entry {
+                    dn: "uid=sam,ou=people,dc=example,dc=com",
+                    password: "sam-password",
+                    attributes: person("sam", "sam@example.com", vec![]),
+                },
+                Entry {
+                    dn: "uid=sam,ou=contractors,dc=example,dc=com",
+                    password: "sam-password",
+                    attributes: person("sam", "sam@contractor.example.com", vec![]),
+                },
+            ];
+            Self {
+                entries,
+                binds: Mutex::new(Vec::new()),
+                connections: AtomicUsize::new(0),
+            }
+        }
+
+        async fn serve(self: Arc<Self>, mut socket: TcpStream) {
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 4096];
+            loop {
+                while let Some(len) = message_len(&buf) {
+                    let Ok((_, message)) = parse_tag(&buf[..len]) else {
+                        return;
+                    };
+                    buf.drain(..len);
+                    let Some(replies) = self.handle(message) else {
+                        return;
+                    };
+                    let mut out = Vec::new();
+                    for reply in replies {
+                        encode(&reply, &mut out);
+                    }
+                    if socket.write_all(&out).await.is_err() {
+                        return;
+                    }
+                }
+                match socket.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                }
+            }
+        }
+
+        /// Replies to one `LDAPMessage`, or `None` to close the connection.
+        fn handle(&self, message: StructureTag) -> Option<Vec<StructureTag>> {
+            let mut parts = message.expect_constructed()?.into_iter();
+            let id = integer_value(parts.next()?)?;
+            let op = parts.next()?;
+            let reply = |op: u64, children| {
+                sequence(vec![
+                    primitive(TagClass::Universal, 2, integer_bytes(id)),
+                    constructed(TagClass::Application, op, children),
+                ])
+            };
+            let result = |code: i64| {
+                vec![
+                    primitive(TagClass::Universal, 10, integer_bytes(code)),
+                    octet_string(""),
+                    octet_string(""),
+                ]
+            };
+            match (op.class, op.id) {
+                (TagClass::Application, 0) => {
+                    let mut fields = op.expect_constructed()?.into_iter().skip(1);
+                    let dn = string(fields.next()?)?;
+                    let password = string(fields.next()?)?;
+                    self.binds.lock().unwrap().push(dn.clone());
+                    let valid = (dn == SERVICE_DN && password == SERVICE_PASSWORD)
+                        || self
+                            .entries
+                            .iter()
+                            .any(|e| e.dn == dn && e.password == password);
+                    let code = if valid { 0 } else { INVALID_CREDENTIALS as i64 };
+                    Some(vec![reply(1, result(code))])
+                }
+                (TagClass::Application, 3) => {
+                    let filter = op.expect_constructed()?.into_iter().nth(6)?;
+                    let mut replies: Vec<StructureTag> = self
+                        .entries
+                        .iter()
+                        .filter(|entry| matches(entry, &filter))
+                        .map(|entry| reply(4, search_entry(entry)))
+                        .collect();
+                    replies.push(reply(5, result(0)));
+                    Some(replies)
+                }
+                // Unbind
+                _ => None,
+            }
+        }
+    }
+
+    fn matches(entry: &Entry, filter: &StructureTag) -> bool {
+        let values = |attribute: &str| {
+            entry
+                .attributes
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+                .map(|(_, values)| values.as_slice())
+                .unwrap_or_default()
+        };
+        let children = || match &filter.payload {
+            PL::C(children) => children.as_slice(),
+            PL::P(_) => &[],
+        };
+        match filter.id {
+            0 => children().iter().all(|f| matches(entry, f)),
+            1 => children().iter().any(|f| matches(entry, f)),
+            2 => !children().iter().any(|f| matches(entry, f)),
+            3 => {
+                let [attribute, value] = children() else {
+                    return false;
+                };
+                let value = string(value.clone()).unwrap_or_default();
+                values(&string(attribute.clone()).unwrap_or_default())
+                    .iter()
+                    .any(|v| v.eq_ignore_ascii_case(&value))
+            }
+            4 => {
+                let [attribute, parts] = children() else {
+                    return false;
+                };
+                let PL::C(parts) = &parts.payload else {
+                    return false;
+                };
+                values(&string(attribute.clone()).unwrap_or_default())
+                    .iter()
+                    .any(|v| substring_match(&v.to_lowercase(), parts))
+            }
+            7 => match &filter.payload {
+                PL::P(attribute) => !values(&String::from_utf8_lossy(attribute)).is_empty(),
+                PL::C(_) => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// `initial`, `any` and `final` parts matched in order.
+    fn substring_match(value: &str, parts: &[StructureTag]) -> bool {
+        let mut rest = value;
+        for part in parts {
+            let PL::P(bytes) = &part.payload else {
+                return false;
+            };
+            let needle = String::from_utf8_lossy(bytes).to_lowercase();
+            match part.id {
+                0 if rest.starts_with(&needle) => rest = &rest[needle.len()..],
+                1 => match rest.find(&needle) {
+                    Some(at) => rest = &rest[at + needle.len()..],
+                    None => return false,
+                },
+                2 => return rest.ends_with(&needle),
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    fn search_entry(entry: &Entry) -> Vec<StructureTag> {
+        let attributes = entry
+            .attributes
+            .iter()
+            .map(|(name, values)| {
+                sequence(vec![
+                    octet_string(name),
+                    constructed(
+                        TagClass::Universal,
+                        17,
+                        values.iter().map(|v| octet_string(v)).collect(),
+                    ),
+                ])
+            })
+            .collect();
+        vec![octet_string(entry.dn), sequence(attributes)]
+    }
+
+    //===============================
+    // BER
+    //===============================
+    /// Length of the first complete element in `buf`, header included.
+    fn message_len(buf: &[u8]) -> Option<usize> {
+        let first = *buf.get(1)? as usize;
+        let (header, len) = if first < 0x80 {
+            (2, first)
+        } else {
+            let n = first & 0x7f;
+            let bytes = buf.get(2..2 + n)?;
+            (2 + n, bytes.iter().fold(0, |len, b| len << 8 | *b as usize))
+        };
+        (buf.len() >= header + len).then_some(header + len)
+    }
+
+    fn encode(tag: &StructureTag, out: &mut Vec<u8>) {
+        let class = match tag.class {
+            TagClass::Universal => 0x00,
+            TagClass::Application => 0x40,
+            TagClass::Context => 0x80,
+            TagClass::Private => 0xc0,
+        };
+        let (form, content) = match &tag.payload {
+            PL::P(bytes) => (0x00, bytes.clone()),
+            PL::C(children) => {
+                let mut content = Vec::new();
+                for child in children {
+                    encode(child, &mut content);
+                }
+                (0x20, content)
+            }
+        };
+        out.push(class | form | tag.id as u8);
+        if content.len() < 0x80 {
+            out.push(content.len() as u8);
+        } else {
+            let len = content.len().to_be_bytes();
+            let len = &len[len.iter().take_while(|b| **b == 0).count()..];
+            out.push(0x80 | len.len() as u8);
+            out.extend_from_slice(len);
+        }
+        out.extend(content);
+    }
+
+    fn primitive(class: TagClass, id: u64, bytes: Vec<u8>) -> StructureTag {
+        StructureTag {
+            class,
+            id,
+            payload: PL::P(bytes),
+        }
+    }
+
+    fn constructed(class: TagClass, id: u64, children: Vec<StructureTag>) -> StructureTag {
+        StructureTag {
+            class,
+            id,
+            payload: PL::C(children),
+        }
+    }
+
+    fn sequence(children: Vec<StructureTag>) -> StructureTag {
+        constructed(TagClass::Universal, 16, children)
+    }
+
+    fn octet_string(value: &str) -> StructureTag {
+        primitive(TagClass::Universal, 4, value.as_bytes().to_vec())
+    }
+
+    fn integer_bytes(value: i64) -> Vec<u8> {
+        let bytes = value.to_be_bytes();
+        let mut skip = 0;
+        while skip < 7 && bytes[skip] == 0 && bytes[skip + 1] < 0x80 {
+            skip += 1;
+        }
+        bytes[skip..].to_vec()
+    }
+
+    fn integer_value(tag: StructureTag) -> Option<i64> {
+        let bytes = tag.expect_primitive()?;
+        Some(bytes.iter().fold(0, |n, b| n << 8 | *b as i64))
+    }
+
+    fn string(tag: StructureTag) -> Option<String> {
+        String::from_utf8(tag.expect_primitive()?).ok()
+    }
+
+    /// A directory configured like `LDAP_*` would for the stand-in, with `uid` logins,
+    /// `entryUUID` subjects and `ADMINS` members as administrators.
+    pub(crate) async fn directory() -> (LdapDirectory, Arc<DirectoryStandIn>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        let stand_in = Arc::new(DirectoryStandIn::new());
+        let server = stand_in.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                server.connections.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(server.clone().serve(socket));
+            }
+        });
+        let directory = LdapDirectory {
+            url,
+            settings: LdapConnSettings::new(),
+            bind_dn: Some(SERVICE_DN.to_string()),
+            bind_password: SERVICE_PASSWORD.to_string(),
+            user_base_dn: "dc=example,dc=com".to_string(),
+            user_filter: "(&(objectClass=person)(uid={username}))".to_string(),
+            attributes: AttributeMap {
+                email: "mail".to_string(),
+                phone: None,
+                id: Some("entryUUID".to_string()),
+            },
+            group_attribute: "memberOf".to_string(),
+            admin_groups: vec![ADMINS.to_ascii_lowercase()],
+            timeout: Duration::from_secs(5),
+            idle: Mutex::new(Vec::new()),
+            slots: Semaphore::new(2),
+        };
+        (directory, stand_in)
+    }
+
+    #[actix_web::test]
+    async fn finds_the_entry_then_binds_as_it() {
+        let (directory, stand_in) = directory().await;
+
+        let user = directory
+            .authenticate("jane", JANE_PASSWORD)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.dn, "uid=jane,ou=people,dc=example,dc=com");
+        assert_eq!(user.subject, "jane");
+        assert_eq!(user.email.as_deref(), Some("jane@example.com"));
+        assert_eq!(user.groups, vec![ADMINS.to_string()]);
+        // The connection is bound back to the service account before it is pooled.
+        assert_eq!(
+            *stand_in.binds.lock().unwrap(),
+            vec![SERVICE_DN, user.dn.as_str(), SERVICE_DN]
+        );
+
+        directory
+            .authenticate("bob", "bob-password")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stand_in.connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn rejects_a_wrong_password() {
+        let (directory, stand_in) = directory().await;
+
+        let user = directory.authenticate("jane", "wrong").await.unwrap();
+        assert!(user.is_none());
+        // Rejected by the directory (result code 49), not before asking it.
+        assert!(
+            stand_in
+                .binds
+                .lock()
+                .unwrap()
+                .contains(&"uid=jane,ou=people,dc=example,dc=com".to_string())
+        );
+
+        assert!(directory.authenticate("jane", "").await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn rejects_unknown_and_ambiguous_users() {
+        let (directory, stand_in) = directory().await;
+
+        let user = directory.authenticate("nobody", "password").await.unwrap();
+        assert!(user.is_none());
+        let user = directory.authenticate("sam", "sam-password").await.unwrap();
+        assert!(user.is_none());
+        // Neither got as far as a bind with the password.
+        assert!(
+            stand_in
+                .binds
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|dn| dn == SERVICE_DN)
+        );
+    }
+
+    #[actix_web::test]
+    async fn escapes_the_username_in_the_filter() {
+        let (directory, _) = directory().await;
+
+        // Unescaped, these would match jane's entry and accept her password.
+        for username in ["jan*", "*", "nobody)(uid=jane", "jane)(|(uid=*"] {
+            let user = directory
+                .authenticate(username, JANE_PASSWORD)
+                .await
+                .unwrap();
+            assert!(user.is_none(), "{} matched an entry", username);
+        }
+    }
+
+    #[actix_web::test]
+    async fn maps_admin_groups_to_roles() {
+        let (mut directory, _) = directory().await;
+
+        assert_eq!(
+            directory.role_for(&["CN=admins,OU=Groups,DC=example,DC=com".to_string()]),
+            Some(Role::Admin)
+        );
+        assert_eq!(
+            directory.role_for(&["cn=staff,ou=groups,dc=example,dc=com".to_string()]),
+            Some(Role::User)
+        );
+        assert_eq!(directory.role_for(&[]), Some(Role::User));
+
+        directory.admin_groups.clear();
+        assert_eq!(directory.role_for(&[ADMINS.to_string()]), None);
+    }
+}
@@ -1,2 +1,25 @@
+pub mod account_deletion;
+pub mod account_export;
+pub mod api_key;
 pub mod auth_middleware;
+pub mod client_ip;
+pub mod cors;
+pub mod crypto;
+pub mod identifier;
+pub mod impersonation;
 pub mod jwt;
+pub mod ldap;
+pub mod mailer;
+pub mod oauth;
+pub mod oidc;
+pub mod otp;
+pub mod passwordless;
+pub mod phone;
+pub mod proxy_protocol;
+pub mod revocation;
+pub mod saml;
+pub mod security_headers;
+pub mod shutdown;
+pub mod sms;
+pub mod sso;
+pub mod tls;
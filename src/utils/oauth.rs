@@ -0,0 +1,618 @@
+// src/utils/oauth.rs
+use crate::models::oauth_client_model::{Column, Entity, Model};
+use crate::models::service_account_model::{
+    self, Column as ServiceAccountColumn, Entity as ServiceAccount,
+};
+use crate::utils::crypto::{
+    constant_time_eq, random_hex, random_string, sha256_base64url, sha256_hex,
+};
+use crate::utils::revocation::is_user_token_revoked;
+use actix_web::{
+    HttpRequest, HttpResponse,
+    http::{StatusCode, header},
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::Utc;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, SetExpiry, SetOptions};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use tracing::{error, warn};
+use url::Url;
+
+const CODE_TTL_SECS: u64 = 60;
+/// RFC 8628 §6.1: consonants only, so user codes are easy to type and never spell words.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LEN: usize = 8;
+
+//===============================
+// OAuth Configuration
+//===============================
+#[derive(Clone, Debug)]
+pub struct OAuthConfig {
+    pub access_token_ttl: i64,
+    pub refresh_token_ttl: u64,
+    /// Front-end page that shows the consent screen. `GET /oauth/authorize` redirects there
+    /// with the original query; without it, the consent details are returned as JSON.
+    pub consent_url: Option<String>,
+    /// Lifetime of device and user codes (RFC 8628), in seconds.
+    pub device_code_ttl: u64,
+    /// Minimum seconds between device token polls.
+    pub device_poll_interval: u64,
+    /// Page where users enter a device's user code. The device flow is off without it.
+    pub device_verification_url: Option<Url>,
+}
+
+impl OAuthConfig {
+    pub fn from_env() -> Self {
+        Self {
+            access_token_ttl: env::var("OAUTH_ACCESS_TOKEN_TTL")
+                .map(|v| v.parse().expect("OAUTH_ACCESS_TOKEN_TTL must be seconds"))
+                .unwrap_or(3600),
+            refresh_token_ttl: env::var("OAUTH_REFRESH_TOKEN_TTL")
+                .map(|v| v.parse().expect("OAUTH_REFRESH_TOKEN_TTL must be seconds"))
+                .unwrap_or(30 * 24 * 3600),
+            consent_url: env::var("OAUTH_CONSENT_URL").ok(),
+            device_code_ttl: env::var("OAUTH_DEVICE_CODE_TTL")
+                .map(|v| v.parse().expect("OAUTH_DEVICE_CODE_TTL must be seconds"))
+                .unwrap_or(600),
+            device_poll_interval: env::var("OAUTH_DEVICE_POLL_INTERVAL")
+                .map(|v| {
+                    v.parse()
+                        .expect("OAUTH_DEVICE_POLL_INTERVAL must be seconds")
+                })
+                .unwrap_or(5),
+            device_verification_url: env::var("OAUTH_DEVICE_VERIFICATION_URL").ok().map(|v| {
+                Url::parse(&v).expect("OAUTH_DEVICE_VERIFICATION_URL must be an absolute URL")
+            }),
+        }
+    }
+}
+
+//===============================
+// Protocol Errors
+//===============================
+/// RFC 6749 §5.2 error body. The token endpoint speaks the OAuth format, not `{code, message}`,
+/// so standard client libraries can parse it.
+pub fn oauth_error(status: StatusCode, error: &str, description: &str) -> HttpResponse {
+    HttpResponse::build(status).json(json!({"error":error,"error_description":description}))
+}
+
+pub fn server_error() -> HttpResponse {
+    oauth_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "server_error",
+        "Internal server error",
+    )
+}
+
+//===============================
+// Grants stored in Redis
+//===============================
+/// Everything bound to an authorization code, checked again when it is redeemed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthorizationGrant {
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub username: String,
+    pub scope: String,
+    pub code_challenge: String,
+    pub nonce: Option<String>,
+    /// When the user signed in, reported as the ID token `auth_time`.
+    pub auth_time: i64,
+}
+
+/// State behind an opaque refresh token.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshGrant {
+    pub client_id: String,
+    pub username: String,
+    pub scope: String,
+    #[serde(default)]
+    pub auth_time: i64,
+}
+
+/// Store a grant under a fresh random code for `CODE_TTL_SECS`. Only the hash is used as key.
+pub async fn store_authorization_code(
+    redis: &ConnectionManager,
+    grant: &AuthorizationGrant,
+) -> redis::RedisResult<String> {
+    let code = random_hex(32);
+    store(redis, "oauth:code", &code, grant, CODE_TTL_SECS).await?;
+    Ok(code)
+}
+
+/// Redeem a code. `GETDEL` makes it single use even under concurrent requests.
+pub async fn take_authorization_code(
+    redis: &ConnectionManager,
+    code: &str,
+) -> redis::RedisResult<Option<AuthorizationGrant>> {
+    take(redis, "oauth:code", code).await
+}
+
+/// Hashes of the live refresh tokens of `username`, so their sessions can be listed without
+/// scanning the keyspace. Members whose token expired are dropped when the set is read.
+fn user_refresh_tokens_key(username: &str) -> String {
+    format!("oauth:rt_user:{}", username)
+}
+
+pub async fn issue_refresh_token(
+    redis: &ConnectionManager,
+    grant: &RefreshGrant,
+    ttl: u64,
+) -> redis::RedisResult<String> {
+    let token = random_hex(32);
+    store(redis, "oauth:rt", &token, grant, ttl).await?;
+    let index = user_refresh_tokens_key(&grant.username);
+    let mut conn = redis.clone();
+    conn.sadd::<_, _, ()>(&index, sha256_hex(&token)).await?;
+    // Every token gets the same lifetime, so the newest one decides when the set can go.
+    conn.expire::<_, ()>(&index, ttl as i64).await?;
+    Ok(token)
+}
+
+/// Consume a refresh token; callers issue a replacement (rotation).
+pub async fn take_refresh_token(
+    redis: &ConnectionManager,
+    token: &str,
+) -> redis::RedisResult<Option<RefreshGrant>> {
+    let grant: Option<RefreshGrant> = take(redis, "oauth:rt", token).await?;
+    if let Some(grant) = &grant {
+        let mut conn = redis.clone();
+        conn.srem::<_, _, ()>(user_refresh_tokens_key(&grant.username), sha256_hex(token))
+            .await?;
+    }
+    Ok(grant)
+}
+
+/// Look up a refresh token without consuming it, with its remaining lifetime in seconds.
+pub async fn peek_refresh_token(
+    redis: &ConnectionManager,
+    token: &str,
+) -> redis::RedisResult<Option<(RefreshGrant, i64)>> {
+    peek_refresh_grant(redis, &format!("oauth:rt:{}", sha256_hex(token))).await
+}
+
+async fn peek_refresh_grant(
+    redis: &ConnectionManager,
+    key: &str,
+) -> redis::RedisResult<Option<(RefreshGrant, i64)>> {
+    let mut conn = redis.clone();
+    let payload: Option<String> = conn.get(key).await?;
+    let Some(grant) = payload.and_then(|p| serde_json::from_str(&p).ok()) else {
+        return Ok(None);
+    };
+    let ttl: i64 = conn.ttl(key).await?;
+    Ok(Some((grant, ttl)))
+}
+
+/// Every refresh token of `username` that can still be used, with its remaining lifetime in
+/// seconds. Tokens from before the user's sessions were revoked are left out, as the token
+/// endpoint refuses them.
+pub async fn refresh_grants_of(
+    redis: &ConnectionManager,
+    username: &str,
+) -> redis::RedisResult<Vec<(RefreshGrant, i64)>> {
+    let index = user_refresh_tokens_key(username);
+    let mut conn = redis.clone();
+    let hashes: Vec<String> = conn.smembers(&index).await?;
+
+    let mut grants = Vec::new();
+    for hash in hashes {
+        match peek_refresh_grant(redis, &format!("oauth:rt:{}", hash)).await? {
+            Some((grant, ttl)) => {
+                if !is_user_token_revoked(redis, username, grant.auth_time).await? {
+                    grants.push((grant, ttl));
+                }
+            }
+            None => conn.srem::<_, _, ()>(&index, &hash).await?,
+        }
+    }
+    Ok(grants)
+}
+
+async fn store<T: Serialize>(
+    redis: &ConnectionManager,
+    namespace: &str,
+    secret: &str,
+    value: &T,
+    ttl: u64,
+) -> redis::RedisResult<()> {
+    let payload = serde_json::to_string(value).expect("grant serializes");
+    let mut conn = redis.clone();
+    conn.set_ex(
+        format!("{}:{}", namespace, sha256_hex(secret)),
+        payload,
+        ttl,
+    )
+    .await
+}
+
+async fn take<T: for<'de> Deserialize<'de>>(
+    redis: &ConnectionManager,
+    namespace: &str,
+    secret: &str,
+) -> redis::RedisResult<Option<T>> {
+    let mut conn = redis.clone();
+    let payload: Option<String> = conn
+        .get_del(format!("{}:{}", namespace, sha256_hex(secret)))
+        .await?;
+    Ok(payload.and_then(|p| serde_json::from_str(&p).ok()))
+}
+
+//===============================
+// Device Authorization (RFC 8628)
+//===============================
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+/// State of a device authorization, shared by the polling device and the approving user.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceGrant {
+    pub client_id: String,
+    pub scope: String,
+    pub user_code: String,
+    pub status: DeviceStatus,
+    /// User who approved or denied the request.
+    pub username: Option<String>,
+    pub auth_time: i64,
+}
+
+/// Polling bookkeeping of a device grant. It lives under its own key, so a poll never writes
+/// the grant and cannot undo the user's decision.
+#[derive(Debug, Serialize, Deserialize)]
+struct DevicePolling {
+    /// Current polling interval; raised on every `slow_down`.
+    interval: u64,
+    last_polled_at: Option<i64>,
+}
+
+/// A device grant loaded from Redis, with the key needed to update or remove it.
+pub struct StoredDeviceGrant {
+    key: String,
+    pub grant: DeviceGrant,
+}
+
+/// Start a device authorization and return `(device_code, user_code)`. Both codes expire
+/// together; the user code maps to the hashed device code so a user can approve it.
+pub async fn start_device_authorization(
+    redis: &ConnectionManager,
+    client_id: &str,
+    scope: &str,
+    interval: u64,
+    ttl: u64,
+) -> redis::RedisResult<(String, String)> {
+    let device_code = random_hex(32);
+    let key = format!("oauth:device:{}", sha256_hex(&device_code));
+    let mut conn = redis.clone();
+
+    // Retry on the (unlikely) collision with a live user code.
+    let mut user_code = random_string(USER_CODE_ALPHABET, USER_CODE_LEN);
+    let nx = SetOptions::default()
+        .conditional_set(redis::ExistenceCheck::NX)
+        .with_expiration(SetExpiry::EX(ttl));
+    while !conn
+        .set_options::<_, _, bool>(format!("oauth:device_user:{}", user_code), &key, nx.clone())
+        .await?
+    {
+        user_code = random_string(USER_CODE_ALPHABET, USER_CODE_LEN);
+    }
+
+    let grant = DeviceGrant {
+        client_id: client_id.to_string(),
+        scope: scope.to_string(),
+        user_code: user_code.clone(),
+        status: DeviceStatus::Pending,
+        username: None,
+        auth_time: 0,
+    };
+    let polling = DevicePolling {
+        interval,
+        last_polled_at: None,
+    };
+    let payload = serde_json::to_string(&grant).expect("grant serializes");
+    let polling = serde_json::to_string(&polling).expect("polling serializes");
+    conn.set_ex::<_, _, ()>(poll_key(&key), polling, ttl)
+        .await?;
+    conn.set_ex::<_, _, ()>(&key, payload, ttl).await?;
+    Ok((device_code, user_code))
+}
+
+/// Canonical form of a user-entered code: `bcdf-ghjk`, `BCDF GHJK` and `BCDFGHJK` all match.
+pub fn normalize_user_code(input: &str) -> String {
+    input
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// `BCDFGHJK` → `BCDF-GHJK` for display.
+pub fn format_user_code(user_code: &str) -> String {
+    let (head, tail) = user_code.split_at(user_code.len() / 2);
+    format!("{}-{}", head, tail)
+}
+
+pub async fn device_grant_by_device_code(
+    redis: &ConnectionManager,
+    device_code: &str,
+) -> redis::RedisResult<Option<StoredDeviceGrant>> {
+    load_device_grant(redis, format!("oauth:device:{}", sha256_hex(device_code))).await
+}
+
+pub async fn device_grant_by_user_code(
+    redis: &ConnectionManager,
+    user_code: &str,
+) -> redis::RedisResult<Option<StoredDeviceGrant>> {
+    let mut conn = redis.clone();
+    let key: Option<String> = conn
+        .get(format!(
+            "oauth:device_user:{}",
+            normalize_user_code(user_code)
+        ))
+        .await?;
+    match key {
+        Some(key) => load_device_grant(redis, key).await,
+        None => Ok(None),
+    }
+}
+
+async fn load_device_grant(
+    redis: &ConnectionManager,
+    key: String,
+) -> redis::RedisResult<Option<StoredDeviceGrant>> {
+    let mut conn = redis.clone();
+    let payload: Option<String> = conn.get(&key).await?;
+    Ok(payload
+        .and_then(|p| serde_json::from_str(&p).ok())
+        .map(|grant| StoredDeviceGrant { key, grant }))
+}
+
+fn poll_key(grant_key: &str) -> String {
+    format!("{}:poll", grant_key)
+}
+
+/// Record the user's decision, keeping the grant's original expiry. A grant that expired or
+/// was finished in the meantime is not brought back.
+pub async fn save_device_grant(
+    redis: &ConnectionManager,
+    stored: &StoredDeviceGrant,
+) -> redis::RedisResult<()> {
+    let payload = serde_json::to_string(&stored.grant).expect("grant serializes");
+    let keep_ttl = SetOptions::default()
+        .conditional_set(redis::ExistenceCheck::XX)
+        .with_expiration(SetExpiry::KEEPTTL);
+    let mut conn = redis.clone();
+    conn.set_options::<_, _, Option<String>>(&stored.key, payload, keep_ttl)
+        .await?;
+    Ok(())
+}
+
+/// Record a poll of `stored` and return whether it came sooner than the current interval
+/// allows. Each such poll raises the interval by 5 seconds (RFC 8628 §3.5 `slow_down`).
+pub async fn record_device_poll(
+    redis: &ConnectionManager,
+    stored: &StoredDeviceGrant,
+) -> redis::RedisResult<bool> {
+    let key = poll_key(&stored.key);
+    let mut conn = redis.clone();
+    let payload: Option<String> = conn.get(&key).await?;
+    let Some(mut polling) = payload.and_then(|p| serde_json::from_str::<DevicePolling>(&p).ok())
+    else {
+        // Expires with the grant, which is gone by the next poll.
+        return Ok(false);
+    };
+
+    let now = Utc::now().timestamp();
+    let too_fast = polling
+        .last_polled_at
+        .is_some_and(|last| now - last < polling.interval as i64);
+    polling.last_polled_at = Some(now);
+    if too_fast {
+        polling.interval += 5;
+    }
+    let payload = serde_json::to_string(&polling).expect("polling serializes");
+    let keep_ttl = SetOptions::default()
+        .conditional_set(redis::ExistenceCheck::XX)
+        .with_expiration(SetExpiry::KEEPTTL);
+    conn.set_options::<_, _, Option<String>>(&key, payload, keep_ttl)
+        .await?;
+    Ok(too_fast)
+}
+
+/// Remove both codes once the device has its answer. Returns `false` when a concurrent poll
+/// already did, so only one of them hands out tokens.
+pub async fn finish_device_grant(
+    redis: &ConnectionManager,
+    stored: &StoredDeviceGrant,
+) -> redis::RedisResult<bool> {
+    let mut conn = redis.clone();
+    let removed: i64 = conn.del(&stored.key).await?;
+    conn.del::<_, ()>(&[
+        poll_key(&stored.key),
+        format!("oauth:device_user:{}", stored.grant.user_code),
+    ])
+    .await?;
+    Ok(removed == 1)
+}
+
+//===============================
+// PKCE
+//===============================
+/// RFC 7636 `S256`: the verifier must hash to the challenge sent with the authorization request.
+pub fn verify_pkce(verifier: &str, challenge: &str) -> bool {
+    let well_formed = (43..=128).contains(&verifier.len())
+        && verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~'));
+    well_formed && constant_time_eq(&sha256_base64url(verifier), challenge)
+}
+
+//===============================
+// Client Authentication
+//===============================
+#[derive(Clone)]
+pub struct ClientCredentials {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+/// Read credentials from `client_secret_basic` or, failing that, `client_secret_post`.
+pub fn client_credentials(
+    req: &HttpRequest,
+    form_client_id: Option<&str>,
+    form_client_secret: Option<&str>,
+) -> Option<ClientCredentials> {
+    let basic = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|pair| {
+            let (id, secret) = pair.split_once(':')?;
+            Some(ClientCredentials {
+                client_id: id.to_string(),
+                client_secret: Some(secret.to_string()),
+            })
+        });
+
+    basic.or_else(|| {
+        Some(ClientCredentials {
+            client_id: form_client_id?.to_string(),
+            client_secret: form_client_secret.map(str::to_string),
+        })
+    })
+}
+
+/// Authenticate the calling client. Confidential clients must present their secret;
+/// public clients identify themselves by `client_id` and are bound to PKCE.
+pub async fn authenticate_client(
+    db: &DatabaseConnection,
+    credentials: Option<ClientCredentials>,
+) -> Result<Model, HttpResponse> {
+    let invalid_client = || {
+        oauth_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+            "Client authentication failed",
+        )
+    };
+    let credentials = credentials.ok_or_else(invalid_client)?;
+
+    let client = match Entity::find()
+        .filter(Column::ClientId.eq(&credentials.client_id))
+        .one(db)
+        .await
+    {
+        Ok(Some(client)) => client,
+        Ok(None) => return Err(invalid_client()),
+        Err(e) => {
+            error!("Database error: {}", e);
+            return Err(server_error());
+        }
+    };
+
+    let authenticated = match (&client.client_secret_hash, &credentials.client_secret) {
+        (Some(hash), Some(secret)) => constant_time_eq(hash, &sha256_hex(secret)),
+        (None, None) => true,
+        _ => false,
+    };
+    if !authenticated {
+        warn!("Client authentication failed for {}", client.client_id);
+        return Err(invalid_client());
+    }
+    Ok(client)
+}
+
+/// Authenticate a service account for the client-credentials grant. A secret is always required.
+pub async fn authenticate_service_account(
+    db: &DatabaseConnection,
+    credentials: Option<ClientCredentials>,
+) -> Result<service_account_model::Model, HttpResponse> {
+    let invalid_client = || {
+        oauth_error(
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+            "Client authentication failed",
+        )
+    };
+    let credentials = credentials.ok_or_else(invalid_client)?;
+
+    let account = match ServiceAccount::find()
+        .filter(ServiceAccountColumn::ClientId.eq(&credentials.client_id))
+        .filter(ServiceAccountColumn::Active.eq(true))
+        .one(db)
+        .await
+    {
+        Ok(Some(account)) => account,
+        Ok(None) => return Err(invalid_client()),
+        Err(e) => {
+            error!("Database error: {}", e);
+            return Err(server_error());
+        }
+    };
+
+    let authenticated = match (&account.client_secret_hash, &credentials.client_secret) {
+        (Some(hash), Some(secret)) => constant_time_eq(hash, &sha256_hex(secret)),
+        _ => false,
+    };
+    if !authenticated {
+        warn!(
+            "Client authentication failed for service account {}",
+            account.name
+        );
+        return Err(invalid_client());
+    }
+    Ok(account)
+}
+
+/// Caller of the introspection and revocation endpoints.
+pub enum OAuthCaller {
+    Client(Model),
+    Service(service_account_model::Model),
+}
+
+impl OAuthCaller {
+    pub fn client_id(&self) -> &str {
+        match self {
+            OAuthCaller::Client(client) => &client.client_id,
+            OAuthCaller::Service(account) => account.client_id.as_deref().unwrap_or_default(),
+        }
+    }
+
+    /// Whether the caller proved possession of a secret.
+    pub fn is_confidential(&self) -> bool {
+        match self {
+            OAuthCaller::Client(client) => client.confidential,
+            OAuthCaller::Service(_) => true,
+        }
+    }
+}
+
+/// Authenticate a registered OAuth client, falling back to a service account.
+pub async fn authenticate_caller(
+    db: &DatabaseConnection,
+    credentials: Option<ClientCredentials>,
+) -> Result<OAuthCaller, HttpResponse> {
+    match authenticate_client(db, credentials.clone()).await {
+        Ok(client) => Ok(OAuthCaller::Client(client)),
+        Err(_) => authenticate_service_account(db, credentials)
+            .await
+            .map(OAuthCaller::Service),
+    }
+}
+
+/// Random client identifier and secret for a new registration.
+pub fn generate_client_credentials() -> (String, String) {
+    (random_hex(16), random_hex(32))
+}
@@ -0,0 +1,147 @@
+// src/utils/oidc.rs
+use crate::models::auth_model;
+use chrono::{Duration, Utc};
+use jsonwebtoken::jwk::{Jwk, JwkSet, KeyAlgorithm, PublicKeyUse, ThumbprintHash};
+use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+use serde_json::{Map, Value, json};
+use std::env;
+use std::io;
+
+//===============================
+// OIDC Provider Configuration
+//===============================
+/// RS256 signing key and issuer used for ID tokens. Asymmetric signing lets public clients
+/// verify ID tokens against the published JWKS without sharing a secret.
+pub struct OidcProvider {
+    pub issuer: String,
+    key_id: String,
+    encoding_key: EncodingKey,
+    jwk: Jwk,
+}
+
+impl OidcProvider {
+    /// OpenID Connect is enabled when `OIDC_SIGNING_KEY_PATH` is set.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let Ok(key_path) = env::var("OIDC_SIGNING_KEY_PATH") else {
+            return Ok(None);
+        };
+        let issuer = env::var("OIDC_ISSUER")
+            .expect("OIDC_ISSUER must be set with OIDC_SIGNING_KEY_PATH")
+            .trim_end_matches('/')
+            .to_string();
+
+        let pem = std::fs::read(&key_path)?;
+        let encoding_key = EncodingKey::from_rsa_pem(&pem)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut jwk = Jwk::from_encoding_key(&encoding_key, Algorithm::RS256)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // The RFC 7638 thumbprint changes with the key, so rotated keys get a new `kid`.
+        let key_id = jwk.thumbprint(ThumbprintHash::SHA256);
+        jwk.common.key_id = Some(key_id.clone());
+        jwk.common.public_key_use = Some(PublicKeyUse::Signature);
+        jwk.common.key_algorithm = Some(KeyAlgorithm::RS256);
+
+        Ok(Some(Self {
+            issuer,
+            key_id,
+            encoding_key,
+            jwk,
+        }))
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: vec![self.jwk.clone()],
+        }
+    }
+
+    /// `/.well-known/openid-configuration` document (OpenID Connect Discovery 1.0).
+    pub fn discovery_document(&self, scopes: &[&str]) -> Value {
+        let issuer = &self.issuer;
+        json!({
+            "issuer":issuer,
+            "authorization_endpoint":format!("{}/oauth/authorize", issuer),
+            "token_endpoint":format!("{}/oauth/token", issuer),
+            "device_authorization_endpoint":format!("{}/oauth/device_authorization", issuer),
+            "userinfo_endpoint":format!("{}/oauth/userinfo", issuer),
+            "jwks_uri":format!("{}/.well-known/jwks.json", issuer),
+            "introspection_endpoint":format!("{}/oauth/introspect", issuer),
+            "revocation_endpoint":format!("{}/oauth/revoke", issuer),
+            "scopes_supported":scopes,
+            "response_types_supported":["code"],
+            "response_modes_supported":["query"],
+            "grant_types_supported":[
+                "authorization_code","refresh_token","client_credentials",
+                "urn:ietf:params:oauth:grant-type:device_code",
+            ],
+            "subject_types_supported":["public"],
+            "id_token_signing_alg_values_supported":["RS256"],
+            "token_endpoint_auth_methods_supported":["client_secret_basic","client_secret_post","none"],
+            "code_challenge_methods_supported":["S256"],
+            "claims_supported":[
+                "iss","sub","aud","exp","iat","auth_time","nonce","azp",
+                "preferred_username","updated_at","email","email_verified",
+                "phone_number","phone_number_verified",
+            ],
+        })
+    }
+
+    /// Sign an ID token for `user`, including the claims released by `scope`.
+    pub fn encode_id_token(
+        &self,
+        user: &auth_model::Model,
+        client_id: &str,
+        scope: &str,
+        auth_time: i64,
+        nonce: Option<&str>,
+        ttl: Duration,
+    ) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = Utc::now();
+        let mut claims = Map::new();
+        claims.insert("iss".into(), json!(self.issuer));
+        claims.insert("aud".into(), json!(client_id));
+        claims.insert("azp".into(), json!(client_id));
+        claims.insert("iat".into(), json!(now.timestamp()));
+        claims.insert("exp".into(), json!((now + ttl).timestamp()));
+        claims.insert("auth_time".into(), json!(auth_time));
+        if let Some(nonce) = nonce {
+            claims.insert("nonce".into(), json!(nonce));
+        }
+        claims.extend(user_claims(user, scope));
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(self.key_id.clone());
+        encode(&header, &claims, &self.encoding_key)
+    }
+}
+
+//===============================
+// Standard Claims
+//===============================
+/// Claims about `user` released by the granted scopes (OpenID Connect Core §5.4).
+/// `sub` is the stable user id, so it survives username changes.
+pub fn user_claims(user: &auth_model::Model, scope: &str) -> Map<String, Value> {
+    let scopes: Vec<&str> = scope.split_whitespace().collect();
+    let mut claims = Map::new();
+    claims.insert("sub".into(), json!(user.id.to_string()));
+
+    if scopes.contains(&"profile") {
+        claims.insert("preferred_username".into(), json!(user.username));
+        claims.insert("updated_at".into(), json!(user.updated_at.timestamp()));
+    }
+    if scopes.contains(&"email") {
+        claims.insert("email".into(), json!(user.email));
+        claims.insert(
+            "email_verified".into(),
+            json!(user.email_verified_at.is_some()),
+        );
+    }
+    if scopes.contains(&"phone") {
+        claims.insert("phone_number".into(), json!(user.phone));
+        claims.insert(
+            "phone_number_verified".into(),
+            json!(user.phone_verified_at.is_some()),
+        );
+    }
+    claims
+}
@@ -0,0 +1,163 @@
+// src/utils/otp.rs
+use crate::utils::crypto::{constant_time_eq, random_hex, random_string, sha256_hex};
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, SetExpiry, SetOptions};
+use std::env;
+
+const OTP_LEN: usize = 6;
+const DAY: i64 = 24 * 60 * 60;
+
+//===============================
+// One-Time Codes
+//===============================
+// Codes are kept per user and per purpose (`email_login`, `phone_verify`, `sms_login`), so a
+// code sent for one flow is never accepted by another.
+pub enum OtpCheck {
+    Valid,
+    /// Wrong code, or none pending (expired or already used).
+    Invalid,
+    /// The attempt cap was reached; the pending code has been discarded.
+    TooManyAttempts,
+}
+
+fn code_key(purpose: &str, user_id: i32) -> String {
+    format!("otp:{}:{}", purpose, user_id)
+}
+
+fn attempts_key(purpose: &str, user_id: i32) -> String {
+    format!("otp_attempts:{}:{}", purpose, user_id)
+}
+
+/// Six digits are guessable offline from a plain hash, so the digest is keyed with the
+/// server secret and bound to the purpose and user.
+fn digest(purpose: &str, user_id: i32, code: &str) -> String {
+    let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    sha256_hex(&format!("otp:{}:{}:{}:{}", purpose, secret, user_id, code))
+}
+
+/// Replace any pending `purpose` code for `user_id` with a new one and reset its attempt count.
+pub async fn issue_code(
+    redis: &ConnectionManager,
+    purpose: &str,
+    user_id: i32,
+    ttl: u64,
+) -> redis::RedisResult<String> {
+    let code = random_string(b"0123456789", OTP_LEN);
+    let mut conn = redis.clone();
+    conn.set_ex::<_, _, ()>(
+        code_key(purpose, user_id),
+        digest(purpose, user_id, &code),
+        ttl,
+    )
+    .await?;
+    conn.set_ex::<_, _, ()>(attempts_key(purpose, user_id), 0, ttl)
+        .await?;
+    Ok(code)
+}
+
+/// Check `code` against the pending one. Every check counts as an attempt; a valid code is
+/// consumed, so concurrent checks of the same code succeed at most once.
+pub async fn verify_code(
+    redis: &ConnectionManager,
+    purpose: &str,
+    user_id: i32,
+    code: &str,
+    max_attempts: u64,
+) -> redis::RedisResult<OtpCheck> {
+    let mut conn = redis.clone();
+    let Some(stored) = conn
+        .get::<_, Option<String>>(code_key(purpose, user_id))
+        .await?
+    else {
+        return Ok(OtpCheck::Invalid);
+    };
+
+    let attempts: u64 = conn.incr(attempts_key(purpose, user_id), 1).await?;
+    if attempts > max_attempts {
+        conn.del::<_, ()>(&[code_key(purpose, user_id), attempts_key(purpose, user_id)])
+            .await?;
+        return Ok(OtpCheck::TooManyAttempts);
+    }
+    if !constant_time_eq(&stored, &digest(purpose, user_id, code)) {
+        return Ok(OtpCheck::Invalid);
+    }
+
+    let consumed: u64 = conn.del(code_key(purpose, user_id)).await?;
+    conn.del::<_, ()>(attempts_key(purpose, user_id)).await?;
+    Ok(if consumed == 1 {
+        OtpCheck::Valid
+    } else {
+        OtpCheck::Invalid
+    })
+}
+
+//===============================
+// Send Limits
+//===============================
+/// `true` if a `purpose` code may be sent to `user_id` now, starting the cooldown.
+pub async fn start_cooldown(
+    redis: &ConnectionManager,
+    purpose: &str,
+    user_id: i32,
+    seconds: u64,
+) -> redis::RedisResult<bool> {
+    let nx = SetOptions::default()
+        .conditional_set(redis::ExistenceCheck::NX)
+        .with_expiration(SetExpiry::EX(seconds));
+    let mut conn = redis.clone();
+    conn.set_options(format!("otp_cooldown:{}:{}", purpose, user_id), 1, nx)
+        .await
+}
+
+/// Count one send against `user_id`'s daily budget, shared by every `purpose` on `channel`;
+/// `false` once more than `limit` were sent in the current 24-hour window.
+pub async fn within_daily_limit(
+    redis: &ConnectionManager,
+    channel: &str,
+    user_id: i32,
+    limit: u64,
+) -> redis::RedisResult<bool> {
+    let key = format!("otp_daily:{}:{}", channel, user_id);
+    let mut conn = redis.clone();
+    let sent: u64 = conn.incr(&key, 1).await?;
+    if sent == 1 {
+        conn.expire::<_, ()>(&key, DAY).await?;
+    }
+    Ok(sent <= limit)
+}
+
+//===============================
+// Second-Factor Challenges
+//===============================
+/// Opaque token returned by a first-factor login, exchanged with a code for the login JWT.
+pub async fn start_challenge(
+    redis: &ConnectionManager,
+    user_id: i32,
+    ttl: u64,
+) -> redis::RedisResult<String> {
+    let token = random_hex(32);
+    let mut conn = redis.clone();
+    conn.set_ex::<_, _, ()>(
+        format!("mfa_challenge:{}", sha256_hex(&token)),
+        user_id,
+        ttl,
+    )
+    .await?;
+    Ok(token)
+}
+
+/// The user a pending challenge belongs to. The challenge stays valid until `end_challenge`.
+pub async fn challenge_user(
+    redis: &ConnectionManager,
+    token: &str,
+) -> redis::RedisResult<Option<i32>> {
+    let mut conn = redis.clone();
+    conn.get(format!("mfa_challenge:{}", sha256_hex(token)))
+        .await
+}
+
+pub async fn end_challenge(redis: &ConnectionManager, token: &str) -> redis::RedisResult<()> {
+    let mut conn = redis.clone();
+    conn.del(format!("mfa_challenge:{}", sha256_hex(token)))
+        .await
+}
@@ -0,0 +1,112 @@
+// src/utils/passwordless.rs
+use crate::utils::crypto::{random_hex, sha256_hex};
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use std::env;
+
+//===============================
+// Passwordless Configuration
+//===============================
+/// Email-based sign-in without a password. Each method is off unless listed in
+/// `PASSWORDLESS_METHODS` (`magic_link`, `email_otp`).
+#[derive(Clone, Debug)]
+pub struct PasswordlessConfig {
+    pub magic_link: bool,
+    pub email_otp: bool,
+    /// Front-end page the emailed link opens with a `token` query parameter; the page posts it
+    /// to `/magic-link/verify`. Links never point at the API itself, so mail scanners that
+    /// prefetch links cannot consume them.
+    pub magic_link_url: Option<String>,
+    pub magic_link_ttl: u64,
+    pub otp_ttl: u64,
+    /// Wrong codes accepted before the pending code is discarded.
+    pub otp_max_attempts: u64,
+    /// Minimum seconds between two emails to the same user.
+    pub resend_cooldown: u64,
+    /// Sign-in emails (links and codes together) sent to one user per day.
+    pub daily_limit: u64,
+    /// Lifetime of the code emailed when an administrator forces a password reset.
+    pub reset_code_ttl: u64,
+}
+
+impl PasswordlessConfig {
+    pub fn from_env() -> Self {
+        let methods = env::var("PASSWORDLESS_METHODS").unwrap_or_default();
+        let mut config = Self {
+            magic_link: false,
+            email_otp: false,
+            magic_link_url: env::var("MAGIC_LINK_URL").ok(),
+            magic_link_ttl: seconds("MAGIC_LINK_TTL", 900),
+            otp_ttl: seconds("EMAIL_OTP_TTL", 600),
+            otp_max_attempts: env::var("EMAIL_OTP_MAX_ATTEMPTS")
+                .map(|v| v.parse().expect("EMAIL_OTP_MAX_ATTEMPTS must be a number"))
+                .unwrap_or(5),
+            resend_cooldown: seconds("PASSWORDLESS_RESEND_COOLDOWN", 60),
+            daily_limit: env::var("PASSWORDLESS_DAILY_LIMIT")
+                .map(|v| {
+                    v.parse()
+                        .expect("PASSWORDLESS_DAILY_LIMIT must be a number")
+                })
+                .unwrap_or(10),
+            reset_code_ttl: seconds("PASSWORD_RESET_TTL", 86400),
+        };
+        for method in methods.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+            match method {
+                "magic_link" => config.magic_link = true,
+                "email_otp" => config.email_otp = true,
+                other => panic!(
+                    "PASSWORDLESS_METHODS must list magic_link or email_otp, got {}",
+                    other
+                ),
+            }
+        }
+        if config.magic_link && config.magic_link_url.is_none() {
+            panic!("MAGIC_LINK_URL must be set when magic_link is enabled");
+        }
+        config
+    }
+
+    /// Whether users can confirm their email, by signing in with an emailed code or link.
+    pub fn confirms_email(&self) -> bool {
+        self.magic_link || self.email_otp
+    }
+}
+
+fn seconds(key: &str, default: u64) -> u64 {
+    env::var(key)
+        .map(|v| {
+            v.parse()
+                .unwrap_or_else(|_| panic!("{} must be a number of seconds", key))
+        })
+        .unwrap_or(default)
+}
+
+//===============================
+// Magic Links
+//===============================
+/// Store a single-use sign-in token for `user_id` and return it. Only its hash is kept.
+pub async fn issue_magic_link(
+    redis: &ConnectionManager,
+    user_id: i32,
+    ttl: u64,
+) -> redis::RedisResult<String> {
+    let token = random_hex(32);
+    let mut conn = redis.clone();
+    conn.set_ex::<_, _, ()>(
+        format!("passwordless:link:{}", sha256_hex(&token)),
+        user_id,
+        ttl,
+    )
+    .await?;
+    Ok(token)
+}
+
+/// Consume a magic-link token, returning the user it was issued for.
+pub async fn redeem_magic_link(
+    redis: &ConnectionManager,
+    token: &str,
+) -> redis::RedisResult<Option<i32>> {
+    let mut conn = redis.clone();
+    conn.get_del(format!("passwordless:link:{}", sha256_hex(token)))
+        .await
+}
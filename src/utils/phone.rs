@@ -0,0 +1,29 @@
+// src/utils/phone.rs
+use phonenumber::{Mode, country};
+use std::env;
+
+/// Region assumed for numbers written without a `+` country code, from `PHONE_DEFAULT_REGION`
+/// (ISO 3166 alpha-2, e.g. `TH`). Without it only international numbers are accepted.
+pub fn default_region() -> Option<country::Id> {
+    let region = env::var("PHONE_DEFAULT_REGION").ok()?;
+    let region = region.trim();
+    if region.is_empty() {
+        return None;
+    }
+    Some(region.to_ascii_uppercase().parse().unwrap_or_else(|_| {
+        panic!(
+            "PHONE_DEFAULT_REGION must be an ISO 3166 country code, got {}",
+            region
+        )
+    }))
+}
+
+/// Parse `input` in any common notation (`+66 81 234 5678`, `081-234-5678`, ...) and return
+/// it in E.164 (`+66812345678`), the form phone numbers are stored and compared in. `None`
+/// when it is not a valid number for its region.
+pub fn normalize_phone(input: &str) -> Option<String> {
+    let number = phonenumber::parse(default_region(), input.trim()).ok()?;
+    number
+        .is_valid()
+        .then(|| number.format().mode(Mode::E164).to_string())
+}
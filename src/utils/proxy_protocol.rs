@@ -0,0 +1,239 @@
+// src/utils/proxy_protocol.rs
+use crate::utils::client_ip::TrustedProxies;
+use crate::utils::tls::{HANDSHAKE_TIMEOUT, store_peer_certificate};
+use actix_http::{HttpService, Protocol, Request, Response, body::MessageBody};
+use actix_service::{
+    IntoServiceFactory, Service, ServiceFactory, ServiceFactoryExt, fn_service, map_config,
+};
+use actix_web::{Error, dev::AppConfig};
+use rustls::ServerConfig;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsAcceptor, server::TlsStream};
+use tracing::warn;
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const V1_MAX_LEN: usize = 107;
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+//===============================
+// HTTP Service over PROXY protocol
+//===============================
+/// HTTP service that expects every connection to start with a PROXY protocol (v1 or v2) header.
+/// The address announced in the header becomes the request's peer address, so `ClientIp`
+/// resolves it like any directly connected client. With a TLS config the handshake follows the
+/// header, and HTTP/2 is served when negotiated through ALPN.
+///
+/// Connections from peers outside `TRUSTED_PROXIES` are refused, since anyone able to send the
+/// header could otherwise claim any source address.
+pub fn http_service<I, S, B>(
+    app: I,
+    trusted: TrustedProxies,
+    tls: Option<Arc<ServerConfig>>,
+) -> impl ServiceFactory<TcpStream, Config = (), Response = (), Error = (), InitError = ()>
+where
+    I: IntoServiceFactory<S, Request>,
+    S: ServiceFactory<Request, Config = AppConfig> + 'static,
+    S::Error: Into<Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service<Request>>::Future: 'static,
+    S::Service: 'static,
+    B: MessageBody + 'static,
+{
+    let app = app
+        .into_factory()
+        .map_err(|err| err.into().error_response());
+    let http = HttpService::build()
+        .on_connect_ext(|io: &ProxiedStream, ext| {
+            if let ProxiedStream::Tls(tls) = io {
+                store_peer_certificate(tls.get_ref().1, ext);
+            }
+        })
+        .finish(map_config(app, |_| AppConfig::default()));
+
+    let acceptor = tls.map(|config| {
+        // Offer HTTP/2 first; clients without ALPN support fall back to HTTP/1.1.
+        let mut config = (*config).clone();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        TlsAcceptor::from(Arc::new(config))
+    });
+
+    fn_service(move |mut io: TcpStream| {
+        let trusted = trusted.clone();
+        let acceptor = acceptor.clone();
+        async move {
+            let peer = io.peer_addr()?;
+            if !trusted.contains(peer.ip()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("PROXY header from untrusted peer {}", peer),
+                ));
+            }
+
+            let source = tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut io))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "PROXY header timeout"))??;
+
+            let peer = Some(source.unwrap_or(peer));
+
+            match acceptor {
+                Some(acceptor) => {
+                    // A client that stalls mid-handshake must not hold the connection open.
+                    let tls = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(io))
+                        .await
+                        .map_err(|_| {
+                            io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timeout")
+                        })??;
+                    let protocol = match tls.get_ref().1.alpn_protocol() {
+                        Some(b"h2") => Protocol::Http2,
+                        _ => Protocol::Http1,
+                    };
+                    Ok((ProxiedStream::Tls(Box::new(tls)), protocol, peer))
+                }
+                None => Ok((ProxiedStream::Plain(io), Protocol::Http1, peer)),
+            }
+        }
+    })
+    .map_err(|e: io::Error| warn!("Rejected PROXY protocol connection: {}", e))
+    .and_then(http.map_err(|e| warn!("HTTP dispatch error: {}", e)))
+}
+
+//===============================
+// Connection Stream
+//===============================
+/// A connection after its PROXY header was consumed, with or without TLS on top.
+pub enum ProxiedStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for ProxiedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ProxiedStream::Plain(io) => Pin::new(io).poll_read(cx, buf),
+            ProxiedStream::Tls(io) => Pin::new(io.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ProxiedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ProxiedStream::Plain(io) => Pin::new(io).poll_write(cx, buf),
+            ProxiedStream::Tls(io) => Pin::new(io.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ProxiedStream::Plain(io) => Pin::new(io).poll_flush(cx),
+            ProxiedStream::Tls(io) => Pin::new(io.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ProxiedStream::Plain(io) => Pin::new(io).poll_shutdown(cx),
+            ProxiedStream::Tls(io) => Pin::new(io.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+//===============================
+// Header Parsing
+//===============================
+/// Consume the PROXY header from the stream and return the announced source address.
+/// `None` means the header carried no address (v1 `UNKNOWN` or v2 `LOCAL`).
+async fn read_header(io: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    // Both versions are at least 12 bytes long, so this never reads past the header.
+    let mut prefix = [0u8; 12];
+    io.read_exact(&mut prefix).await?;
+
+    if &prefix == V2_SIGNATURE {
+        read_v2(io).await
+    } else if prefix.starts_with(b"PROXY ") {
+        let mut line = prefix.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LEN {
+                return Err(invalid("PROXY v1 header too long"));
+            }
+            line.push(io.read_u8().await?);
+        }
+        parse_v1(&line[..line.len() - 2])
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("PROXY v1 header not ASCII"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", src, _dst, src_port, _dst_port] => {
+            let ip: IpAddr = src.parse().map_err(|_| invalid("bad PROXY v1 address"))?;
+            let port: u16 = src_port.parse().map_err(|_| invalid("bad PROXY v1 port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed PROXY v1 header")),
+    }
+}
+
+async fn read_v2(io: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let version_command = io.read_u8().await?;
+    let family = io.read_u8().await?;
+    let len = io.read_u16().await? as usize;
+    let mut body = vec![0u8; len];
+    io.read_exact(&mut body).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    // LOCAL connections (health checks from the proxy itself) carry no client address.
+    if version_command & 0x0F == 0 {
+        return Ok(None);
+    }
+
+    match family >> 4 {
+        // AF_INET: src(4) dst(4) src_port(2) dst_port(2)
+        1 if body.len() >= 12 => {
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // AF_INET6: src(16) dst(16) src_port(2) dst_port(2)
+        2 if body.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&body[..16]);
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        // AF_UNSPEC or AF_UNIX: nothing usable as a client IP.
+        0 | 3 => Ok(None),
+        _ => Err(invalid("malformed PROXY v2 address block")),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,62 @@
+// src/utils/revocation.rs
+use chrono::Utc;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+
+//===============================
+// JWT Blacklist
+//===============================
+// JWTs are stateless, so a revoked token is remembered in Redis until it would have expired.
+fn blacklist_key(token: &str) -> String {
+    format!("bl:{}", token)
+}
+
+/// Blacklist `token` until its `exp`. Returns `false` when it has already expired.
+pub async fn revoke_jwt(
+    redis: &ConnectionManager,
+    token: &str,
+    exp: usize,
+) -> redis::RedisResult<bool> {
+    let ttl = (exp as i64).saturating_sub(Utc::now().timestamp());
+    if ttl <= 0 {
+        return Ok(false);
+    }
+    let mut conn = redis.clone();
+    conn.set_ex::<_, _, ()>(blacklist_key(token), "1", ttl as u64)
+        .await?;
+    Ok(true)
+}
+
+pub async fn is_jwt_revoked(redis: &ConnectionManager, token: &str) -> redis::RedisResult<bool> {
+    let mut conn = redis.clone();
+    conn.exists(blacklist_key(token)).await
+}
+
+//===============================
+// Per-User Cutoff
+//===============================
+// Revoking every session of a user records when it happened; tokens issued before that second
+// are rejected, so signing in again right away works. Kept without expiry.
+fn cutoff_key(username: &str) -> String {
+    format!("revoked_before:{}", username)
+}
+
+/// Invalidate every login token, access token and refresh token issued to `username` so far.
+pub async fn revoke_user_tokens(
+    redis: &ConnectionManager,
+    username: &str,
+) -> redis::RedisResult<()> {
+    let mut conn = redis.clone();
+    conn.set(cutoff_key(username), Utc::now().timestamp()).await
+}
+
+/// Whether a token issued to `username` at `issued_at` (Unix seconds) was revoked since.
+pub async fn is_user_token_revoked(
+    redis: &ConnectionManager,
+    username: &str,
+    issued_at: i64,
+) -> redis::RedisResult<bool> {
+    let mut conn = redis.clone();
+    let cutoff: Option<i64> = conn.get(cutoff_key(username)).await?;
+    Ok(cutoff.is_some_and(|cutoff| issued_at < cutoff))
+}
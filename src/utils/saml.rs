@@ -0,0 +1,880 @@
+// src/utils/saml.rs
+use crate::services::identity_service::ExternalIdentity;
+use crate::utils::crypto::{constant_time_eq, random_hex, sha256_hex};
+use crate::utils::sso::Provisioning;
+use actix_web::cookie::time::Duration as CookieDuration;
+use actix_web::cookie::{Cookie, SameSite};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bergshamra::dsig::verify::verify_all_document_with_source;
+use bergshamra::keys::loader::load_x509_cert_der;
+use bergshamra::{DsigContext, KeysManager, VerifyResult};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, SetExpiry, SetOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::io::Write;
+use uppsala::{Document, NodeId};
+use url::Url;
+
+const PROTOCOL_NS: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const ASSERTION_NS: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const STATUS_SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const REQUEST_TTL_SECS: u64 = 600;
+/// Cookie tying an AuthnRequest to the browser that started the sign-in.
+pub const BINDING_COOKIE: &str = "saml_binding";
+/// Tolerated clock difference with the IdP when checking assertion validity windows.
+const CLOCK_SKEW_SECS: i64 = 60;
+
+//===============================
+// Tenant Configuration
+//===============================
+/// `auth_users` fields and the SAML attribute names they are read from. The username falls
+/// back to the `NameID`.
+#[derive(Clone, Debug)]
+struct AttributeMap {
+    email: String,
+    username: Option<String>,
+    phone: Option<String>,
+}
+
+/// An enterprise tenant's identity provider, trusted for one SP entity.
+pub struct SamlTenant {
+    pub name: String,
+    pub idp_entity_id: String,
+    idp_sso_url: String,
+    /// DER of the IdP signing certificate; the only key assertions are verified against.
+    idp_certificate: Vec<u8>,
+    pub sp_entity_id: String,
+    pub acs_url: String,
+    attributes: AttributeMap,
+    pub provisioning: Provisioning,
+}
+
+/// Tenants from `SAML_TENANTS`, each configured by `SAML_<TENANT>_*` variables.
+pub struct SamlConfig {
+    tenants: HashMap<String, SamlTenant>,
+}
+
+impl SamlConfig {
+    pub fn from_env() -> std::io::Result<Self> {
+        let names: Vec<String> = env::var("SAML_TENANTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+        let mut tenants = HashMap::new();
+        if !names.is_empty() {
+            // The entity ID and ACS URL are compared against signed assertions, so they cannot
+            // be derived from the request's Host header.
+            let base_url = env::var("SAML_SP_BASE_URL")
+                .expect("SAML_SP_BASE_URL must be set with SAML_TENANTS")
+                .trim_end_matches('/')
+                .to_string();
+            for name in names {
+                let tenant = SamlTenant::from_env(name.clone(), &base_url)?;
+                tenants.insert(name, tenant);
+            }
+        }
+        Ok(Self { tenants })
+    }
+
+    pub fn tenant(&self, name: &str) -> Option<&SamlTenant> {
+        self.tenants.get(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+    }
+}
+
+impl SamlTenant {
+    fn from_env(name: String, base_url: &str) -> std::io::Result<Self> {
+        let prefix = format!("SAML_{}", name.to_ascii_uppercase());
+        let var = |key: &str| env::var(format!("{}_{}", prefix, key)).ok();
+        let required =
+            |key: &str| var(key).unwrap_or_else(|| panic!("{}_{} must be set", prefix, key));
+
+        let pem = std::fs::read_to_string(required("IDP_CERT_PATH"))?;
+        let idp_certificate = pem_to_der(&pem).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}_IDP_CERT_PATH is not a PEM certificate", prefix),
+            )
+        })?;
+        // Fail at startup rather than on the first login.
+        load_x509_cert_der(&idp_certificate)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+
+        let sp_base = format!("{}/api/v1/auth/saml/{}", base_url, name);
+        Ok(Self {
+            idp_entity_id: required("IDP_ENTITY_ID"),
+            idp_sso_url: required("IDP_SSO_URL"),
+            idp_certificate,
+            sp_entity_id: var("SP_ENTITY_ID").unwrap_or_else(|| format!("{}/metadata", sp_base)),
+            acs_url: format!("{}/acs", sp_base),
+            attributes: AttributeMap {
+                email: var("ATTRIBUTE_EMAIL").unwrap_or_else(|| "email".to_string()),
+                username: var("ATTRIBUTE_USERNAME"),
+                phone: var("ATTRIBUTE_PHONE"),
+            },
+            provisioning: Provisioning::from_env(&format!("{}_PROVISIONING", prefix)),
+            name,
+        })
+    }
+
+    /// Cookie carrying the browser binding secret, only sent back to the SAML routes. The IdP
+    /// returns the browser with a cross-site form POST, which only carries `SameSite=None`
+    /// cookies; those must be `Secure`, so plain-HTTP deployments fall back to `Lax`.
+    pub fn binding_cookie(&self, secret: &str) -> Cookie<'static> {
+        let secure = self.acs_url.starts_with("https://");
+        Cookie::build(BINDING_COOKIE, secret.to_string())
+            .path("/api/v1/auth/saml/")
+            .http_only(true)
+            .secure(secure)
+            .same_site(if secure {
+                SameSite::None
+            } else {
+                SameSite::Lax
+            })
+            .max_age(CookieDuration::seconds(REQUEST_TTL_SECS as i64))
+            .finish()
+    }
+
+    /// `identities.provider` value for this tenant's users.
+    pub fn provider_name(&self) -> String {
+        format!("saml:{}", self.name)
+    }
+
+    /// SP metadata to hand to the IdP administrator.
+    pub fn metadata(&self) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<md:EntityDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" entityID="{entity_id}">
+  <md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" protocolSupportEnumeration="{protocol}">
+    <md:NameIDFormat>urn:oasis:names:tc:SAML:1.1:nameid-format:unspecified</md:NameIDFormat>
+    <md:AssertionConsumerService Binding="urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST" Location="{acs}" index="0" isDefault="true"/>
+  </md:SPSSODescriptor>
+</md:EntityDescriptor>
+"#,
+            entity_id = xml_escape(&self.sp_entity_id),
+            protocol = PROTOCOL_NS,
+            acs = xml_escape(&self.acs_url),
+        )
+    }
+
+    /// IdP URL carrying a new `AuthnRequest` (HTTP-Redirect binding: DEFLATE, base64, query).
+    pub fn authn_request_url(&self, request_id: &str) -> String {
+        let request = format!(
+            r#"<samlp:AuthnRequest xmlns:samlp="{protocol}" xmlns:saml="{assertion}" ID="{id}" Version="2.0" IssueInstant="{now}" Destination="{destination}" AssertionConsumerServiceURL="{acs}" ProtocolBinding="urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST"><saml:Issuer>{issuer}</saml:Issuer><samlp:NameIDPolicy AllowCreate="true"/></samlp:AuthnRequest>"#,
+            protocol = PROTOCOL_NS,
+            assertion = ASSERTION_NS,
+            id = request_id,
+            now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            destination = xml_escape(&self.idp_sso_url),
+            acs = xml_escape(&self.acs_url),
+            issuer = xml_escape(&self.sp_entity_id),
+        );
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(request.as_bytes())
+            .expect("writing to a Vec cannot fail");
+        let deflated = encoder.finish().expect("writing to a Vec cannot fail");
+
+        let mut url = Url::parse(&self.idp_sso_url).expect("IDP_SSO_URL must be absolute");
+        url.query_pairs_mut()
+            .append_pair("SAMLRequest", &STANDARD.encode(deflated));
+        url.to_string()
+    }
+
+    /// Check a base64 `SAMLResponse` from the ACS endpoint: the assertion must be signed by the
+    /// tenant's IdP, issued by it for this SP and ACS URL, and within its validity window.
+    pub fn validate_response(&self, saml_response: &str) -> Result<ValidatedAssertion, String> {
+        let compact: String = saml_response.split_whitespace().collect();
+        let bytes = STANDARD
+            .decode(compact)
+            .map_err(|e| format!("SAMLResponse is not base64: {}", e))?;
+        let xml = String::from_utf8(bytes).map_err(|_| "SAMLResponse is not UTF-8")?;
+        // No legitimate response carries a DTD; refusing it rules out entity expansion.
+        if xml.contains("<!DOCTYPE") || xml.contains("<!ENTITY") {
+            return Err("SAMLResponse contains a DTD".to_string());
+        }
+        let doc = uppsala::parse(&xml).map_err(|e| format!("invalid XML: {}", e))?;
+        let now = Utc::now();
+
+        // 1. Response envelope
+        let response = doc.document_element().ok_or("empty document")?;
+        if !is_element(&doc, response, PROTOCOL_NS, "Response") {
+            return Err("document is not a SAML Response".to_string());
+        }
+        if let Some(destination) = doc.get_attribute(response, "Destination")
+            && destination != self.acs_url
+        {
+            return Err(format!("Destination {} is not this ACS", destination));
+        }
+        let status = child(&doc, response, PROTOCOL_NS, "Status")
+            .and_then(|status| child(&doc, status, PROTOCOL_NS, "StatusCode"))
+            .and_then(|code| doc.get_attribute(code, "Value"));
+        if status != Some(STATUS_SUCCESS) {
+            return Err(format!("IdP returned status {:?}", status));
+        }
+        if child(&doc, response, ASSERTION_NS, "EncryptedAssertion").is_some() {
+            return Err("encrypted assertions are not supported".to_string());
+        }
+        let assertions = doc.child_elements_by_name_ns(response, ASSERTION_NS, "Assertion");
+        let [assertion] = assertions[..] else {
+            return Err(format!(
+                "expected one Assertion, found {}",
+                assertions.len()
+            ));
+        };
+
+        // 2. Signature over the assertion (or the whole response) with the IdP's key
+        self.verify_signature(&doc, &xml, response, assertion)?;
+
+        // 3. Issuer, audience and validity window
+        let issuer = child(&doc, assertion, ASSERTION_NS, "Issuer").map(|i| text(&doc, i));
+        if issuer.as_deref() != Some(self.idp_entity_id.as_str()) {
+            return Err(format!("unexpected assertion issuer {:?}", issuer));
+        }
+        let assertion_id = doc
+            .get_attribute(assertion, "ID")
+            .ok_or("Assertion has no ID")?
+            .to_string();
+        let conditions = child(&doc, assertion, ASSERTION_NS, "Conditions")
+            .ok_or("Assertion has no Conditions")?;
+        if let Some(not_before) = time_attribute(&doc, conditions, "NotBefore")?
+            && now + Duration::seconds(CLOCK_SKEW_SECS) < not_before
+        {
+            return Err("assertion is not yet valid".to_string());
+        }
+        let audiences: Vec<String> = doc
+            .child_elements_by_name_ns(conditions, ASSERTION_NS, "AudienceRestriction")
+            .into_iter()
+            .flat_map(|r| doc.child_elements_by_name_ns(r, ASSERTION_NS, "Audience"))
+            .map(|a| text(&doc, a))
+            .collect();
+        if !audiences.contains(&self.sp_entity_id) {
+            return Err("assertion is not addressed to this SP".to_string());
+        }
+
+        // 4. Subject: bearer confirmation for this ACS, in response to our request
+        let subject =
+            child(&doc, assertion, ASSERTION_NS, "Subject").ok_or("Assertion has no Subject")?;
+        let name_id = child(&doc, subject, ASSERTION_NS, "NameID")
+            .map(|n| text(&doc, n))
+            .filter(|n| !n.is_empty())
+            .ok_or("Subject has no NameID")?;
+        let confirmation = doc
+            .child_elements_by_name_ns(subject, ASSERTION_NS, "SubjectConfirmation")
+            .into_iter()
+            .find(|c| doc.get_attribute(*c, "Method") == Some(BEARER))
+            .and_then(|c| child(&doc, c, ASSERTION_NS, "SubjectConfirmationData"))
+            .ok_or("Subject has no bearer confirmation")?;
+        if doc.get_attribute(confirmation, "Recipient") != Some(self.acs_url.as_str()) {
+            return Err("bearer confirmation is for another recipient".to_string());
+        }
+        let in_response_to = doc
+            .get_attribute(confirmation, "InResponseTo")
+            .ok_or("unsolicited responses are not accepted")?
+            .to_string();
+        let conditions_expiry = time_attribute(&doc, conditions, "NotOnOrAfter")?;
+        let confirmation_expiry = time_attribute(&doc, confirmation, "NotOnOrAfter")?
+            .ok_or("bearer confirmation has no NotOnOrAfter")?;
+        let expires_at =
+            conditions_expiry.map_or(confirmation_expiry, |c| c.min(confirmation_expiry));
+        if now - Duration::seconds(CLOCK_SKEW_SECS) >= expires_at {
+            return Err("assertion has expired".to_string());
+        }
+
+        // 5. Attribute mapping
+        let attributes = attribute_values(&doc, assertion);
+        let attribute = |name: &Option<String>| {
+            name.as_ref()
+                .and_then(|name| attributes.get(name))
+                .and_then(|values| values.first().cloned())
+        };
+        let identity = ExternalIdentity {
+            email: attribute(&Some(self.attributes.email.clone())),
+            // The tenant's IdP is authoritative for its own directory.
+            email_verified: true,
+            preferred_username: attribute(&self.attributes.username).or(Some(name_id.clone())),
+            phone: attribute(&self.attributes.phone),
+            subject: name_id,
+        };
+
+        Ok(ValidatedAssertion {
+            assertion_id,
+            in_response_to,
+            expires_at,
+            identity,
+        })
+    }
+
+    fn verify_signature(
+        &self,
+        doc: &Document<'_>,
+        xml: &str,
+        response: NodeId,
+        assertion: NodeId,
+    ) -> Result<(), String> {
+        let key = load_x509_cert_der(&self.idp_certificate).map_err(|e| e.to_string())?;
+        let mut keys = KeysManager::new();
+        keys.add_key(key);
+        // Secure defaults: only the configured key, and signed references must wrap or sit
+        // next to the signature (XML signature wrapping protection).
+        let ctx = DsigContext::new(keys);
+        let results = verify_all_document_with_source(&ctx, doc, Some(xml))
+            .map_err(|e| format!("signature verification failed: {}", e))?;
+
+        // Only trust data under a node a valid signature actually covers.
+        let covered = results.iter().any(|result| match result {
+            VerifyResult::Valid { references, .. } => references.iter().any(|r| {
+                r.digest_verified
+                    && matches!(r.resolved_node, Some(n) if n == assertion || n == response)
+            }),
+            VerifyResult::Invalid { .. } => false,
+        });
+        if covered {
+            Ok(())
+        } else {
+            let reasons: Vec<&str> = results
+                .iter()
+                .filter_map(|r| match r {
+                    VerifyResult::Invalid { reason } => Some(reason.as_str()),
+                    VerifyResult::Valid { .. } => None,
+                })
+                .collect();
+            Err(format!("assertion is not signed by the IdP: {:?}", reasons))
+        }
+    }
+}
+
+/// A verified assertion. `in_response_to` and `assertion_id` still need to be checked
+/// against Redis for request binding and replay.
+pub struct ValidatedAssertion {
+    pub assertion_id: String,
+    pub in_response_to: String,
+    pub expires_at: DateTime<Utc>,
+    pub identity: ExternalIdentity,
+}
+
+//===============================
+// XML Helpers
+//===============================
+fn is_element(doc: &Document<'_>, node: NodeId, ns: &str, name: &str) -> bool {
+    doc.element(node)
+        .is_some_and(|e| e.matches_name_ns(ns, name))
+}
+
+fn child(doc: &Document<'_>, parent: NodeId, ns: &str, name: &str) -> Option<NodeId> {
+    doc.first_child_element_by_name_ns(parent, ns, name)
+}
+
+fn text(doc: &Document<'_>, node: NodeId) -> String {
+    doc.text_content_deep(node).trim().to_string()
+}
+
+fn time_attribute(
+    doc: &Document<'_>,
+    node: NodeId,
+    name: &str,
+) -> Result<Option<DateTime<Utc>>, String> {
+    doc.get_attribute(node, name)
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|t| t.with_timezone(&Utc))
+                .map_err(|_| format!("invalid {} timestamp {}", name, value))
+        })
+        .transpose()
+}
+
+/// `Attribute/@Name` to its values, from every `AttributeStatement` of the assertion.
+fn attribute_values(doc: &Document<'_>, assertion: NodeId) -> HashMap<String, Vec<String>> {
+    let mut attributes = HashMap::new();
+    for statement in doc.child_elements_by_name_ns(assertion, ASSERTION_NS, "AttributeStatement") {
+        for attribute in doc.child_elements_by_name_ns(statement, ASSERTION_NS, "Attribute") {
+            let Some(name) = doc.get_attribute(attribute, "Name") else {
+                continue;
+            };
+            let values = doc
+                .child_elements_by_name_ns(attribute, ASSERTION_NS, "AttributeValue")
+                .into_iter()
+                .map(|v| text(doc, v))
+                .filter(|v| !v.is_empty())
+                .collect();
+            attributes.insert(name.to_string(), values);
+        }
+    }
+    attributes
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn pem_to_der(pem: &str) -> Option<Vec<u8>> {
+    let body: String = pem
+        .lines()
+        .skip_while(|l| !l.starts_with("-----BEGIN CERTIFICATE-----"))
+        .skip(1)
+        .take_while(|l| !l.starts_with("-----END CERTIFICATE-----"))
+        .collect();
+    STANDARD.decode(body).ok().filter(|der| !der.is_empty())
+}
+
+//===============================
+// Request and Replay State in Redis
+//===============================
+/// An AuthnRequest we issued, keyed by its ID.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SamlRequest {
+    pub tenant: String,
+    /// Hash of the `saml_binding` cookie of the browser that started the sign-in, so a
+    /// response posted from another browser (login CSRF) is refused.
+    pub binding_hash: String,
+}
+
+impl SamlRequest {
+    /// Whether the response came from the browser that started the sign-in.
+    pub fn is_bound_to(&self, binding_secret: &str) -> bool {
+        constant_time_eq(&sha256_hex(binding_secret), &self.binding_hash)
+    }
+}
+
+/// Remember an AuthnRequest so only responses to it are accepted. Returns the request ID.
+pub async fn start_saml_request(
+    redis: &ConnectionManager,
+    tenant: &str,
+    binding_secret: &str,
+) -> redis::RedisResult<String> {
+    // IDs must not start with a digit (xs:ID).
+    let request_id = format!("_{}", random_hex(20));
+    let request = SamlRequest {
+        tenant: tenant.to_string(),
+        binding_hash: sha256_hex(binding_secret),
+    };
+    let payload = serde_json::to_string(&request).expect("SamlRequest serializes");
+    let mut conn = redis.clone();
+    let _: () = conn
+        .set_ex(
+            format!("saml:request:{}", request_id),
+            payload,
+            REQUEST_TTL_SECS,
+        )
+        .await?;
+    Ok(request_id)
+}
+
+/// Consume the request a response claims to answer. `GETDEL` makes each request single use.
+pub async fn take_saml_request(
+    redis: &ConnectionManager,
+    request_id: &str,
+) -> redis::RedisResult<Option<SamlRequest>> {
+    let mut conn = redis.clone();
+    let payload: Option<String> = conn.get_del(format!("saml:request:{}", request_id)).await?;
+    Ok(payload.and_then(|p| serde_json::from_str(&p).ok()))
+}
+
+/// Record an assertion ID until the assertion expires. `false` if it was already used.
+pub async fn mark_assertion_used(
+    redis: &ConnectionManager,
+    tenant: &str,
+    assertion: &ValidatedAssertion,
+) -> redis::RedisResult<bool> {
+    let ttl = (assertion.expires_at - Utc::now()).num_seconds() + CLOCK_SKEW_SECS;
+    let options = SetOptions::default()
+        .conditional_set(redis::ExistenceCheck::NX)
+        .with_expiration(SetExpiry::EX(ttl.max(1) as u64));
+    let mut conn = redis.clone();
+    conn.set_options(
+        format!("saml:assertion:{}:{}", tenant, assertion.assertion_id),
+        1,
+        options,
+    )
+    .await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use bergshamra::keys::loader::load_ec_p256_private_pem;
+    use rcgen::{CertifiedKey, KeyPair, generate_simple_self_signed};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    const IDP_ENTITY_ID: &str = "https://idp.corp.example.com/saml";
+    const SP_ENTITY_ID: &str = "https://api.example.com/api/v1/auth/saml/corp/metadata";
+    const ACS_URL: &str = "https://api.example.com/api/v1/auth/saml/corp/acs";
+    const REQUEST_ID: &str = "_4fd3c2a1";
+
+    pub(crate) fn idp_key() -> CertifiedKey<KeyPair> {
+        generate_simple_self_signed(vec!["idp.corp.example.com".to_string()]).unwrap()
+    }
+
+    fn tenant(idp: &CertifiedKey<KeyPair>) -> SamlTenant {
+        SamlTenant {
+            name: "corp".to_string(),
+            idp_entity_id: IDP_ENTITY_ID.to_string(),
+            idp_sso_url: "https://idp.corp.example.com/sso".to_string(),
+            idp_certificate: idp.cert.der().to_vec(),
+            sp_entity_id: SP_ENTITY_ID.to_string(),
+            acs_url: ACS_URL.to_string(),
+            attributes: AttributeMap {
+                email: "email".to_string(),
+                username: None,
+                phone: None,
+            },
+            provisioning: Provisioning::Create,
+        }
+    }
+
+    /// `SAML_TENANTS=corp`, trusting `idp`.
+    pub(crate) fn saml_config(idp: &CertifiedKey<KeyPair>) -> SamlConfig {
+        SamlConfig {
+            tenants: HashMap::from([("corp".to_string(), tenant(idp))]),
+        }
+    }
+
+    fn timestamp(at: DateTime<Utc>) -> String {
+        at.to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+
+    /// An assertion as an IdP would issue it, with an enveloped signature template (empty
+    /// `DigestValue` and `SignatureValue`) for `sign` to fill in.
+    fn assertion(id: &str, name_id: &str, audience: &str, expires_at: DateTime<Utc>) -> String {
+        let signature = format!(
+            r##"<ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#"><ds:SignedInfo><ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/><ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#ecdsa-sha256"/><ds:Reference URI="#{id}"><ds:Transforms><ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"/><ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/></ds:Transforms><ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/><ds:DigestValue></ds:DigestValue></ds:Reference></ds:SignedInfo><ds:SignatureValue></ds:SignatureValue></ds:Signature>"##
+        );
+        format!(
+            r#"<saml:Assertion ID="{id}" Version="2.0" IssueInstant="{now}"><saml:Issuer>{IDP_ENTITY_ID}</saml:Issuer>{signature}<saml:Subject><saml:NameID>{name_id}</saml:NameID><saml:SubjectConfirmation Method="{BEARER}"><saml:SubjectConfirmationData InResponseTo="{REQUEST_ID}" Recipient="{ACS_URL}" NotOnOrAfter="{expires}"/></saml:SubjectConfirmation></saml:Subject><saml:Conditions NotBefore="{now}" NotOnOrAfter="{expires}"><saml:AudienceRestriction><saml:Audience>{audience}</saml:Audience></saml:AudienceRestriction></saml:Conditions><saml:AttributeStatement><saml:Attribute Name="email"><saml:AttributeValue>{name_id}@corp.example.com</saml:AttributeValue></saml:Attribute></saml:AttributeStatement></saml:Assertion>"#,
+            now = timestamp(Utc::now() - Duration::minutes(1)),
+            expires = timestamp(expires_at),
+        )
+    }
+
+    fn response(assertions: &str) -> String {
+        format!(
+            r#"<samlp:Response xmlns:samlp="{PROTOCOL_NS}" xmlns:saml="{ASSERTION_NS}" ID="_resp" Version="2.0" IssueInstant="{now}" Destination="{ACS_URL}" InResponseTo="{REQUEST_ID}"><saml:Issuer>{IDP_ENTITY_ID}</saml:Issuer><samlp:Status><samlp:StatusCode Value="{STATUS_SUCCESS}"/></samlp:Status>{assertions}</samlp:Response>"#,
+            now = timestamp(Utc::now()),
+        )
+    }
+
+    /// Fill in every signature template of `xml` with `idp`'s key.
+    fn sign(idp: &CertifiedKey<KeyPair>, xml: &str) -> String {
+        let key = load_ec_p256_private_pem(idp.signing_key.serialize_pem().as_bytes()).unwrap();
+        let mut keys = KeysManager::new();
+        keys.add_key(key);
+        bergshamra::sign(&DsigContext::new(keys), xml).unwrap()
+    }
+
+    /// The assertion of a signed response, cut out to be pasted elsewhere.
+    fn signed_assertion(xml: &str) -> &str {
+        let start = xml.find("<saml:Assertion").unwrap();
+        let end = xml.rfind("</saml:Assertion>").unwrap() + "</saml:Assertion>".len();
+        &xml[start..end]
+    }
+
+    fn valid_response(idp: &CertifiedKey<KeyPair>, id: &str) -> String {
+        let expires_at = Utc::now() + Duration::minutes(5);
+        sign(
+            idp,
+            &response(&assertion(id, "jane.doe", SP_ENTITY_ID, expires_at)),
+        )
+    }
+
+    fn validate(tenant: &SamlTenant, xml: &str) -> Result<ValidatedAssertion, String> {
+        tenant.validate_response(&STANDARD.encode(xml))
+    }
+
+    fn strip_signature(xml: &str) -> String {
+        let start = xml.find("<ds:Signature").unwrap();
+        let end = xml.find("</ds:Signature>").unwrap() + "</ds:Signature>".len();
+        format!("{}{}", &xml[..start], &xml[end..])
+    }
+
+    #[test]
+    fn accepts_an_assertion_signed_by_the_idp() {
+        let idp = idp_key();
+        let tenant = tenant(&idp);
+
+        let assertion = validate(&tenant, &valid_response(&idp, "_a1")).unwrap();
+        assert_eq!(assertion.assertion_id, "_a1");
+        assert_eq!(assertion.in_response_to, REQUEST_ID);
+        assert_eq!(assertion.identity.subject, "jane.doe");
+        assert_eq!(
+            assertion.identity.email.as_deref(),
+            Some("jane.doe@corp.example.com")
+        );
+        assert_eq!(
+            assertion.identity.preferred_username.as_deref(),
+            Some("jane.doe")
+        );
+    }
+
+    #[test]
+    fn rejects_an_assertion_for_another_audience() {
+        let idp = idp_key();
+        let expires_at = Utc::now() + Duration::minutes(5);
+        let xml = sign(
+            &idp,
+            &response(&assertion(
+                "_a1",
+                "jane.doe",
+                "https://other-sp.example.com",
+                expires_at,
+            )),
+        );
+
+        let error = validate(&tenant(&idp), &xml).err().unwrap();
+        assert_eq!(error, "assertion is not addressed to this SP");
+    }
+
+    #[test]
+    fn rejects_an_expired_assertion() {
+        let idp = idp_key();
+        let expires_at = Utc::now() - Duration::minutes(5);
+        let xml = sign(
+            &idp,
+            &response(&assertion("_a1", "jane.doe", SP_ENTITY_ID, expires_at)),
+        );
+
+        let error = validate(&tenant(&idp), &xml).err().unwrap();
+        assert_eq!(error, "assertion has expired");
+    }
+
+    #[test]
+    fn rejects_unsigned_and_foreign_signed_assertions() {
+        let idp = idp_key();
+        let tenant = tenant(&idp);
+
+        let unsigned = strip_signature(&valid_response(&idp, "_a1"));
+        let error = validate(&tenant, &unsigned).err().unwrap();
+        assert_eq!(
+            error,
+            "signature verification failed: missing required element: Signature"
+        );
+
+        let foreign = valid_response(&idp_key(), "_a1");
+        let error = validate(&tenant, &foreign).err().unwrap();
+        assert!(
+            error.starts_with("assertion is not signed by the IdP"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn rejects_an_assertion_altered_after_signing() {
+        let idp = idp_key();
+        let xml = valid_response(&idp, "_a1")
+            .replace("jane.doe@corp.example.com", "ceo@corp.example.com");
+
+        let error = validate(&tenant(&idp), &xml).err().unwrap();
+        assert!(
+            error.starts_with("assertion is not signed by the IdP"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn rejects_signature_wrapping() {
+        let idp = idp_key();
+        let tenant = tenant(&idp);
+        let signed = valid_response(&idp, "_a1");
+        let legit = signed_assertion(&signed);
+        let expires_at = Utc::now() + Duration::minutes(5);
+        let forged = strip_signature(&assertion("_evil", "admin", SP_ENTITY_ID, expires_at));
+
+        // The signed assertion hidden inside an unsigned one that carries other claims.
+        let wrapped = forged.replace(
+            "</saml:Assertion>",
+            &format!("<saml:Advice>{}</saml:Advice></saml:Assertion>", legit),
+        );
+        let error = validate(&tenant, &response(&wrapped)).err().unwrap();
+        assert!(
+            error.starts_with("assertion is not signed by the IdP"),
+            "{}",
+            error
+        );
+
+        // Or next to it, hoping the first assertion is the one read.
+        let error = validate(&tenant, &response(&format!("{}{}", forged, legit)))
+            .err()
+            .unwrap();
+        assert_eq!(error, "expected one Assertion, found 2");
+    }
+
+    /// Just enough of Redis for the request and replay state and the send counters: `SET`
+    /// (with `NX`), `SETEX`, `GETDEL`, `INCRBY`, and `OK` to whatever else the client sends.
+    pub(crate) async fn redis_stand_in() -> ConnectionManager {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        let store = Arc::new(Mutex::new(HashMap::<String, String>::new()));
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let store = store.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = socket.into_split();
+                    let mut reader = BufReader::new(reader);
+                    while let Some(command) = read_command(&mut reader).await {
+                        let reply = execute(&store, &command);
+                        writer.write_all(reply.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        ConnectionManager::new(redis::Client::open(url).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn read_command(
+        reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    ) -> Option<Vec<String>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok().filter(|n| *n > 0)?;
+        let count: usize = line.trim().strip_prefix('*')?.parse().ok()?;
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).await.ok()?;
+            let len: usize = line.trim().strip_prefix('$')?.parse().ok()?;
+            let mut arg = vec![0; len + 2];
+            reader.read_exact(&mut arg).await.ok()?;
+            arg.truncate(len);
+            args.push(String::from_utf8(arg).ok()?);
+        }
+        Some(args)
+    }
+
+    fn execute(store: &Mutex<HashMap<String, String>>, command: &[String]) -> String {
+        let mut store = store.lock().unwrap();
+        let bulk = |value: Option<String>| match value {
+            Some(v) => format!("${}\r\n{}\r\n", v.len(), v),
+            None => "$-1\r\n".to_string(),
+        };
+        match command[0].to_ascii_uppercase().as_str() {
+            "SET" => {
+                let nx = command[3..].iter().any(|a| a.eq_ignore_ascii_case("NX"));
+                if nx && store.contains_key(&command[1]) {
+                    return bulk(None);
+                }
+                store.insert(command[1].clone(), command[2].clone());
+                "+OK\r\n".to_string()
+            }
+            "SETEX" => {
+                store.insert(command[1].clone(), command[3].clone());
+                "+OK\r\n".to_string()
+            }
+            "GETDEL" => bulk(store.remove(&command[1])),
+            "INCRBY" => {
+                let value = store.entry(command[1].clone()).or_insert("0".to_string());
+                *value = (value.parse::<i64>().unwrap() + command[2].parse::<i64>().unwrap())
+                    .to_string();
+                format!(":{}\r\n", value)
+            }
+            _ => "+OK\r\n".to_string(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn accepts_each_assertion_once() {
+        let redis = redis_stand_in().await;
+        let idp = idp_key();
+        let tenant = tenant(&idp);
+        let assertion = validate(&tenant, &valid_response(&idp, "_a1")).unwrap();
+
+        assert!(
+            mark_assertion_used(&redis, "corp", &assertion)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !mark_assertion_used(&redis, "corp", &assertion)
+                .await
+                .unwrap()
+        );
+        // Another tenant's IdP may reuse the ID.
+        assert!(
+            mark_assertion_used(&redis, "other", &assertion)
+                .await
+                .unwrap()
+        );
+    }
+
+    /// Remember `REQUEST_ID`, the request every test response answers, as issued for the
+    /// `corp` tenant to the browser holding `binding_secret`.
+    pub(crate) async fn outstanding_request(redis: &ConnectionManager, binding_secret: &str) {
+        let request = SamlRequest {
+            tenant: "corp".to_string(),
+            binding_hash: sha256_hex(binding_secret),
+        };
+        let mut conn = redis.clone();
+        let _: () = conn
+            .set_ex(
+                format!("saml:request:{}", REQUEST_ID),
+                serde_json::to_string(&request).unwrap(),
+                REQUEST_TTL_SECS,
+            )
+            .await
+            .unwrap();
+    }
+
+    /// A signed response answering `REQUEST_ID`, encoded as the IdP posts it.
+    pub(crate) fn encoded_response(idp: &CertifiedKey<KeyPair>, id: &str) -> String {
+        STANDARD.encode(valid_response(idp, id))
+    }
+
+    #[actix_web::test]
+    async fn binds_requests_to_the_tenant_and_browser_that_started_them() {
+        let redis = redis_stand_in().await;
+        let secret = random_hex(32);
+
+        let request_id = start_saml_request(&redis, "corp", &secret).await.unwrap();
+        let request = take_saml_request(&redis, &request_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.tenant, "corp");
+        assert!(request.is_bound_to(&secret));
+        assert!(!request.is_bound_to(&random_hex(32)));
+
+        // Each request is answered once, and unknown IDs are refused.
+        assert!(
+            take_saml_request(&redis, &request_id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            take_saml_request(&redis, REQUEST_ID)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn binding_cookie_survives_the_cross_site_post_from_the_idp() {
+        let cookie = tenant(&idp_key()).binding_cookie("secret");
+        assert_eq!(cookie.name(), BINDING_COOKIE);
+        assert_eq!(cookie.path(), Some("/api/v1/auth/saml/"));
+        assert_eq!(cookie.same_site(), Some(SameSite::None));
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.http_only(), Some(true));
+    }
+}
@@ -0,0 +1,228 @@
+// src/utils/security_headers.rs
+use actix_web::{
+    Error, HttpResponse,
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderName, HeaderValue},
+    middleware::Next,
+    web,
+};
+use serde_json::json;
+use std::env;
+use tracing::warn;
+
+//===============================
+// Security Configuration
+//===============================
+#[derive(Clone, Debug)]
+pub struct SecurityConfig {
+    /// Accepted `Host` values. `*` accepts any host, a leading `.` also matches subdomains.
+    /// An empty list disables host validation.
+    pub allowed_hosts: Vec<String>,
+    /// `max-age` for `Strict-Transport-Security`; `0` omits the header.
+    pub hsts_max_age: u64,
+    pub hsts_include_subdomains: bool,
+    pub referrer_policy: String,
+    /// Sent only on `text/html` responses.
+    pub content_security_policy: String,
+}
+
+impl SecurityConfig {
+    pub fn from_env() -> Self {
+        let allowed_hosts: Vec<String> = env::var("ALLOWED_HOSTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|host| host.trim().to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        if allowed_hosts.is_empty() {
+            warn!("ALLOWED_HOSTS is empty, Host header validation is disabled");
+        }
+
+        Self {
+            allowed_hosts,
+            hsts_max_age: env::var("HSTS_MAX_AGE")
+                .map(|v| v.parse().expect("HSTS_MAX_AGE must be a number of seconds"))
+                .unwrap_or(31_536_000),
+            hsts_include_subdomains: env::var("HSTS_INCLUDE_SUBDOMAINS")
+                .map(|v| v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            referrer_policy: env::var("REFERRER_POLICY")
+                .unwrap_or_else(|_| "no-referrer".to_string()),
+            content_security_policy: env::var("CONTENT_SECURITY_POLICY")
+                .unwrap_or_else(|_| "default-src 'none'; frame-ancestors 'none'".to_string()),
+        }
+    }
+
+    fn is_allowed_host(&self, host: &str) -> bool {
+        if self.allowed_hosts.is_empty() {
+            return true;
+        }
+        let host = host.to_ascii_lowercase();
+        self.allowed_hosts.iter().any(|allowed| {
+            if allowed == "*" {
+                true
+            } else if let Some(domain) = allowed.strip_prefix('.') {
+                host == domain || host.ends_with(allowed.as_str())
+            } else {
+                host == *allowed
+            }
+        })
+    }
+}
+
+/// Strip the port from a `Host` value, keeping IPv6 literals intact.
+fn host_without_port(host: &str) -> &str {
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    host.split(':').next().unwrap_or(host)
+}
+
+//===============================
+// Middleware
+//===============================
+/// Reject requests whose `Host` is not listed in `ALLOWED_HOSTS`.
+pub async fn validate_host(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let config = req.app_data::<web::Data<SecurityConfig>>().cloned();
+
+    if let Some(config) = config {
+        // HTTP/2 carries the authority in the URI instead of a Host header.
+        let host = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+            .or_else(|| req.uri().authority().map(|a| a.to_string()));
+
+        let allowed = match host.as_deref() {
+            Some(host) => config.is_allowed_host(host_without_port(host)),
+            None => config.allowed_hosts.is_empty(),
+        };
+
+        if !allowed {
+            warn!("Rejected request with disallowed Host header: {:?}", host);
+            let response = HttpResponse::BadRequest()
+                .json(json!({"code":400,"message":"Invalid Host header"}));
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Add transport and content security headers to every response.
+pub async fn security_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let config = req.app_data::<web::Data<SecurityConfig>>().cloned();
+    let mut res = next.call(req).await?;
+
+    let Some(config) = config else {
+        return Ok(res);
+    };
+
+    let is_html = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+
+    let headers = res.headers_mut();
+    set_default(
+        headers,
+        header::X_CONTENT_TYPE_OPTIONS,
+        "nosniff".to_string(),
+    );
+    set_default(
+        headers,
+        header::REFERRER_POLICY,
+        config.referrer_policy.clone(),
+    );
+    if config.hsts_max_age > 0 {
+        let mut hsts = format!("max-age={}", config.hsts_max_age);
+        if config.hsts_include_subdomains {
+            hsts.push_str("; includeSubDomains");
+        }
+        set_default(headers, header::STRICT_TRANSPORT_SECURITY, hsts);
+    }
+    if is_html {
+        set_default(
+            headers,
+            header::CONTENT_SECURITY_POLICY,
+            config.content_security_policy.clone(),
+        );
+    }
+
+    Ok(res)
+}
+
+// Handlers may set their own value; only fill the header in when it is missing.
+fn set_default(headers: &mut header::HeaderMap, name: HeaderName, value: String) {
+    if headers.contains_key(&name) {
+        return;
+    }
+    match HeaderValue::from_str(&value) {
+        Ok(value) => {
+            headers.insert(name, value);
+        }
+        Err(_) => warn!("Invalid value configured for header {}", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{App, http::StatusCode, test};
+
+    fn config() -> SecurityConfig {
+        SecurityConfig {
+            allowed_hosts: vec!["api.example.com".to_string()],
+            hsts_max_age: 31_536_000,
+            hsts_include_subdomains: false,
+            referrer_policy: "no-referrer".to_string(),
+            content_security_policy: "default-src 'none'".to_string(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn rejects_unknown_hosts_with_the_security_headers() {
+        // Wrapped in the order main.rs uses.
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config()))
+                .wrap(from_fn(validate_host))
+                .wrap(from_fn(security_headers))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for (host, status) in [
+            ("api.example.com", StatusCode::OK),
+            ("evil.example.net", StatusCode::BAD_REQUEST),
+        ] {
+            let request = test::TestRequest::get()
+                .uri("/")
+                .insert_header((header::HOST, host))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), status);
+            let headers = response.headers();
+            assert_eq!(
+                headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+                "nosniff"
+            );
+            assert_eq!(
+                headers.get(header::STRICT_TRANSPORT_SECURITY).unwrap(),
+                "max-age=31536000"
+            );
+        }
+    }
+}
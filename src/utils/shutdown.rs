@@ -0,0 +1,171 @@
+// src/utils/shutdown.rs
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+//===============================
+// Shutdown Configuration
+//===============================
+#[derive(Clone, Debug)]
+pub struct ShutdownConfig {
+    /// Seconds the server waits for in-flight requests after it stops accepting connections.
+    pub request_timeout: u64,
+    /// Seconds readiness reports failing before the server stops accepting connections,
+    /// giving load balancers time to take the instance out of rotation.
+    pub drain_delay: u64,
+    /// Seconds background workers get to flush before the process exits.
+    pub worker_timeout: u64,
+}
+
+impl ShutdownConfig {
+    pub fn from_env() -> Self {
+        Self {
+            request_timeout: env_secs("SHUTDOWN_TIMEOUT", 30),
+            drain_delay: env_secs("SHUTDOWN_DRAIN_DELAY", 5),
+            worker_timeout: env_secs("SHUTDOWN_WORKER_TIMEOUT", 10),
+        }
+    }
+}
+
+fn env_secs(key: &str, default: u64) -> u64 {
+    match std::env::var(key) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a number of seconds", key)),
+        Err(_) => default,
+    }
+}
+
+//===============================
+// Shutdown State
+//===============================
+/// Shared flag flipped as soon as a termination signal arrives.
+/// Readiness checks and background workers observe it.
+pub struct ShutdownState {
+    draining: AtomicBool,
+    notify: watch::Sender<bool>,
+}
+
+impl ShutdownState {
+    pub fn new() -> Self {
+        let (notify, _) = watch::channel(false);
+        Self {
+            draining: AtomicBool::new(false),
+            notify,
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn begin_drain(&self) {
+        if !self.draining.swap(true, Ordering::SeqCst) {
+            self.notify.send_replace(true);
+        }
+    }
+
+    pub fn subscribe(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            rx: self.notify.subscribe(),
+        }
+    }
+}
+
+/// Handed to background workers so they can stop their loop and flush.
+#[derive(Clone)]
+pub struct ShutdownSignal {
+    rx: watch::Receiver<bool>,
+}
+
+impl ShutdownSignal {
+    /// Resolves once shutdown has begun.
+    pub async fn triggered(&mut self) {
+        // An error means the sender is gone, which only happens while exiting anyway.
+        let _ = self.rx.wait_for(|draining| *draining).await;
+    }
+}
+
+//===============================
+// Signal Handling
+//===============================
+/// Waits for SIGTERM or Ctrl-C, flips readiness to failing, then waits out the drain delay.
+/// Passed to `HttpServer::shutdown_signal`, which stops accepting connections once it resolves.
+pub async fn wait_for_signal(state: Arc<ShutdownState>, drain_delay: u64) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut sigterm =
+            signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            _ = sigterm.recv() => info!("SIGTERM received"),
+            _ = tokio::signal::ctrl_c() => info!("SIGINT received"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Ctrl-C received");
+    }
+
+    state.begin_drain();
+    info!(
+        "Readiness set to failing, draining for {}s before stopping listeners",
+        drain_delay
+    );
+    tokio::time::sleep(Duration::from_secs(drain_delay)).await;
+}
+
+//===============================
+// Background Workers
+//===============================
+/// Registry of long-running tasks that must finish before the process exits.
+pub struct BackgroundWorkers {
+    state: Arc<ShutdownState>,
+    handles: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl BackgroundWorkers {
+    pub fn new(state: Arc<ShutdownState>) -> Self {
+        Self {
+            state,
+            handles: Vec::new(),
+        }
+    }
+
+    /// Spawn a worker. It receives a `ShutdownSignal` and should return once it has flushed.
+    pub fn spawn<F, Fut>(&mut self, name: &'static str, worker: F)
+    where
+        F: FnOnce(ShutdownSignal) -> Fut,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let signal = self.state.subscribe();
+        let handle = actix_web::rt::spawn(worker(signal));
+        info!("Background worker {} started", name);
+        self.handles.push((name, handle));
+    }
+
+    /// Signal every worker and wait for each to finish, up to `timeout` seconds in total.
+    pub async fn shutdown(self, timeout: u64) {
+        self.state.begin_drain();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(timeout);
+
+        for (name, mut handle) in self.handles {
+            match tokio::time::timeout_at(deadline, &mut handle).await {
+                Ok(Ok(())) => info!("Background worker {} finished", name),
+                Ok(Err(e)) => error!("Background worker {} failed: {}", name, e),
+                Err(_) => {
+                    warn!(
+                        "Background worker {} did not finish in time, aborting",
+                        name
+                    );
+                    handle.abort();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// src/utils/sms.rs
+use std::env;
+use std::path::PathBuf;
+use tracing::info;
+
+//===============================
+// SMS Configuration
+//===============================
+/// Limits for the codes texted to verify a phone number and to sign in with it.
+#[derive(Clone, Debug)]
+pub struct SmsConfig {
+    pub otp_ttl: u64,
+    /// Wrong codes accepted before the pending code is discarded.
+    pub otp_max_attempts: u64,
+    /// Minimum seconds between two texts of the same kind to a user.
+    pub resend_cooldown: u64,
+    /// Texts a user can be sent per 24 hours, across all kinds.
+    pub daily_limit: u64,
+}
+
+impl SmsConfig {
+    pub fn from_env() -> Self {
+        let number = |key: &str, default: u64| {
+            env::var(key)
+                .map(|v| {
+                    v.parse()
+                        .unwrap_or_else(|_| panic!("{} must be a number", key))
+                })
+                .unwrap_or(default)
+        };
+        Self {
+            otp_ttl: number("SMS_OTP_TTL", 300),
+            otp_max_attempts: number("SMS_OTP_MAX_ATTEMPTS", 5),
+            resend_cooldown: number("SMS_RESEND_COOLDOWN", 60),
+            daily_limit: number("SMS_DAILY_LIMIT", 10),
+        }
+    }
+}
+
+//===============================
+// Outgoing Text Messages
+//===============================
+pub struct Sms {
+    pub to: String,
+    pub body: String,
+}
+
+/// Delivers text messages. Selected with `SMS_PROVIDER`; `log` is the only built-in provider,
+/// gateways plug in by implementing this trait.
+#[async_trait::async_trait]
+pub trait SmsProvider: Send + Sync {
+    async fn send(&self, sms: &Sms) -> Result<(), String>;
+}
+
+pub struct SmsSender {
+    provider: Box<dyn SmsProvider>,
+}
+
+impl SmsSender {
+    pub fn from_env() -> Self {
+        let provider: Box<dyn SmsProvider> = match env::var("SMS_PROVIDER").as_deref() {
+            Ok("log") | Err(_) => Box::new(LogProvider {
+                dir: env::var("SMS_FILE_DIR").ok().map(PathBuf::from),
+            }),
+            Ok(other) => panic!("SMS_PROVIDER must be log, got {}", other),
+        };
+        Self { provider }
+    }
+
+    pub async fn send(&self, sms: Sms) -> Result<(), String> {
+        self.provider.send(&sms).await
+    }
+}
+
+/// Development stand-in: logs the recipient and, with `SMS_FILE_DIR`, writes each message to a
+/// file there. Messages hold one-time codes, so their text is never logged.
+struct LogProvider {
+    dir: Option<PathBuf>,
+}
+
+#[async_trait::async_trait]
+impl SmsProvider for LogProvider {
+    async fn send(&self, sms: &Sms) -> Result<(), String> {
+        info!("Text message to {}", sms.to);
+        if let Some(dir) = &self.dir {
+            let path = dir.join(format!(
+                "{}-{}.txt",
+                chrono::Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+                sms.to
+            ));
+            tokio::fs::write(&path, format!("To: {}\n\n{}\n", sms.to, sms.body))
+                .await
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
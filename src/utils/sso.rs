@@ -0,0 +1,818 @@
+// src/utils/sso.rs
+use crate::services::identity_service::ExternalIdentity;
+use crate::utils::crypto::{constant_time_eq, random_hex, sha256_base64url, sha256_hex};
+use actix_web::HttpRequest;
+use actix_web::cookie::time::Duration as CookieDuration;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+use tokio::sync::{OnceCell, RwLock};
+use url::Url;
+
+const STATE_TTL_SECS: u64 = 600;
+/// Cookie tying a sign-in attempt to the browser that started it.
+pub const BINDING_COOKIE: &str = "sso_binding";
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Minimum time between JWKS fetches, however many unknown `kid`s come in.
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+//===============================
+// Provider Configuration
+//===============================
+/// What to do when someone signs in with an external account that is not linked yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provisioning {
+    /// Only accounts linked by a signed-in user may sign in.
+    Disabled,
+    /// Create a local user (without a password) on first sign-in.
+    Create,
+}
+
+impl Provisioning {
+    /// `disabled` (the default) or `create`.
+    pub fn from_env(var: &str) -> Self {
+        match env::var(var).as_deref() {
+            Err(_) | Ok("disabled") => Self::Disabled,
+            Ok("create") => Self::Create,
+            Ok(other) => panic!("{} must be disabled or create, got {}", var, other),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Endpoints {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+    jwks_uri: Option<String>,
+}
+
+/// An external OpenID Connect provider (or plain OAuth 2.0 provider with a userinfo endpoint,
+/// such as GitHub) users can sign in with.
+pub struct SsoProvider {
+    pub name: String,
+    client_id: String,
+    client_secret: Option<String>,
+    scopes: String,
+    /// Set for OpenID Connect providers: endpoints come from discovery and ID tokens are verified.
+    issuer: Option<String>,
+    endpoints: OnceCell<Endpoints>,
+    jwks: RwLock<Option<CachedJwks>>,
+    pub provisioning: Provisioning,
+    /// Email domains allowed to be provisioned. Empty allows any domain.
+    allowed_domains: Vec<String>,
+}
+
+/// The provider's key set and when it was fetched.
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Providers from `SSO_PROVIDERS`, each configured by `SSO_<NAME>_*` variables.
+pub struct SsoConfig {
+    providers: HashMap<String, SsoProvider>,
+    /// Public base URL of this API that callback URLs are built from. The request's Host
+    /// header is chosen by the client, so it is never used instead.
+    redirect_base_url: String,
+}
+
+impl SsoConfig {
+    pub fn from_env() -> Self {
+        let providers: HashMap<String, SsoProvider> = env::var("SSO_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| (name.clone(), SsoProvider::from_env(name)))
+            .collect();
+        let redirect_base_url = match env::var("SSO_REDIRECT_BASE_URL") {
+            Ok(url) => {
+                Url::parse(&url).expect("SSO_REDIRECT_BASE_URL must be an absolute URL");
+                url.trim_end_matches('/').to_string()
+            }
+            Err(_) if providers.is_empty() => String::new(),
+            Err(_) => panic!("SSO_REDIRECT_BASE_URL must be set with SSO_PROVIDERS"),
+        };
+        Self {
+            providers,
+            redirect_base_url,
+        }
+    }
+
+    /// Cookie carrying the browser binding secret. It is only sent back to the SSO routes,
+    /// and `SameSite=Lax` still lets the provider's top-level redirect include it.
+    pub fn binding_cookie(&self, secret: &str) -> Cookie<'static> {
+        Cookie::build(BINDING_COOKIE, secret.to_string())
+            .path("/api/v1/auth/sso/")
+            .http_only(true)
+            .secure(self.redirect_base_url.starts_with("https://"))
+            .same_site(SameSite::Lax)
+            .max_age(CookieDuration::seconds(STATE_TTL_SECS as i64))
+            .finish()
+    }
+
+    /// Where `provider` sends the browser back to; must be registered with the provider.
+    pub fn callback_url(&self, provider: &str) -> String {
+        format!(
+            "{}/api/v1/auth/sso/{}/callback",
+            self.redirect_base_url, provider
+        )
+    }
+
+    pub fn provider(&self, name: &str) -> Option<&SsoProvider> {
+        self.providers.get(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+}
+
+impl SsoProvider {
+    fn from_env(name: String) -> Self {
+        let var = |key: &str| env::var(format!("SSO_{}_{}", name.to_ascii_uppercase(), key)).ok();
+        let required = |key: &str| {
+            var(key)
+                .unwrap_or_else(|| panic!("SSO_{}_{} must be set", name.to_ascii_uppercase(), key))
+        };
+
+        let issuer = var("ISSUER").map(|url| url.trim_end_matches('/').to_string());
+        // Without an issuer, the endpoints are configured by hand and there is no ID token.
+        let endpoints = OnceCell::new();
+        if issuer.is_none() {
+            endpoints
+                .set(Endpoints {
+                    authorization_endpoint: required("AUTHORIZATION_URL"),
+                    token_endpoint: required("TOKEN_URL"),
+                    userinfo_endpoint: Some(required("USERINFO_URL")),
+                    jwks_uri: None,
+                })
+                .expect("fresh cell");
+        }
+        let default_scopes = if issuer.is_some() {
+            "openid email profile"
+        } else {
+            ""
+        };
+        let provisioning =
+            Provisioning::from_env(&format!("SSO_{}_PROVISIONING", name.to_ascii_uppercase()));
+
+        Self {
+            client_id: required("CLIENT_ID"),
+            client_secret: var("CLIENT_SECRET"),
+            scopes: var("SCOPES").unwrap_or_else(|| default_scopes.to_string()),
+            issuer,
+            endpoints,
+            jwks: RwLock::new(None),
+            provisioning,
+            allowed_domains: var("ALLOWED_DOMAINS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|d| d.trim().to_ascii_lowercase())
+                .filter(|d| !d.is_empty())
+                .collect(),
+            name,
+        }
+    }
+
+    /// Whether an account with this identity may be created on first sign-in. A domain
+    /// restriction only trusts emails the provider has verified.
+    pub fn may_provision(&self, identity: &ExternalIdentity) -> bool {
+        if self.provisioning != Provisioning::Create {
+            return false;
+        }
+        if self.allowed_domains.is_empty() {
+            return true;
+        }
+        match (&identity.email, identity.email_verified) {
+            (Some(email), true) => email
+                .rsplit_once('@')
+                .is_some_and(|(_, domain)| self.allowed_domains.contains(&domain.to_lowercase())),
+            _ => false,
+        }
+    }
+
+    /// OpenID Connect Discovery, fetched once per process.
+    async fn endpoints(&self) -> Result<&Endpoints, String> {
+        self.endpoints
+            .get_or_try_init(|| async {
+                let issuer = self.issuer.as_deref().unwrap_or_default();
+                let url = format!("{}/.well-known/openid-configuration", issuer);
+                get_json::<Endpoints>(&url, None).await
+            })
+            .await
+    }
+
+    /// Provider URL to send the browser to. `state`, `nonce` and the PKCE challenge bind the
+    /// eventual callback to this request.
+    pub async fn authorization_url(
+        &self,
+        redirect_uri: &str,
+        state: &SsoState,
+        state_token: &str,
+    ) -> Result<String, String> {
+        let endpoints = self.endpoints().await?;
+        let mut url = Url::parse(&endpoints.authorization_endpoint)
+            .map_err(|e| format!("invalid authorization endpoint: {}", e))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("scope", &self.scopes)
+            .append_pair("state", state_token)
+            .append_pair("nonce", &state.nonce)
+            .append_pair("code_challenge", &sha256_base64url(&state.code_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.to_string())
+    }
+
+    /// Redeem the authorization code and return who signed in.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+        state: &SsoState,
+    ) -> Result<ExternalIdentity, String> {
+        let endpoints = self.endpoints().await?;
+
+        // 1. Token request, authenticated with client_secret_post
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", &self.client_id),
+            ("code_verifier", &state.code_verifier),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let mut response = http_client()
+            .post(&endpoints.token_endpoint)
+            .insert_header((header::ACCEPT, "application/json"))
+            .send_form(&form)
+            .await
+            .map_err(|e| format!("token request failed: {}", e))?;
+        if !response.status().is_success() {
+            let body = response.body().await.unwrap_or_default();
+            return Err(format!(
+                "token endpoint returned {}: {}",
+                response.status(),
+                String::from_utf8_lossy(&body)
+            ));
+        }
+        let tokens: TokenResponse = response
+            .json()
+            .await
+            .map_err(|e| format!("invalid token response: {}", e))?;
+
+        // 2. Claims from the verified ID token, or from userinfo for plain OAuth 2.0
+        let claims = match (&self.issuer, tokens.id_token) {
+            (Some(issuer), Some(id_token)) => {
+                self.verify_id_token(issuer, &id_token, &state.nonce)
+                    .await?
+            }
+            (Some(_), None) => return Err("token response carries no id_token".to_string()),
+            (None, _) => {
+                let url = endpoints.userinfo_endpoint.as_deref().unwrap_or_default();
+                get_json::<Map<String, Value>>(url, Some(&tokens.access_token)).await?
+            }
+        };
+        identity_from_claims(&claims)
+    }
+
+    async fn verify_id_token(
+        &self,
+        issuer: &str,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<Map<String, Value>, String> {
+        let header = decode_header(id_token).map_err(|e| format!("invalid id_token: {}", e))?;
+        // Symmetric algorithms would let anyone holding the client secret forge tokens.
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(format!(
+                "id_token algorithm {:?} is not allowed",
+                header.alg
+            ));
+        }
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = 60;
+        validation.set_issuer(&[issuer]);
+        validation.set_audience(&[&self.client_id]);
+        let claims = decode::<Map<String, Value>>(id_token, &key, &validation)
+            .map_err(|e| format!("id_token rejected: {}", e))?
+            .claims;
+
+        if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+            return Err("id_token nonce does not match".to_string());
+        }
+        Ok(claims)
+    }
+
+    /// Key for `kid` from the provider's JWKS. An unknown `kid` refetches the set, so provider
+    /// key rotation is picked up without a restart, but at most once per
+    /// `JWKS_REFRESH_INTERVAL`: tokens with made-up `kid`s cannot make us hammer the provider.
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey, String> {
+        let find = |jwks: &JwkSet| match kid {
+            Some(kid) => jwks.find(kid).cloned(),
+            None => jwks.keys.first().cloned(),
+        };
+        if let Some(jwk) = self.jwks.read().await.as_ref().and_then(|c| find(&c.keys)) {
+            return DecodingKey::from_jwk(&jwk).map_err(|e| format!("invalid JWK: {}", e));
+        }
+
+        // Concurrent misses queue on the write lock; only the first one fetches.
+        let mut cache = self.jwks.write().await;
+        if let Some(cached) = cache.as_ref() {
+            if let Some(jwk) = find(&cached.keys) {
+                return DecodingKey::from_jwk(&jwk).map_err(|e| format!("invalid JWK: {}", e));
+            }
+            if cached.fetched_at.elapsed() < JWKS_REFRESH_INTERVAL {
+                return Err("no JWK matches the id_token kid".to_string());
+            }
+        }
+
+        let endpoints = self.endpoints().await?;
+        let jwks_uri = endpoints
+            .jwks_uri
+            .as_deref()
+            .ok_or("provider publishes no jwks_uri")?;
+        let keys = get_json::<JwkSet>(jwks_uri, None).await?;
+        let jwk = find(&keys);
+        *cache = Some(CachedJwks {
+            keys,
+            fetched_at: Instant::now(),
+        });
+        let jwk = jwk.ok_or("no JWK matches the id_token kid")?;
+        DecodingKey::from_jwk(&jwk).map_err(|e| format!("invalid JWK: {}", e))
+    }
+}
+
+//===============================
+// Provider Responses
+//===============================
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+/// Standard claims, with the fallbacks plain OAuth 2.0 providers use (GitHub's numeric `id`
+/// and `login`).
+fn identity_from_claims(claims: &Map<String, Value>) -> Result<ExternalIdentity, String> {
+    let subject = match claims.get("sub").or_else(|| claims.get("id")) {
+        Some(Value::String(sub)) if !sub.is_empty() => sub.clone(),
+        Some(Value::Number(id)) => id.to_string(),
+        _ => return Err("provider returned no subject".to_string()),
+    };
+    let string = |key: &str| {
+        claims
+            .get(key)
+            .and_then(Value::as_str)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    // Some providers send `email_verified` as a string.
+    let email_verified = match claims.get("email_verified") {
+        Some(Value::Bool(verified)) => *verified,
+        Some(Value::String(verified)) => verified == "true",
+        _ => false,
+    };
+    Ok(ExternalIdentity {
+        subject,
+        email: string("email"),
+        email_verified,
+        preferred_username: string("preferred_username").or_else(|| string("login")),
+        phone: string("phone_number"),
+    })
+}
+
+fn http_client() -> awc::Client {
+    awc::Client::builder().timeout(HTTP_TIMEOUT).finish()
+}
+
+async fn get_json<T: DeserializeOwned>(url: &str, bearer: Option<&str>) -> Result<T, String> {
+    let mut request = http_client()
+        .get(url)
+        .insert_header((header::ACCEPT, "application/json"));
+    if let Some(token) = bearer {
+        request = request.bearer_auth(token);
+    }
+    let mut response = request
+        .send()
+        .await
+        .map_err(|e| format!("GET {} failed: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(format!("GET {} returned {}", url, response.status()));
+    }
+    response
+        .json::<T>()
+        .limit(1024 * 1024)
+        .await
+        .map_err(|e| format!("GET {} returned invalid JSON: {}", url, e))
+}
+
+//===============================
+// Login State in Redis
+//===============================
+/// Secret of the browser's binding `cookie`, reusing a valid one so sign-ins started in
+/// several tabs all stay bound.
+pub fn binding_secret(req: &HttpRequest, cookie: &str) -> String {
+    req.cookie(cookie)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|secret| secret.len() == 64 && secret.bytes().all(|b| b.is_ascii_hexdigit()))
+        .unwrap_or_else(|| random_hex(32))
+}
+
+/// Everything bound to one sign-in attempt, keyed by the `state` parameter.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SsoState {
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    /// Set when a signed-in user is linking the identity to their account.
+    pub link_user_id: Option<i32>,
+    /// Hash of the `sso_binding` cookie of the browser that started the attempt, so a
+    /// callback URL lured into another browser (login CSRF) is refused.
+    pub binding_hash: String,
+}
+
+impl SsoState {
+    pub fn new(provider: &str, link_user_id: Option<i32>, binding_secret: &str) -> Self {
+        Self {
+            provider: provider.to_string(),
+            nonce: random_hex(16),
+            code_verifier: random_hex(32),
+            link_user_id,
+            binding_hash: sha256_hex(binding_secret),
+        }
+    }
+
+    /// Whether the callback came from the browser that started the attempt.
+    pub fn is_bound_to(&self, binding_secret: Option<&str>) -> bool {
+        binding_secret
+            .is_some_and(|secret| constant_time_eq(&sha256_hex(secret), &self.binding_hash))
+    }
+}
+
+/// Store `state` under a fresh random token for `STATE_TTL_SECS`.
+pub async fn store_sso_state(
+    redis: &ConnectionManager,
+    state: &SsoState,
+) -> redis::RedisResult<String> {
+    let token = random_hex(32);
+    let payload = serde_json::to_string(state).expect("SsoState serializes");
+    let mut conn = redis.clone();
+    let _: () = conn
+        .set_ex(format!("sso:state:{}", token), payload, STATE_TTL_SECS)
+        .await?;
+    Ok(token)
+}
+
+/// Consume a state token. `GETDEL` makes each callback single use.
+pub async fn take_sso_state(
+    redis: &ConnectionManager,
+    token: &str,
+) -> redis::RedisResult<Option<SsoState>> {
+    let mut conn = redis.clone();
+    let payload: Option<String> = conn.get_del(format!("sso:state:{}", token)).await?;
+    Ok(payload.and_then(|p| serde_json::from_str(&p).ok()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use actix_web::{App, HttpResponse, HttpServer, web};
+    use jsonwebtoken::jwk::Jwk;
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use serde_json::json;
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const CLIENT_ID: &str = "api-client";
+    const CLIENT_SECRET: &str = "client-secret";
+    const REDIRECT_URI: &str = "https://api.example.com/api/v1/auth/sso/mock/callback";
+    const SIGNING_KEY: &[u8] = include_bytes!("../../test/fixtures/oidc-signing-key.pem");
+
+    /// A provider for `issuer`, configured the way `SSO_<NAME>_*` would for an OpenID Connect
+    /// provider with provisioning enabled.
+    pub(crate) fn test_provider(issuer: &str) -> SsoProvider {
+        SsoProvider {
+            name: "mock".to_string(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: Some(CLIENT_SECRET.to_string()),
+            scopes: "openid email profile".to_string(),
+            issuer: Some(issuer.to_string()),
+            endpoints: OnceCell::new(),
+            jwks: RwLock::new(None),
+            provisioning: Provisioning::Create,
+            allowed_domains: Vec::new(),
+        }
+    }
+
+    /// In-process OpenID provider serving discovery, its JWKS and a token endpoint that
+    /// returns whatever ID token the test queued.
+    struct MockIdp {
+        issuer: String,
+        /// `kid` the JWKS publishes the signing key under.
+        kid: Mutex<String>,
+        id_token: Mutex<Option<String>>,
+        token_requests: Mutex<Vec<HashMap<String, String>>>,
+        jwks_fetches: AtomicUsize,
+    }
+
+    impl MockIdp {
+        async fn start() -> web::Data<MockIdp> {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let idp = web::Data::new(MockIdp {
+                issuer: format!("http://{}", listener.local_addr().unwrap()),
+                kid: Mutex::new("key-1".to_string()),
+                id_token: Mutex::new(None),
+                token_requests: Mutex::new(Vec::new()),
+                jwks_fetches: AtomicUsize::new(0),
+            });
+            let data = idp.clone();
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(data.clone())
+                    .route(
+                        "/.well-known/openid-configuration",
+                        web::get().to(discovery),
+                    )
+                    .route("/jwks", web::get().to(jwks))
+                    .route("/token", web::post().to(token))
+            })
+            .workers(1)
+            .listen(listener)
+            .unwrap()
+            .run();
+            actix_web::rt::spawn(server);
+            idp
+        }
+
+        /// Sign `claims` with the provider key and hand them out at the next code redemption.
+        fn issue(&self, kid: &str, claims: Value) {
+            let mut header = Header::new(Algorithm::RS256);
+            header.kid = Some(kid.to_string());
+            let key = EncodingKey::from_rsa_pem(SIGNING_KEY).unwrap();
+            self.issue_raw(encode(&header, &claims, &key).unwrap());
+        }
+
+        fn issue_raw(&self, id_token: String) {
+            *self.id_token.lock().unwrap() = Some(id_token);
+        }
+
+        /// Claims a conforming provider would send for the attempt in `state`.
+        fn claims(&self, state: &SsoState) -> Value {
+            let now = chrono::Utc::now().timestamp();
+            json!({
+                "iss": self.issuer,
+                "aud": CLIENT_ID,
+                "sub": "248289761001",
+                "iat": now,
+                "exp": now + 300,
+                "nonce": state.nonce,
+                "email": "jane@example.com",
+                "email_verified": true,
+                "preferred_username": "jane",
+            })
+        }
+    }
+
+    async fn discovery(idp: web::Data<MockIdp>) -> HttpResponse {
+        HttpResponse::Ok().json(json!({
+            "issuer": idp.issuer,
+            "authorization_endpoint": format!("{}/authorize", idp.issuer),
+            "token_endpoint": format!("{}/token", idp.issuer),
+            "jwks_uri": format!("{}/jwks", idp.issuer),
+        }))
+    }
+
+    async fn jwks(idp: web::Data<MockIdp>) -> HttpResponse {
+        idp.jwks_fetches.fetch_add(1, Ordering::SeqCst);
+        let key = EncodingKey::from_rsa_pem(SIGNING_KEY).unwrap();
+        let mut jwk = Jwk::from_encoding_key(&key, Algorithm::RS256).unwrap();
+        jwk.common.key_id = Some(idp.kid.lock().unwrap().clone());
+        HttpResponse::Ok().json(JwkSet { keys: vec![jwk] })
+    }
+
+    async fn token(
+        idp: web::Data<MockIdp>,
+        form: web::Form<HashMap<String, String>>,
+    ) -> HttpResponse {
+        idp.token_requests.lock().unwrap().push(form.into_inner());
+        HttpResponse::Ok().json(json!({
+            "access_token": "provider-access-token",
+            "token_type": "Bearer",
+            "id_token": idp.id_token.lock().unwrap().take(),
+        }))
+    }
+
+    fn new_state() -> SsoState {
+        SsoState::new("mock", None, "browser-secret")
+    }
+
+    #[actix_web::test]
+    async fn signs_in_with_a_verified_id_token() {
+        let idp = MockIdp::start().await;
+        let provider = test_provider(&idp.issuer);
+        let state = new_state();
+
+        let url = provider
+            .authorization_url(REDIRECT_URI, &state, "state-token")
+            .await
+            .unwrap();
+        let url = Url::parse(&url).unwrap();
+        let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert!(
+            url.as_str()
+                .starts_with(&format!("{}/authorize", idp.issuer))
+        );
+        assert_eq!(query["state"], "state-token");
+        assert_eq!(query["nonce"], state.nonce);
+        assert_eq!(
+            query["code_challenge"],
+            sha256_base64url(&state.code_verifier)
+        );
+
+        idp.issue("key-1", idp.claims(&state));
+        let identity = provider
+            .exchange_code("auth-code", REDIRECT_URI, &state)
+            .await
+            .unwrap();
+        assert_eq!(identity.subject, "248289761001");
+        assert_eq!(identity.email.as_deref(), Some("jane@example.com"));
+        assert!(identity.email_verified);
+        assert_eq!(identity.preferred_username.as_deref(), Some("jane"));
+
+        let requests = idp.token_requests.lock().unwrap();
+        assert_eq!(requests[0]["code"], "auth-code");
+        assert_eq!(requests[0]["code_verifier"], state.code_verifier);
+        assert_eq!(requests[0]["client_secret"], CLIENT_SECRET);
+        assert_eq!(requests[0]["redirect_uri"], REDIRECT_URI);
+    }
+
+    #[actix_web::test]
+    async fn rejects_an_id_token_with_another_nonce() {
+        let idp = MockIdp::start().await;
+        let provider = test_provider(&idp.issuer);
+        let state = new_state();
+
+        // An ID token minted for another sign-in attempt, e.g. replayed by an attacker.
+        idp.issue("key-1", idp.claims(&new_state()));
+        let error = provider
+            .exchange_code("auth-code", REDIRECT_URI, &state)
+            .await
+            .unwrap_err();
+        assert_eq!(error, "id_token nonce does not match");
+    }
+
+    #[actix_web::test]
+    async fn rejects_id_tokens_for_another_client_or_issuer() {
+        let idp = MockIdp::start().await;
+        let provider = test_provider(&idp.issuer);
+        let state = new_state();
+
+        let mut claims = idp.claims(&state);
+        claims["aud"] = json!("another-client");
+        idp.issue("key-1", claims);
+        let error = provider
+            .exchange_code("auth-code", REDIRECT_URI, &state)
+            .await
+            .unwrap_err();
+        assert!(error.starts_with("id_token rejected"), "{}", error);
+
+        let mut claims = idp.claims(&state);
+        claims["iss"] = json!("https://evil.example.com");
+        idp.issue("key-1", claims);
+        let error = provider
+            .exchange_code("auth-code", REDIRECT_URI, &state)
+            .await
+            .unwrap_err();
+        assert!(error.starts_with("id_token rejected"), "{}", error);
+    }
+
+    #[actix_web::test]
+    async fn rejects_expired_and_symmetrically_signed_id_tokens() {
+        let idp = MockIdp::start().await;
+        let provider = test_provider(&idp.issuer);
+        let state = new_state();
+
+        let mut claims = idp.claims(&state);
+        claims["exp"] = json!(chrono::Utc::now().timestamp() - 3600);
+        idp.issue("key-1", claims);
+        let error = provider
+            .exchange_code("auth-code", REDIRECT_URI, &state)
+            .await
+            .unwrap_err();
+        assert!(error.starts_with("id_token rejected"), "{}", error);
+
+        // Signed with the client secret, which the client itself could do.
+        let forged = encode(
+            &Header::new(Algorithm::HS256),
+            &idp.claims(&state),
+            &EncodingKey::from_secret(CLIENT_SECRET.as_bytes()),
+        )
+        .unwrap();
+        idp.issue_raw(forged);
+        let error = provider
+            .exchange_code("auth-code", REDIRECT_URI, &state)
+            .await
+            .unwrap_err();
+        assert_eq!(error, "id_token algorithm HS256 is not allowed");
+    }
+
+    #[actix_web::test]
+    async fn refetches_the_jwks_at_most_once_per_interval() {
+        let idp = MockIdp::start().await;
+        let provider = test_provider(&idp.issuer);
+        let state = new_state();
+
+        idp.issue("key-1", idp.claims(&state));
+        provider
+            .exchange_code("auth-code", REDIRECT_URI, &state)
+            .await
+            .unwrap();
+        assert_eq!(idp.jwks_fetches.load(Ordering::SeqCst), 1);
+
+        // The provider rotates its key; tokens with the new kid fail until the interval passes.
+        *idp.kid.lock().unwrap() = "key-2".to_string();
+        for _ in 0..3 {
+            idp.issue("key-2", idp.claims(&state));
+            let error = provider
+                .exchange_code("auth-code", REDIRECT_URI, &state)
+                .await
+                .unwrap_err();
+            assert_eq!(error, "no JWK matches the id_token kid");
+        }
+        assert_eq!(idp.jwks_fetches.load(Ordering::SeqCst), 1);
+
+        if let Some(cached) = provider.jwks.write().await.as_mut() {
+            cached.fetched_at = Instant::now().checked_sub(JWKS_REFRESH_INTERVAL).unwrap();
+        }
+        idp.issue("key-2", idp.claims(&state));
+        provider
+            .exchange_code("auth-code", REDIRECT_URI, &state)
+            .await
+            .unwrap();
+        assert_eq!(idp.jwks_fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn state_is_bound_to_the_browser_that_started_the_attempt() {
+        let state = new_state();
+        assert!(state.is_bound_to(Some("browser-secret")));
+        assert!(!state.is_bound_to(Some("another-browser")));
+        assert!(!state.is_bound_to(None));
+    }
+
+    #[test]
+    fn binding_cookie_is_http_only_and_scoped_to_sso_routes() {
+        let config = SsoConfig {
+            providers: HashMap::new(),
+            redirect_base_url: "https://api.example.com".to_string(),
+        };
+        let cookie = config.binding_cookie("browser-secret");
+        assert_eq!(cookie.name(), BINDING_COOKIE);
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_eq!(cookie.path(), Some("/api/v1/auth/sso/"));
+        assert_eq!(config.callback_url("mock"), REDIRECT_URI);
+    }
+
+    #[test]
+    fn domain_restriction_only_trusts_verified_emails() {
+        let mut provider = test_provider("https://idp.example.com");
+        provider.allowed_domains = vec!["example.com".to_string()];
+        let identity = |email: &str, email_verified| ExternalIdentity {
+            subject: "1".to_string(),
+            email: Some(email.to_string()),
+            email_verified,
+            preferred_username: None,
+            phone: None,
+        };
+
+        assert!(provider.may_provision(&identity("jane@Example.com", true)));
+        assert!(!provider.may_provision(&identity("jane@example.com", false)));
+        assert!(!provider.may_provision(&identity("jane@example.org", true)));
+
+        provider.provisioning = Provisioning::Disabled;
+        assert!(!provider.may_provision(&identity("jane@example.com", true)));
+    }
+}
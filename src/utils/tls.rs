@@ -0,0 +1,548 @@
+// src/utils/tls.rs
+use crate::utils::shutdown::ShutdownSignal;
+use actix_web::dev::Extensions;
+use rustls::crypto::{CryptoProvider, ring};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use std::any::Any;
+use std::env;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpStream;
+use tracing::{error, info};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+/// Time a client gets to complete the TLS handshake, on either listener. Matches the
+/// HttpServer default.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+
+//===============================
+// TLS Configuration
+//===============================
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// Client certificates are requested and verified when presented, but not required.
+    Optional,
+    /// The handshake fails without a certificate signed by the client CA.
+    Required,
+}
+
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// CA bundle used to verify client certificates; `None` disables client authentication,
+    /// which is only allowed with `ClientAuth::Optional`.
+    pub client_ca_path: Option<PathBuf>,
+    pub client_auth: ClientAuth,
+    /// Seconds between certificate file change checks; `0` disables polling (SIGHUP still works).
+    pub reload_interval: u64,
+}
+
+impl TlsConfig {
+    /// TLS is enabled when `TLS_CERT_PATH` is set.
+    pub fn from_env() -> Option<Self> {
+        let cert_path = env::var("TLS_CERT_PATH").ok()?;
+        let key_path =
+            env::var("TLS_KEY_PATH").expect("TLS_KEY_PATH must be set with TLS_CERT_PATH");
+        let client_auth = match env::var("TLS_CLIENT_AUTH").as_deref() {
+            Ok("required") => ClientAuth::Required,
+            Ok("optional") | Err(_) => ClientAuth::Optional,
+            Ok(other) => panic!(
+                "TLS_CLIENT_AUTH must be optional or required, got {}",
+                other
+            ),
+        };
+
+        let client_ca_path = env::var("TLS_CLIENT_CA_PATH").ok().map(PathBuf::from);
+        if client_auth == ClientAuth::Required && client_ca_path.is_none() {
+            panic!("TLS_CLIENT_CA_PATH must be set with TLS_CLIENT_AUTH=required");
+        }
+
+        Some(Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path,
+            client_auth,
+            reload_interval: env::var("TLS_RELOAD_INTERVAL")
+                .map(|v| {
+                    v.parse()
+                        .expect("TLS_RELOAD_INTERVAL must be a number of seconds")
+                })
+                .unwrap_or(30),
+        })
+    }
+
+    /// Build the rustls server config. Certificates are served through `CertReloader`,
+    /// so replacing them never requires rebinding the listener.
+    pub fn server_config(&self, reloader: Arc<CertReloader>) -> io::Result<ServerConfig> {
+        let provider = reloader.provider.clone();
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+
+        let builder = match &self.client_ca_path {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in CertificateDer::pem_file_iter(path).map_err(pem_error)? {
+                    roots.add(cert.map_err(pem_error)?).map_err(tls_error)?;
+                }
+                let mut verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+                if self.client_auth == ClientAuth::Optional {
+                    verifier = verifier.allow_unauthenticated();
+                }
+                let verifier = verifier
+                    .build()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None if self.client_auth == ClientAuth::Required => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "client certificates are required but no client CA is configured",
+                ));
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        // ALPN (h2, http/1.1) is filled in by the HTTP service the config is bound to.
+        Ok(builder.with_cert_resolver(reloader))
+    }
+}
+
+//===============================
+// Hot Certificate Reload
+//===============================
+/// Serves the current certificate to new handshakes. Reloading swaps the key for future
+/// connections only; established connections keep the certificate they negotiated with.
+pub struct CertReloader {
+    config: TlsConfig,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<CertifiedKey>>,
+    loaded_at: RwLock<Option<SystemTime>>,
+}
+
+impl CertReloader {
+    pub fn new(config: TlsConfig) -> io::Result<Self> {
+        let provider = Arc::new(ring::default_provider());
+        let key = load_certified_key(&config, &provider)?;
+        let modified = last_modified(&config);
+        Ok(Self {
+            config,
+            provider,
+            current: RwLock::new(Arc::new(key)),
+            loaded_at: RwLock::new(modified),
+        })
+    }
+
+    /// Reload the certificate and key from disk. On failure the previous pair stays in use.
+    pub fn reload(&self) -> io::Result<()> {
+        let key = load_certified_key(&self.config, &self.provider)?;
+        *self.current.write().expect("certificate lock poisoned") = Arc::new(key);
+        *self.loaded_at.write().expect("certificate lock poisoned") = last_modified(&self.config);
+        Ok(())
+    }
+
+    fn files_changed(&self) -> bool {
+        let loaded_at = *self.loaded_at.read().expect("certificate lock poisoned");
+        last_modified(&self.config) != loaded_at
+    }
+}
+
+impl ResolvesServerCert for CertReloader {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().ok()?.clone())
+    }
+}
+
+impl fmt::Debug for CertReloader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertReloader")
+            .field("cert_path", &self.config.cert_path)
+            .finish()
+    }
+}
+
+/// Background worker reloading certificates on SIGHUP or when the files change on disk.
+pub async fn watch_certificates(reloader: Arc<CertReloader>, mut shutdown: ShutdownSignal) {
+    let mut hangup = hangup_listener();
+    let poll = reloader.config.reload_interval;
+    let mut ticker = tokio::time::interval(Duration::from_secs(poll.max(1)));
+
+    loop {
+        let reason = tokio::select! {
+            _ = shutdown.triggered() => break,
+            _ = next_hangup(&mut hangup) => "SIGHUP",
+            _ = ticker.tick(), if poll > 0 => {
+                if !reloader.files_changed() {
+                    continue;
+                }
+                "file change"
+            }
+        };
+
+        match reloader.reload() {
+            Ok(()) => info!("TLS certificate reloaded ({})", reason),
+            Err(e) => error!("TLS certificate reload failed, keeping previous: {}", e),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn hangup_listener() -> tokio::signal::unix::Signal {
+    use tokio::signal::unix::{SignalKind, signal};
+    signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler")
+}
+
+#[cfg(unix)]
+async fn next_hangup(hangup: &mut tokio::signal::unix::Signal) {
+    hangup.recv().await;
+}
+
+#[cfg(not(unix))]
+fn hangup_listener() {}
+
+#[cfg(not(unix))]
+async fn next_hangup(_: &mut ()) {
+    std::future::pending::<()>().await;
+}
+
+//===============================
+// Client Certificates
+//===============================
+/// Leaf certificate presented by the client. Only present when the handshake verified it
+/// against `TLS_CLIENT_CA_PATH`; read it with `req.conn_data::<PeerCertificate>()`.
+#[derive(Clone, Debug)]
+pub struct PeerCertificate(pub CertificateDer<'static>);
+
+impl PeerCertificate {
+    /// Identities a service account can be registered under: DNS, URI and email SANs,
+    /// followed by the subject common names.
+    pub fn identities(&self) -> Vec<String> {
+        let Ok((_, cert)) = X509Certificate::from_der(self.0.as_ref()) else {
+            return Vec::new();
+        };
+
+        let mut identities = Vec::new();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(value)
+                    | GeneralName::URI(value)
+                    | GeneralName::RFC822Name(value) => identities.push(value.to_string()),
+                    _ => {}
+                }
+            }
+        }
+        identities.extend(
+            cert.subject()
+                .iter_common_name()
+                .filter_map(|cn| cn.as_str().ok())
+                .map(str::to_string),
+        );
+        identities
+    }
+}
+
+/// `on_connect` hook storing the verified client certificate in the connection data.
+pub fn capture_peer_certificate(conn: &dyn Any, ext: &mut Extensions) {
+    if let Some(tls) = conn.downcast_ref::<actix_tls::accept::rustls_0_23::TlsStream<TcpStream>>() {
+        store_peer_certificate(tls.get_ref().1, ext);
+    }
+}
+
+pub fn store_peer_certificate(session: &rustls::ServerConnection, ext: &mut Extensions) {
+    if let Some(leaf) = session.peer_certificates().and_then(|chain| chain.first()) {
+        ext.insert(PeerCertificate(leaf.clone().into_owned()));
+    }
+}
+
+//===============================
+// Loading Helpers
+//===============================
+fn load_certified_key(config: &TlsConfig, provider: &CryptoProvider) -> io::Result<CertifiedKey> {
+    let chain = CertificateDer::pem_file_iter(&config.cert_path)
+        .map_err(pem_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(pem_error)?;
+    if chain.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no certificates in {}", config.cert_path.display()),
+        ));
+    }
+    let key = PrivateKeyDer::from_pem_file(&config.key_path).map_err(pem_error)?;
+    CertifiedKey::from_der(chain, key, provider).map_err(tls_error)
+}
+
+fn last_modified(config: &TlsConfig) -> Option<SystemTime> {
+    let cert = std::fs::metadata(&config.cert_path)
+        .and_then(|m| m.modified())
+        .ok();
+    let key = std::fs::metadata(&config.key_path)
+        .and_then(|m| m.modified())
+        .ok();
+    cert.max(key)
+}
+
+fn pem_error(e: rustls::pki_types::pem::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn tls_error(e: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertifiedIssuer, CertifiedKey as GeneratedKey,
+        DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+        generate_simple_self_signed,
+    };
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, ServerConnection};
+    use std::fs::{self, File};
+
+    /// Scratch directory for certificate files, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("tls-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn server_key() -> GeneratedKey<KeyPair> {
+        generate_simple_self_signed(vec!["localhost".to_string()]).unwrap()
+    }
+
+    fn client_ca() -> CertifiedIssuer<'static, KeyPair> {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap()
+    }
+
+    fn client_key(ca: &CertifiedIssuer<'static, KeyPair>) -> GeneratedKey<KeyPair> {
+        let mut params = CertificateParams::new(vec!["svc.example.com".to_string()]).unwrap();
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "billing");
+        let signing_key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&signing_key, ca).unwrap();
+        GeneratedKey { cert, signing_key }
+    }
+
+    /// Write `key` as the served certificate and return a config pointing at it.
+    fn tls_config(
+        dir: &TempDir,
+        key: &GeneratedKey<KeyPair>,
+        ca: Option<&CertifiedIssuer<'static, KeyPair>>,
+        client_auth: ClientAuth,
+    ) -> TlsConfig {
+        let config = TlsConfig {
+            cert_path: dir.0.join("cert.pem"),
+            key_path: dir.0.join("key.pem"),
+            client_ca_path: ca.map(|_| dir.0.join("client-ca.pem")),
+            client_auth,
+            reload_interval: 0,
+        };
+        write_key(&config, key);
+        if let (Some(ca), Some(path)) = (ca, &config.client_ca_path) {
+            fs::write(path, ca.pem()).unwrap();
+        }
+        config
+    }
+
+    fn write_key(config: &TlsConfig, key: &GeneratedKey<KeyPair>) {
+        fs::write(&config.cert_path, key.cert.pem()).unwrap();
+        fs::write(&config.key_path, key.signing_key.serialize_pem()).unwrap();
+    }
+
+    /// A client trusting only `server`, presenting `identity` when given.
+    fn client_config(
+        server: &GeneratedKey<KeyPair>,
+        identity: Option<&GeneratedKey<KeyPair>>,
+    ) -> ClientConfig {
+        let mut roots = RootCertStore::empty();
+        roots.add(server.cert.der().clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        match identity {
+            Some(key) => builder
+                .with_client_auth_cert(
+                    vec![key.cert.der().clone()],
+                    PrivateKeyDer::from_pem_slice(key.signing_key.serialize_pem().as_bytes())
+                        .unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        }
+    }
+
+    /// Run a handshake in memory and return the server side once both ends are done.
+    fn handshake(
+        server: &ServerConfig,
+        client: ClientConfig,
+    ) -> Result<ServerConnection, rustls::Error> {
+        let mut server = ServerConnection::new(Arc::new(server.clone()))?;
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut client = ClientConnection::new(Arc::new(client), name)?;
+        for _ in 0..10 {
+            let mut flight = Vec::new();
+            client.write_tls(&mut flight).unwrap();
+            let mut flight = flight.as_slice();
+            while !flight.is_empty() {
+                server.read_tls(&mut flight).unwrap();
+                server.process_new_packets()?;
+            }
+
+            let mut flight = Vec::new();
+            server.write_tls(&mut flight).unwrap();
+            let mut flight = flight.as_slice();
+            while !flight.is_empty() {
+                client.read_tls(&mut flight).unwrap();
+                client.process_new_packets()?;
+            }
+
+            if !client.is_handshaking() && !server.is_handshaking() {
+                return Ok(server);
+            }
+        }
+        panic!("handshake did not finish");
+    }
+
+    fn served_certificate(reloader: &CertReloader) -> CertificateDer<'static> {
+        reloader.current.read().unwrap().cert[0].clone()
+    }
+
+    #[test]
+    fn serves_the_configured_certificate() {
+        let dir = TempDir::new("serve");
+        let key = server_key();
+        let config = tls_config(&dir, &key, None, ClientAuth::Optional);
+        let reloader = Arc::new(CertReloader::new(config.clone()).unwrap());
+        let server = config.server_config(reloader).unwrap();
+
+        let session = handshake(&server, client_config(&key, None)).unwrap();
+        assert!(session.peer_certificates().is_none());
+
+        let stranger = server_key();
+        assert!(handshake(&server, client_config(&stranger, None)).is_err());
+    }
+
+    #[test]
+    fn reloads_certificates_replaced_on_disk() {
+        let dir = TempDir::new("reload");
+        let old = server_key();
+        let config = tls_config(&dir, &old, None, ClientAuth::Optional);
+        let reloader = Arc::new(CertReloader::new(config.clone()).unwrap());
+        let server = config.server_config(reloader.clone()).unwrap();
+        assert!(!reloader.files_changed());
+
+        // Rewrite the pair with a later timestamp, as a renewal would.
+        let new = server_key();
+        write_key(&config, &new);
+        let later = SystemTime::now() + Duration::from_secs(5);
+        for path in [&config.cert_path, &config.key_path] {
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(later)
+                .unwrap();
+        }
+        assert!(reloader.files_changed());
+
+        reloader.reload().unwrap();
+        assert!(!reloader.files_changed());
+        assert_eq!(&served_certificate(&reloader), new.cert.der());
+        // The config built before the reload serves the new certificate.
+        assert!(handshake(&server, client_config(&new, None)).is_ok());
+        assert!(handshake(&server, client_config(&old, None)).is_err());
+    }
+
+    #[test]
+    fn failed_reload_keeps_the_previous_certificate() {
+        let dir = TempDir::new("bad-reload");
+        let key = server_key();
+        let config = tls_config(&dir, &key, None, ClientAuth::Optional);
+        let reloader = CertReloader::new(config.clone()).unwrap();
+
+        fs::write(&config.key_path, "not a key").unwrap();
+        assert!(reloader.reload().is_err());
+        assert_eq!(&served_certificate(&reloader), key.cert.der());
+    }
+
+    #[test]
+    fn required_client_auth_needs_a_client_ca() {
+        let dir = TempDir::new("required-no-ca");
+        let key = server_key();
+        let config = tls_config(&dir, &key, None, ClientAuth::Required);
+        let reloader = Arc::new(CertReloader::new(config.clone()).unwrap());
+        let err = config.server_config(reloader).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn required_client_auth_accepts_only_certificates_from_the_ca() {
+        let dir = TempDir::new("required");
+        let key = server_key();
+        let ca = client_ca();
+        let config = tls_config(&dir, &key, Some(&ca), ClientAuth::Required);
+        let reloader = Arc::new(CertReloader::new(config.clone()).unwrap());
+        let server = config.server_config(reloader).unwrap();
+
+        assert!(handshake(&server, client_config(&key, None)).is_err());
+        let foreign = client_key(&client_ca());
+        assert!(handshake(&server, client_config(&key, Some(&foreign))).is_err());
+
+        let client = client_key(&ca);
+        let session = handshake(&server, client_config(&key, Some(&client))).unwrap();
+        let mut ext = Extensions::new();
+        store_peer_certificate(&session, &mut ext);
+        let peer = ext.get::<PeerCertificate>().unwrap();
+        assert_eq!(peer.identities(), vec!["svc.example.com", "billing"]);
+    }
+
+    #[test]
+    fn optional_client_auth_accepts_anonymous_clients() {
+        let dir = TempDir::new("optional");
+        let key = server_key();
+        let ca = client_ca();
+        let config = tls_config(&dir, &key, Some(&ca), ClientAuth::Optional);
+        let reloader = Arc::new(CertReloader::new(config.clone()).unwrap());
+        let server = config.server_config(reloader).unwrap();
+
+        let session = handshake(&server, client_config(&key, None)).unwrap();
+        let mut ext = Extensions::new();
+        store_peer_certificate(&session, &mut ext);
+        assert!(ext.get::<PeerCertificate>().is_none());
+
+        // A certificate that is presented must still verify.
+        let foreign = client_key(&client_ca());
+        assert!(handshake(&server, client_config(&key, Some(&foreign))).is_err());
+        let client = client_key(&ca);
+        let session = handshake(&server, client_config(&key, Some(&client))).unwrap();
+        assert!(session.peer_certificates().is_some());
+    }
+}